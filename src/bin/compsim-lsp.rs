@@ -0,0 +1,31 @@
+//the language server on its own, for editors that want a binary rather than "compsim-rs lsp";
+//it builds the modules the server needs straight from the main crate
+#![allow(dead_code)]
+extern crate snowflake;
+#[macro_use]extern crate enum_primitive;
+extern crate num;
+
+#[path = "../utils.rs"]        mod utils;
+#[path = "../parser.rs"]       mod parser;
+#[path = "../expr.rs"]         mod expr;
+#[path = "../preprocessor.rs"] mod preprocessor;
+#[path = "../diagnostic.rs"]   mod diagnostic;
+#[path = "../object.rs"]       mod object;
+#[path = "../linker.rs"]       mod linker;
+#[path = "../listing.rs"]      mod listing;
+#[path = "../debuginfo.rs"]    mod debuginfo;
+#[path = "../optimizer.rs"]    mod optimizer;
+#[path = "../json.rs"]         mod json;
+#[path = "../lsp.rs"]          mod lsp;
+
+use std::io;
+use std::process;
+
+fn main() {
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    if let Err(err) = lsp::run(stdin.lock(), &mut stdout.lock()) {
+        println!("error: {}", err);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,490 @@
+use std::error::Error;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Replacement {
+    Lru,
+    Fifo,
+    Random,
+    Plru,
+}
+
+//write back caches allocate on a write miss, write through caches pass the miss on
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WritePolicy {
+    WriteBack,
+    WriteThrough,
+}
+
+//the level below a cache, either another cache or the memory bus
+pub trait Backing {
+    //blocks that run past the end of memory come back shorter
+    fn fetch(&mut self, addr:u64, n:usize) -> Vec<u64>;
+    fn store(&mut self, values:Vec<(u64, u64)>);
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct CacheStats {
+    pub hits:u64,
+    pub misses:u64,
+    pub evictions:u64,
+    pub writebacks:u64,
+}
+
+impl fmt::Display for CacheStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let accesses = self.hits + self.misses;
+        let rate = if accesses == 0 { 0.0 } else { self.hits as f64 * 100.0 / accesses as f64 };
+        write!(f, "{} hits, {} misses ({:.1}% hit rate), {} evictions, {} writebacks",
+               self.hits, self.misses, rate, self.evictions, self.writebacks)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CacheError {
+    Geometry(String),
+}
+
+impl fmt::Display for CacheError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CacheError::Geometry(ref s) => write!(f, "Geometry: {}", s),
+        }
+    }
+}
+
+impl Error for CacheError {
+    fn description(&self) -> &str {
+        match *self {
+            CacheError::Geometry(_) => "invalid cache geometry",
+        }
+    }
+
+    fn cause(&self) -> Option<&Error> {
+        None
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Line {
+    valid:bool,
+    dirty:bool,
+    shared:bool, //another cache may hold the line as well
+    tag:u64,   //the address of the line divided by its size
+    words:Vec<u64>,
+    used:u64,
+    filled:u64,
+}
+
+//a set associative cache of words. Sizes are counted in words, lines that cannot be filled
+//completely because they run past the end of memory are not cached.
+#[derive(Debug, Clone)]
+pub struct Cache {
+    name:String,
+    line:usize,
+    ways:usize,
+    sets:Vec<Vec<Line>>,
+    plru:Vec<Vec<bool>>, //a tree per set, false points the victim left
+    replacement:Replacement,
+    write:WritePolicy,
+    stats:CacheStats,
+    clock:u64,
+    seed:u64,
+}
+
+impl Cache {
+
+    pub fn new(name:&str, size:usize, line:usize, ways:usize) -> Result<Cache, CacheError> {
+        if size == 0 || line == 0 || ways == 0 {
+            return Err(CacheError::Geometry(format!("{}: size, line size and associativity have to be positive", name)));
+        }
+        if size % (line * ways) != 0 {
+            return Err(CacheError::Geometry(format!("{}: {} words are no whole number of {} way sets of {} word lines", name, size, ways, line)));
+        }
+        let empty = Line { valid:false, dirty:false, shared:false, tag:0, words:vec![0; line], used:0, filled:0 };
+        let sets = size / (line * ways);
+        Ok(Cache {  name:name.to_string(),
+                    line:line,
+                    ways:ways,
+                    sets:vec![vec![empty; ways]; sets],
+                    plru:vec![vec![false; ways.next_power_of_two() - 1]; sets],
+                    replacement:Replacement::Lru,
+                    write:WritePolicy::WriteBack,
+                    stats:CacheStats::default(),
+                    clock:0,
+                    seed:0x2545_f491_4f6c_dd1d,
+        })
+    }
+
+    pub fn set_replacement(&mut self, _replacement:Replacement) -> &mut Cache {
+        self.replacement = _replacement;
+        self
+    }
+
+    pub fn set_write_policy(&mut self, _write:WritePolicy) -> &mut Cache {
+        self.write = _write;
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.stats
+    }
+
+    pub fn line_size(&self) -> usize {
+        self.line
+    }
+
+    pub fn holds(&self, addr:u64) -> bool {
+        self.find(addr).is_some()
+    }
+
+    pub fn is_dirty(&self, addr:u64) -> bool {
+        self.find(addr).map(|(set, way)| self.sets[set][way].dirty).unwrap_or(false)
+    }
+
+    pub fn is_shared(&self, addr:u64) -> bool {
+        self.find(addr).map(|(set, way)| self.sets[set][way].shared).unwrap_or(false)
+    }
+
+    //kept for coherence protocols, the cache itself does not look at it
+    pub fn set_shared(&mut self, addr:u64, shared:bool) {
+        if let Some((set, way)) = self.find(addr) {
+            self.sets[set][way].shared = shared;
+        }
+    }
+
+    pub fn read(&mut self, addr:u64, next:&mut Backing) -> u64 {
+        match self.lookup(addr, true, next) {
+            Some((set, way)) => self.sets[set][way].words[self.offset(addr)],
+            None => *next.fetch(addr, 1).first().expect("Received empty block from the next level"),
+        }
+    }
+
+    pub fn write(&mut self, addr:u64, word:u64, next:&mut Backing) {
+        let allocate = self.write == WritePolicy::WriteBack;
+        let offset = self.offset(addr);
+        match self.lookup(addr, allocate, next) {
+            Some((set, way)) => {
+                self.sets[set][way].words[offset] = word;
+                if self.write == WritePolicy::WriteBack {
+                    self.sets[set][way].dirty = true;
+                    return;
+                }
+            },
+            None => {},
+        }
+        next.store(vec![(addr, word)]);
+    }
+
+    //fills a line of the level above, every line touched counts as one access
+    pub fn read_block(&mut self, addr:u64, n:usize, next:&mut Backing) -> Vec<u64> {
+        let mut res = Vec::with_capacity(n);
+        while res.len() < n {
+            let a = addr + res.len() as u64;
+            match self.lookup(a, true, next) {
+                Some((set, way)) => {
+                    let offset = self.offset(a);
+                    let count = (self.line - offset).min(n - res.len());
+                    res.extend_from_slice(&self.sets[set][way].words[offset..offset + count]);
+                },
+                None => {
+                    res.extend(next.fetch(a, n - res.len()));
+                    break;
+                },
+            }
+        }
+        res
+    }
+
+    pub fn store_block(&mut self, values:Vec<(u64, u64)>, next:&mut Backing) {
+        let allocate = self.write == WritePolicy::WriteBack;
+        let mut through = Vec::new();
+        let mut current:Option<(u64, Option<(usize, usize)>)> = None;
+        for (addr, word) in values {
+            let tag = addr / self.line as u64;
+            let found = match current {
+                Some((t, found)) if t == tag => found,
+                _ => self.lookup(addr, allocate, next),
+            };
+            current = Some((tag, found));
+            match found {
+                Some((set, way)) => {
+                    let offset = self.offset(addr);
+                    self.sets[set][way].words[offset] = word;
+                    if allocate {
+                        self.sets[set][way].dirty = true;
+                        continue;
+                    }
+                },
+                None => {},
+            }
+            through.push((addr, word));
+        }
+        if !through.is_empty() {
+            next.store(through);
+        }
+    }
+
+    //drops the line without writing it back, for words that changed behind the cache's back
+    pub fn invalidate(&mut self, addr:u64) {
+        if let Some((set, way)) = self.find(addr) {
+            self.sets[set][way].valid = false;
+        }
+    }
+
+    //writes the line holding addr back if it is dirty
+    pub fn clean(&mut self, addr:u64, next:&mut Backing) {
+        if let Some((set, way)) = self.find(addr) {
+            if self.sets[set][way].dirty {
+                self.sets[set][way].dirty = false;
+                self.stats.writebacks += 1;
+                let line = self.line as u64;
+                let entry = &self.sets[set][way];
+                next.store(entry.words.iter().enumerate().map(|(i, &word)| (entry.tag * line + i as u64, word)).collect());
+            }
+        }
+    }
+
+    //writes all dirty lines back, they stay cached
+    pub fn flush(&mut self, next:&mut Backing) {
+        let line = self.line as u64;
+        for set in self.sets.iter_mut() {
+            for entry in set.iter_mut().filter(|entry| entry.valid && entry.dirty) {
+                entry.dirty = false;
+                self.stats.writebacks += 1;
+                next.store(entry.words.iter().enumerate().map(|(i, &word)| (entry.tag * line + i as u64, word)).collect());
+            }
+        }
+    }
+
+    fn offset(&self, addr:u64) -> usize {
+        (addr % self.line as u64) as usize
+    }
+
+    fn find(&self, addr:u64) -> Option<(usize, usize)> {
+        let tag = addr / self.line as u64;
+        let set = (tag % self.sets.len() as u64) as usize;
+        self.sets[set].iter().position(|entry| entry.valid && entry.tag == tag).map(|way| (set, way))
+    }
+
+    //counts a hit or a miss; None if the word is not cached afterwards
+    fn lookup(&mut self, addr:u64, allocate:bool, next:&mut Backing) -> Option<(usize, usize)> {
+        if let Some((set, way)) = self.find(addr) {
+            self.stats.hits += 1;
+            self.touch(set, way);
+            return Some((set, way));
+        }
+        self.stats.misses += 1;
+        if !allocate {
+            return None;
+        }
+        let tag = addr / self.line as u64;
+        let words = next.fetch(tag * self.line as u64, self.line);
+        if words.len() < self.line {
+            return None;
+        }
+        let set = (tag % self.sets.len() as u64) as usize;
+        let way = self.victim(set);
+        if self.sets[set][way].valid {
+            self.stats.evictions += 1;
+            if self.sets[set][way].dirty {
+                self.stats.writebacks += 1;
+                let line = self.line as u64;
+                let old = &self.sets[set][way];
+                next.store(old.words.iter().enumerate().map(|(i, &word)| (old.tag * line + i as u64, word)).collect());
+            }
+        }
+        self.clock += 1;
+        self.sets[set][way] = Line { valid:true, dirty:false, shared:false, tag:tag, words:words, used:self.clock, filled:self.clock };
+        self.touch(set, way);
+        Some((set, way))
+    }
+
+    fn touch(&mut self, set:usize, way:usize) {
+        self.clock += 1;
+        self.sets[set][way].used = self.clock;
+        let (mut node, mut lo, mut size) = (0, 0, self.ways.next_power_of_two());
+        while size > 1 {
+            let half = size / 2;
+            if way < lo + half {
+                self.plru[set][node] = true;
+                node = 2 * node + 1;
+            } else {
+                self.plru[set][node] = false;
+                lo += half;
+                node = 2 * node + 2;
+            }
+            size = half;
+        }
+    }
+
+    //free ways are used first
+    fn victim(&mut self, set:usize) -> usize {
+        if let Some(way) = self.sets[set].iter().position(|entry| !entry.valid) {
+            return way;
+        }
+        let lines = &self.sets[set];
+        match self.replacement {
+            Replacement::Lru    => (0..self.ways).min_by_key(|&way| lines[way].used).unwrap(),
+            Replacement::Fifo   => (0..self.ways).min_by_key(|&way| lines[way].filled).unwrap(),
+            Replacement::Random => {
+                //xorshift, so that runs can be repeated
+                self.seed ^= self.seed << 13;
+                self.seed ^= self.seed >> 7;
+                self.seed ^= self.seed << 17;
+                (self.seed % self.ways as u64) as usize
+            },
+            Replacement::Plru   => {
+                //a tree padded to a power of two; its right halves may lack ways, its left ones never do
+                let (mut node, mut lo, mut size) = (0, 0, self.ways.next_power_of_two());
+                while size > 1 {
+                    let half = size / 2;
+                    if self.plru[set][node] && lo + half < self.ways {
+                        lo += half;
+                        node = 2 * node + 2;
+                    } else {
+                        node = 2 * node + 1;
+                    }
+                    size = half;
+                }
+                lo
+            },
+        }
+    }
+}
+
+//a cache passes what it cannot answer on to the next level
+struct Level<'a> {
+    cache:&'a mut Cache,
+    next:&'a mut Backing,
+}
+
+impl<'a> Backing for Level<'a> {
+    fn fetch(&mut self, addr:u64, n:usize) -> Vec<u64> {
+        self.cache.read_block(addr, n, self.next)
+    }
+
+    fn store(&mut self, values:Vec<(u64, u64)>) {
+        self.cache.store_block(values, self.next)
+    }
+}
+
+//split first level caches for instructions and data over an optional unified second level.
+//Words in uncached ranges, like those of memory mapped devices, always go to memory.
+#[derive(Debug, Clone)]
+pub struct Hierarchy {
+    pub l1i:Cache,
+    pub l1d:Cache,
+    pub l2:Option<Cache>,
+    uncached:Vec<(u64, u64)>,
+}
+
+impl Hierarchy {
+
+    pub fn new(l1i:Cache, l1d:Cache) -> Hierarchy {
+        Hierarchy { l1i:l1i, l1d:l1d, l2:None, uncached:Vec::new() }
+    }
+
+    pub fn set_l2(&mut self, _l2:Cache) -> &mut Hierarchy {
+        self.l2 = Some(_l2);
+        self
+    }
+
+    pub fn add_uncached(&mut self, start:u64, len:u64) -> &mut Hierarchy {
+        self.uncached.push((start, len));
+        self
+    }
+
+    pub fn is_cached(&self, addr:u64) -> bool {
+        !self.uncached.iter().any(|&(start, len)| start <= addr && addr - start < len)
+    }
+
+    //code that was just written may still sit in a dirty data line, which is written back first
+    pub fn fetch_instr(&mut self, addr:u64, memory:&mut Backing) -> u64 {
+        if !self.is_cached(addr) {
+            return *memory.fetch(addr, 1).first().expect("Received empty block from memory");
+        }
+        match self.l2 {
+            Some(ref mut l2) => {
+                self.l1d.clean(addr, &mut Level { cache:l2, next:memory });
+                self.l1i.read(addr, &mut Level { cache:l2, next:memory })
+            },
+            None => {
+                self.l1d.clean(addr, memory);
+                self.l1i.read(addr, memory)
+            },
+        }
+    }
+
+    pub fn read(&mut self, addr:u64, memory:&mut Backing) -> u64 {
+        if !self.is_cached(addr) {
+            return *memory.fetch(addr, 1).first().expect("Received empty block from memory");
+        }
+        match self.l2 {
+            Some(ref mut l2) => self.l1d.read(addr, &mut Level { cache:l2, next:memory }),
+            None => self.l1d.read(addr, memory),
+        }
+    }
+
+    //instructions that are overwritten have to be fetched again
+    pub fn write(&mut self, addr:u64, word:u64, memory:&mut Backing) {
+        if !self.is_cached(addr) {
+            return memory.store(vec![(addr, word)]);
+        }
+        self.l1i.invalidate(addr);
+        match self.l2 {
+            Some(ref mut l2) => self.l1d.write(addr, word, &mut Level { cache:l2, next:memory }),
+            None => self.l1d.write(addr, word, memory),
+        }
+    }
+
+    //writes the data line holding addr back through all levels
+    pub fn clean(&mut self, addr:u64, memory:&mut Backing) {
+        match self.l2 {
+            Some(ref mut l2) => {
+                self.l1d.clean(addr, &mut Level { cache:l2, next:memory });
+                l2.clean(addr, memory);
+            },
+            None => self.l1d.clean(addr, memory),
+        }
+    }
+
+    //drops addr from every level without writing it back
+    pub fn invalidate(&mut self, addr:u64) {
+        self.l1i.invalidate(addr);
+        self.l1d.invalidate(addr);
+        if let Some(ref mut l2) = self.l2 {
+            l2.invalidate(addr);
+        }
+    }
+
+    pub fn holds(&self, addr:u64) -> bool {
+        self.l1d.holds(addr) || self.l2.as_ref().map(|l2| l2.holds(addr)).unwrap_or(false)
+    }
+
+    pub fn flush(&mut self, memory:&mut Backing) {
+        match self.l2 {
+            Some(ref mut l2) => {
+                self.l1d.flush(&mut Level { cache:l2, next:memory });
+                l2.flush(memory);
+            },
+            None => self.l1d.flush(memory),
+        }
+    }
+
+    pub fn levels(&self) -> Vec<&Cache> {
+        let mut res = vec![&self.l1i, &self.l1d];
+        if let Some(ref l2) = self.l2 {
+            res.push(l2);
+        }
+        res
+    }
+
+    pub fn report(&self) -> String {
+        self.levels().iter().map(|cache| format!("{}: {}\n", cache.name(), cache.stats())).collect()
+    }
+}
@@ -0,0 +1,295 @@
+use std::collections::{HashMap, HashSet, BTreeMap, BTreeSet};
+use disassembler::Disassembler;
+use optimizer::Word;
+use utils::{Instruction, Opcode, Reg};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Edge {
+    FallThrough,
+    Branch,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BasicBlock {
+    pub start:u64,
+    pub instructions:Vec<Instruction>,
+    pub successors:Vec<(usize, Edge)>,
+    pub predecessors:Vec<usize>,
+}
+
+impl BasicBlock {
+    pub fn end(&self) -> u64 {
+        self.start + self.instructions.len() as u64
+    }
+}
+
+//a natural loop: the header dominates every block of the body, the latches jump back to it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Loop {
+    pub header:usize,
+    pub latches:Vec<usize>,
+    pub body:BTreeSet<usize>,
+}
+
+//the blocks of all instructions of a program, also the ones that cannot be reached. CALL does not
+//end a block since the callee comes back, but the called address starts one of its own.
+#[derive(Debug, Clone)]
+pub struct Cfg {
+    pub blocks:Vec<BasicBlock>,
+    pub entry:Option<usize>,
+    idom:Vec<Option<usize>>,
+    loops:Vec<Loop>,
+    disassembler:Disassembler,
+}
+
+//the jump target of a branch; new branch opcodes only have to be added here
+pub fn branch_target(instr:Instruction) -> Option<u64> {
+    match instr.opcode() {
+        Opcode::Jz | Opcode::Jgz | Opcode::Jlz => Some(instr.addr()),
+        _ => None,
+    }
+}
+
+fn ends_block(instr:Instruction) -> bool {
+    match instr.opcode() {
+        Opcode::Ret => true,
+        Opcode::Add | Opcode::Mul | Opcode::Ld | Opcode::Pop => instr.reg1() == Reg::ISP,
+        _ => branch_target(instr).is_some(),
+    }
+}
+
+impl Cfg {
+
+    pub fn new(words:&BTreeMap<u64, Word>, labels:&HashMap<String, u64>, entry:u64) -> Cfg {
+        let code:BTreeMap<u64, Instruction> = words.iter().filter_map(|(&addr, &word)| match word {
+            Word::Instr(instr) if instr.is_valid() => Some((addr, instr)),
+            _ => None,
+        }).collect();
+
+        let mut leaders = BTreeSet::new();
+        let mut targets:HashSet<u64> = labels.values().cloned().collect();
+        leaders.insert(entry);
+        for (&addr, &instr) in code.iter() {
+            if !code.contains_key(&addr.wrapping_sub(1)) {
+                leaders.insert(addr);
+            }
+            if ends_block(instr) {
+                leaders.insert(addr + 1);
+            }
+            let target = match instr.opcode() {
+                Opcode::Call => Some(instr.addr()),
+                _ => branch_target(instr),
+            };
+            if let Some(target) = target {
+                leaders.insert(target);
+                targets.insert(target);
+            }
+        }
+
+        let mut blocks:Vec<BasicBlock> = Vec::new();
+        for (&addr, &instr) in code.iter() {
+            if leaders.contains(&addr) {
+                blocks.push(BasicBlock{ start:addr, instructions:Vec::new(), successors:Vec::new(), predecessors:Vec::new() });
+            }
+            blocks.last_mut().unwrap().instructions.push(instr);
+        }
+
+        let starts:HashMap<u64, usize> = blocks.iter().enumerate().map(|(i, block)| (block.start, i)).collect();
+        for i in 0..blocks.len() {
+            let last = *blocks[i].instructions.last().unwrap();
+            let last_addr = blocks[i].end() - 1;
+            let mut successors = Vec::new();
+            if let Some(&target) = branch_target(last).and_then(|target| starts.get(&target)) {
+                successors.push((target, Edge::Branch));
+            }
+            if falls_through(&code, &targets, last_addr, last) {
+                if let Some(&next) = starts.get(&(last_addr + 1)) {
+                    successors.push((next, Edge::FallThrough));
+                }
+            }
+            for &(succ, _) in successors.iter() {
+                blocks[succ].predecessors.push(i);
+            }
+            blocks[i].successors = successors;
+        }
+
+        let mut cfg = Cfg {
+            entry:starts.get(&entry).cloned(),
+            idom:vec![None; blocks.len()],
+            blocks:blocks,
+            loops:Vec::new(),
+            disassembler:Disassembler::with_symbols(labels),
+        };
+        cfg.find_dominators();
+        cfg.find_loops();
+        cfg
+    }
+
+    pub fn block_at(&self, addr:u64) -> Option<usize> {
+        self.blocks.iter().position(|block| block.start <= addr && addr < block.end())
+    }
+
+    //None for the entry and for blocks that cannot be reached from it
+    pub fn idom(&self, block:usize) -> Option<usize> {
+        self.idom[block]
+    }
+
+    pub fn dominates(&self, a:usize, b:usize) -> bool {
+        if Some(b) != self.entry && self.idom[b].is_none() {
+            return false;
+        }
+        let mut cur = Some(b);
+        while let Some(block) = cur {
+            if block == a {
+                return true;
+            }
+            cur = self.idom[block];
+        }
+        false
+    }
+
+    pub fn loops(&self) -> &[Loop] {
+        &self.loops
+    }
+
+    //blocks in reverse postorder, starting at the entry
+    fn reverse_postorder(&self) -> Vec<usize> {
+        let entry = match self.entry {
+            Some(entry) => entry,
+            None => return Vec::new(),
+        };
+        let mut order = Vec::new();
+        let mut visited = HashSet::new();
+        let mut stack = vec![(entry, 0)];
+        visited.insert(entry);
+        while let Some((block, i)) = stack.pop() {
+            match self.blocks[block].successors.get(i) {
+                Some(&(succ, _)) => {
+                    stack.push((block, i + 1));
+                    if visited.insert(succ) {
+                        stack.push((succ, 0));
+                    }
+                },
+                None => order.push(block),
+            }
+        }
+        order.reverse();
+        order
+    }
+
+    //Cooper, Harvey and Kennedy: "A Simple, Fast Dominance Algorithm"
+    fn find_dominators(&mut self) {
+        let order = self.reverse_postorder();
+        let entry = match self.entry {
+            Some(entry) => entry,
+            None => return,
+        };
+        let mut rank = vec![usize::max_value(); self.blocks.len()];
+        for (i, &block) in order.iter().enumerate() {
+            rank[block] = i;
+        }
+        let mut idom = vec![None; self.blocks.len()];
+        idom[entry] = Some(entry);
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &block in order.iter().skip(1) {
+                let mut new_idom = None;
+                for &pred in self.blocks[block].predecessors.iter().filter(|&&pred| idom[pred].is_some()) {
+                    new_idom = Some(match new_idom {
+                        None => pred,
+                        Some(mut other) => {
+                            let mut finger = pred;
+                            while finger != other {
+                                while rank[finger] > rank[other] { finger = idom[finger].unwrap(); }
+                                while rank[other] > rank[finger] { other = idom[other].unwrap(); }
+                            }
+                            finger
+                        },
+                    });
+                }
+                if new_idom.is_some() && idom[block] != new_idom {
+                    idom[block] = new_idom;
+                    changed = true;
+                }
+            }
+        }
+        idom[entry] = None;
+        self.idom = idom;
+    }
+
+    //every edge to a block that dominates its source closes a loop
+    fn find_loops(&mut self) {
+        let mut loops:BTreeMap<usize, Loop> = BTreeMap::new();
+        for latch in 0..self.blocks.len() {
+            for &(header, _) in self.blocks[latch].successors.iter() {
+                if !self.dominates(header, latch) {
+                    continue;
+                }
+                let l = loops.entry(header).or_insert(Loop{ header:header, latches:Vec::new(), body:BTreeSet::new() });
+                l.latches.push(latch);
+                l.body.insert(header);
+                let mut stack = vec![latch];
+                while let Some(block) = stack.pop() {
+                    if l.body.insert(block) {
+                        stack.extend(self.blocks[block].predecessors.iter().cloned());
+                    }
+                }
+            }
+        }
+        self.loops = loops.into_iter().map(|(_, l)| l).collect();
+    }
+
+    //one box per block with its disassembly, branches are labeled and edges that close a loop are red
+    pub fn to_dot(&self) -> String {
+        let mut dot = "digraph cfg {\n    node [shape=box, fontname=\"monospace\"];\n".to_string();
+        for (i, block) in self.blocks.iter().enumerate() {
+            let mut label = String::new();
+            for (offset, &instr) in block.instructions.iter().enumerate() {
+                let addr = block.start + offset as u64;
+                if let Some(name) = self.disassembler.symbol_at(addr) {
+                    label = label + name + ":\\l";
+                }
+                label = label + &format!("{:#014x}  {}\\l", addr, escape(&self.disassembler.render(instr)));
+            }
+            let style = if Some(i) == self.entry { ", style=bold" } else { "" };
+            dot = dot + &format!("    b{} [label=\"{}\"{}];\n", i, label, style);
+        }
+        for (i, block) in self.blocks.iter().enumerate() {
+            for &(succ, edge) in block.successors.iter() {
+                let mut attrs = Vec::new();
+                if edge == Edge::Branch {
+                    let mnemonic = self.disassembler.render(*block.instructions.last().unwrap());
+                    attrs.push(format!("label=\"{}\"", mnemonic.split_whitespace().next().unwrap_or("")));
+                }
+                if self.dominates(succ, i) {
+                    attrs.push("color=red".to_string());
+                }
+                if attrs.is_empty() {
+                    dot = dot + &format!("    b{} -> b{};\n", i, succ);
+                } else {
+                    dot = dot + &format!("    b{} -> b{} [{}];\n", i, succ, attrs.join(", "));
+                }
+            }
+        }
+        dot + "}\n"
+    }
+}
+
+//JLZ X right behind JGZ X (or the other way around) is an unconditional jump, as long as nothing
+//jumps to the second one directly
+fn falls_through(code:&BTreeMap<u64, Instruction>, targets:&HashSet<u64>, addr:u64, instr:Instruction) -> bool {
+    let partner = match instr.opcode() {
+        Opcode::Jgz => Opcode::Jlz,
+        Opcode::Jlz => Opcode::Jgz,
+        _ => return !ends_block(instr) || branch_target(instr).is_some(),
+    };
+    match code.get(&addr.wrapping_sub(1)) {
+        Some(prev) => !(prev.opcode() == partner && prev.addr() == instr.addr() && !targets.contains(&addr)),
+        None => true,
+    }
+}
+
+fn escape(s:&str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
@@ -0,0 +1,225 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use cache::{Backing, Hierarchy};
+
+//MESI, as seen by the first level data cache of a core
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum State {
+    Modified,
+    Exclusive,
+    Shared,
+    Invalid,
+}
+
+impl fmt::Display for State {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            State::Modified     => write!(f, "M"),
+            State::Exclusive    => write!(f, "E"),
+            State::Shared       => write!(f, "S"),
+            State::Invalid      => write!(f, "I"),
+        }
+    }
+}
+
+fn state(caches:&Hierarchy, addr:u64) -> State {
+    if !caches.l1d.holds(addr) {
+        State::Invalid
+    } else if caches.l1d.is_dirty(addr) {
+        State::Modified
+    } else if caches.l1d.is_shared(addr) {
+        State::Shared
+    } else {
+        State::Exclusive
+    }
+}
+
+//what went over the bus for a single line and what it did to the copies in the caches
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LineTraffic {
+    pub reads:u64,           //BusRd, a read or instruction fetch miss
+    pub read_exclusives:u64, //BusRdX, a write miss
+    pub upgrades:u64,        //BusUpgr, a write to a shared line
+    pub invalidations:u64,   //copies other caches lost
+    pub flushes:u64,         //dirty copies other caches had to write back
+    pub transitions:BTreeMap<(State, State), u64>,
+}
+
+impl fmt::Display for LineTraffic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} read(s), {} read-exclusive(s), {} upgrade(s), {} invalidation(s), {} flush(es)",
+               self.reads, self.read_exclusives, self.upgrades, self.invalidations, self.flushes)
+    }
+}
+
+//the caches of all cores on one snooping bus. Holding the lock is owning the bus, so every
+//transaction is finished before the next one starts. A copy in the instruction cache of another
+//core counts as shared, so that code written by one core is fetched again by the others.
+pub struct Bus {
+    caches:Vec<Hierarchy>,
+    lines:BTreeMap<u64, LineTraffic>,
+}
+
+impl Bus {
+
+    pub fn new(caches:Vec<Hierarchy>) -> Bus {
+        Bus { caches:caches, lines:BTreeMap::new() }
+    }
+
+    pub fn caches(&self, core:usize) -> &Hierarchy {
+        &self.caches[core]
+    }
+
+    //the transitions of all lines together
+    pub fn transitions(&self) -> BTreeMap<(State, State), u64> {
+        let mut res = BTreeMap::new();
+        for traffic in self.lines.values() {
+            for (&key, n) in traffic.transitions.iter() {
+                *res.entry(key).or_insert(0) += *n;
+            }
+        }
+        res
+    }
+
+    //keyed by the address of the line
+    pub fn lines(&self) -> &BTreeMap<u64, LineTraffic> {
+        &self.lines
+    }
+
+    pub fn state(&self, core:usize, addr:u64) -> State {
+        state(&self.caches[core], addr)
+    }
+
+    fn line(&mut self, core:usize, addr:u64) -> &mut LineTraffic {
+        let size = self.caches[core].l1d.line_size() as u64;
+        self.lines.entry(addr / size * size).or_insert(LineTraffic::default())
+    }
+
+    fn count(&mut self, core:usize, addr:u64, from:State, to:State) {
+        if from != to {
+            *self.line(core, addr).transitions.entry((from, to)).or_insert(0) += 1;
+        }
+    }
+
+    //every other cache writes a dirty copy back and keeps a shared one or, if invalidate is
+    //set, drops it. True if any of them held the line.
+    fn snoop(&mut self, core:usize, addr:u64, invalidate:bool, memory:&mut Backing) -> bool {
+        let mut held = false;
+        for other in (0..self.caches.len()).filter(|&other| other != core) {
+            if !self.caches[other].holds(addr) && !self.caches[other].l1i.holds(addr) {
+                continue;
+            }
+            held = true;
+            let before = state(&self.caches[other], addr);
+            if before == State::Modified || self.caches[other].l2.as_ref().map(|l2| l2.is_dirty(addr)).unwrap_or(false) {
+                self.line(core, addr).flushes += 1;
+            }
+            self.caches[other].clean(addr, memory);
+            if invalidate {
+                self.caches[other].invalidate(addr);
+                self.line(core, addr).invalidations += 1;
+            } else {
+                self.caches[other].l1d.set_shared(addr, true);
+            }
+            let after = state(&self.caches[other], addr);
+            self.count(core, addr, before, after);
+        }
+        held
+    }
+
+    //a miss in the instruction cache is a read as well, whoever holds the line keeps a shared copy
+    pub fn fetch_instr(&mut self, core:usize, addr:u64, memory:&mut Backing) -> u64 {
+        if self.caches[core].l1i.holds(addr) || !self.caches[core].is_cached(addr) {
+            return self.caches[core].fetch_instr(addr, memory);
+        }
+        self.line(core, addr).reads += 1;
+        self.snoop(core, addr, false, memory);
+        self.caches[core].fetch_instr(addr, memory)
+    }
+
+    pub fn read(&mut self, core:usize, addr:u64, memory:&mut Backing) -> u64 {
+        let before = state(&self.caches[core], addr);
+        if before != State::Invalid || !self.caches[core].is_cached(addr) {
+            return self.caches[core].read(addr, memory);
+        }
+        self.line(core, addr).reads += 1;
+        let shared = self.snoop(core, addr, false, memory);
+        let word = self.caches[core].read(addr, memory);
+        self.caches[core].l1d.set_shared(addr, shared);
+        let after = state(&self.caches[core], addr);
+        self.count(core, addr, before, after);
+        word
+    }
+
+    pub fn write(&mut self, core:usize, addr:u64, word:u64, memory:&mut Backing) {
+        let before = state(&self.caches[core], addr);
+        if self.caches[core].is_cached(addr) {
+            match before {
+                State::Modified | State::Exclusive => {},
+                State::Shared => {
+                    self.line(core, addr).upgrades += 1;
+                    self.snoop(core, addr, true, memory);
+                },
+                State::Invalid => {
+                    self.line(core, addr).read_exclusives += 1;
+                    self.snoop(core, addr, true, memory);
+                },
+            }
+        }
+        self.caches[core].write(addr, word, memory);
+        self.caches[core].l1d.set_shared(addr, false);
+        let after = state(&self.caches[core], addr);
+        self.count(core, addr, before, after);
+    }
+
+    pub fn flush(&mut self, core:usize, memory:&mut Backing) {
+        self.caches[core].flush(memory);
+    }
+
+    pub fn report(&self) -> String {
+        let mut res = String::from("transitions:\n");
+        for (&(from, to), n) in self.transitions().iter() {
+            res.push_str(&format!("    {} -> {}: {}\n", from, to, n));
+        }
+        res.push_str("lines:\n");
+        for (addr, traffic) in self.lines.iter() {
+            res.push_str(&format!("    {:#014x}: {}\n", addr, traffic));
+            for (&(from, to), n) in traffic.transitions.iter() {
+                res.push_str(&format!("        {} -> {}: {}\n", from, to, n));
+            }
+        }
+        res
+    }
+}
+
+//how a core reaches the bus it shares with the others
+#[derive(Clone)]
+pub struct Port {
+    pub bus:Arc<Mutex<Bus>>,
+    pub core:usize,
+}
+
+impl fmt::Debug for Port {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Port {{ core: {} }}", self.core)
+    }
+}
+
+impl Port {
+    pub fn fetch_instr(&self, addr:u64, memory:&mut Backing) -> u64 {
+        self.bus.lock().expect("Another core failed while it owned the bus").fetch_instr(self.core, addr, memory)
+    }
+
+    pub fn read(&self, addr:u64, memory:&mut Backing) -> u64 {
+        self.bus.lock().expect("Another core failed while it owned the bus").read(self.core, addr, memory)
+    }
+
+    pub fn write(&self, addr:u64, word:u64, memory:&mut Backing) {
+        self.bus.lock().expect("Another core failed while it owned the bus").write(self.core, addr, word, memory)
+    }
+
+    pub fn flush(&self, memory:&mut Backing) {
+        self.bus.lock().expect("Another core failed while it owned the bus").flush(self.core, memory)
+    }
+}
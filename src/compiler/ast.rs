@@ -0,0 +1,46 @@
+//every value is one 64 bit word, so pointers and ints only differ in how the program uses them;
+//the declared types are kept for readability of the tree but not checked
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Num(i64),
+    Var(String),
+    Unary(&'static str, Box<Expr>),    //- ! * &
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+    Assign(Box<Expr>, Box<Expr>),
+    Index(Box<Expr>, Box<Expr>),       //a[i] is *(a + i)
+    Call(String, Vec<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Stmt {
+    Block(Vec<(Stmt, usize)>),
+    Decl(String, Option<u64>, Option<Expr>),   //name, array length, initializer
+    If(Expr, Box<(Stmt, usize)>, Option<Box<(Stmt, usize)>>),
+    While(Expr, Box<(Stmt, usize)>),
+    Return(Option<Expr>),
+    Expr(Expr),
+    Empty,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+    pub name:String,
+    pub params:Vec<String>,
+    pub body:Vec<(Stmt, usize)>,
+    pub line:usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Global {
+    pub name:String,
+    pub len:Option<u64>,    //arrays have a length
+    pub init:Vec<i64>,
+    pub line:usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Program {
+    pub globals:Vec<Global>,
+    pub functions:Vec<Function>,
+}
@@ -0,0 +1,469 @@
+use std::collections::{HashMap, BTreeMap};
+use compiler::CompileError;
+use compiler::ast::*;
+use utils::Instruction;
+
+//where a name lives: globals are addressed directly, locals and parameters relative to EBP
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Place {
+    Global(bool),           //is an array
+    Local(i64, bool),       //offset from EBP, is an array
+}
+
+//calling convention: arguments are pushed from right to left, CALL pushes the return address,
+//the callee saves EBP and points it at the saved value, so
+//  [EBP + 2 + i]   parameter i
+//  [EBP + 1]       return address
+//  [EBP]           EBP of the caller
+//  [EBP - 1] ...   locals
+//the result is returned in EAX and the caller removes the arguments.
+//
+//the ISA has neither immediates nor register indirect addressing: constants come from a pool of
+//.words, and loads and stores through a computed address patch the address into a LD or SAV
+//(see RUNTIME). EAX holds the value of an expression, EBX the left operand, ECX is scratch and
+//EDX belongs to the runtime.
+pub struct CodeGen {
+    code:Vec<String>,
+    data:Vec<String>,
+    constants:BTreeMap<i64, String>,
+    addresses:BTreeMap<String, String>,
+    next_label:usize,
+    globals:HashMap<String, Place>,
+    functions:HashMap<String, usize>,
+    //state of the function that is being compiled
+    scopes:Vec<HashMap<String, Place>>,
+    locals:i64,
+    frame_size:i64,
+    ret_label:String,
+    line:usize,
+}
+
+const STACK_TOP:i64 = 0x10000;
+
+//__load: EAX = [EAX], __store: [EBX] = EAX, both clobber EDX
+const RUNTIME:&'static str = "\
+__load:
+    LD EDX, __load_template
+    ADD EDX, EAX
+    SAV __load_slot, EDX
+__load_slot:
+    NOP
+    RET
+__store:
+    LD EDX, __store_template
+    ADD EDX, EBX
+    SAV __store_slot, EDX
+__store_slot:
+    NOP
+    RET";
+
+impl CodeGen {
+
+    pub fn new() -> CodeGen {
+        CodeGen {
+            code:Vec::new(),
+            data:Vec::new(),
+            constants:BTreeMap::new(),
+            addresses:BTreeMap::new(),
+            next_label:0,
+            globals:HashMap::new(),
+            functions:HashMap::new(),
+            scopes:Vec::new(),
+            locals:0,
+            frame_size:0,
+            ret_label:String::new(),
+            line:0,
+        }
+    }
+
+    pub fn program(mut self, program:&Program) -> Result<String, CompileError> {
+        for global in program.globals.iter() {
+            if self.globals.insert(global.name.clone(), Place::Global(global.len.is_some())).is_some() {
+                return Err(CompileError::Redefinition(global.line, global.name.clone()));
+            }
+        }
+        for function in program.functions.iter() {
+            if self.globals.contains_key(&function.name) || self.functions.insert(function.name.clone(), function.params.len()).is_some() {
+                return Err(CompileError::Redefinition(function.line, function.name.clone()));
+            }
+        }
+        if self.functions.get("main") != Some(&0) {
+            return Err(CompileError::NoMain);
+        }
+
+        self.label("__start");
+        self.emit("LD ESP, __stack_top".to_string());
+        self.emit("LD EBP, __stack_top".to_string());
+        self.emit("CALL main".to_string());
+        //there is no halt instruction, the program ends in a loop with the result of main in EAX
+        self.label("__halt");
+        self.jump("__halt");
+        for function in program.functions.iter() {
+            try!(self.function(function));
+        }
+
+        let mut out = self.code.join("\n") + "\n" + RUNTIME + "\n";
+        out = out + &format!("__load_template: .word {:#x}\n", "LD EAX 0".parse::<Instruction>().unwrap().0);
+        out = out + &format!("__store_template: .word {:#x}\n", "SAV 0 EAX".parse::<Instruction>().unwrap().0);
+        out = out + &format!("__stack_top: .word {:#x}\n", STACK_TOP);
+        for global in program.globals.iter() {
+            out = out + &global_data(global) + "\n";
+        }
+        for line in self.data.iter() {
+            out = out + line + "\n";
+        }
+        for (value, name) in self.constants.iter() {
+            out = out + &format!("{}: .word {}\n", name, value);
+        }
+        for (target, name) in self.addresses.iter() {
+            out = out + &format!("{}: .word {}\n", name, target);
+        }
+        Ok(out)
+    }
+
+    fn emit(&mut self, line:String) {
+        self.code.push(format!("    {}", line));
+    }
+
+    fn label(&mut self, name:&str) {
+        self.code.push(format!("{}:", name));
+    }
+
+    fn new_label(&mut self, hint:&str) -> String {
+        self.next_label += 1;
+        format!("__{}{}", hint, self.next_label)
+    }
+
+    //the label of a pool word holding value
+    fn constant(&mut self, value:i64) -> String {
+        let next = self.constants.len();
+        self.constants.entry(value).or_insert(format!("__const{}", next)).clone()
+    }
+
+    fn load_const(&mut self, reg:&str, value:i64) {
+        let label = self.constant(value);
+        self.emit(format!("LD {}, {}", reg, label));
+    }
+
+    //there is no unconditional jump, but one of SIGN and !SIGN is always true
+    fn jump(&mut self, label:&str) {
+        self.emit(format!("JGZ {}", label));
+        self.emit(format!("JLZ {}", label));
+    }
+
+    //sets ZERO and SIGN according to EAX
+    fn test(&mut self) {
+        self.load_const("ECX", 0);
+        self.emit("ADD EAX, ECX".to_string());
+    }
+
+    fn function(&mut self, function:&Function) -> Result<(), CompileError> {
+        self.line = function.line;
+        self.ret_label = format!("__ret_{}", function.name);
+        let frame_label = format!("__frame_{}", function.name);
+        let mut params = HashMap::new();
+        for (i, param) in function.params.iter().enumerate() {
+            if params.insert(param.clone(), Place::Local(2 + i as i64, false)).is_some() {
+                return Err(CompileError::Redefinition(function.line, param.clone()));
+            }
+        }
+        self.scopes = vec![params];
+        self.locals = 0;
+        self.frame_size = 0;
+
+        self.code.push(String::new());
+        self.label(&function.name);
+        self.emit("PUSH EBP".to_string());
+        self.load_const("EBP", 0);
+        self.emit("ADD EBP, ESP".to_string());
+        self.emit(format!("LD ECX, {}", frame_label));
+        self.emit("ADD ESP, ECX".to_string());
+        try!(self.block(&function.body));
+        //falling off the end returns 0
+        self.load_const("EAX", 0);
+        let ret_label = self.ret_label.clone();
+        self.label(&ret_label);
+        self.load_const("ESP", 0);
+        self.emit("ADD ESP, EBP".to_string());
+        self.emit("POP EBP".to_string());
+        self.emit("RET".to_string());
+        self.data.push(format!("{}: .word {}", frame_label, -self.frame_size));
+        Ok(())
+    }
+
+    fn block(&mut self, stmts:&[(Stmt, usize)]) -> Result<(), CompileError> {
+        let locals = self.locals;
+        self.scopes.push(HashMap::new());
+        for &(ref stmt, line) in stmts.iter() {
+            try!(self.statement(stmt, line));
+        }
+        self.scopes.pop();
+        //the slots of a finished block are used again by the next one
+        self.locals = locals;
+        Ok(())
+    }
+
+    fn statement(&mut self, stmt:&Stmt, line:usize) -> Result<(), CompileError> {
+        if line != self.line {
+            self.line = line;
+            self.code.push(format!("    ; line {}", line));
+        }
+        match *stmt {
+            Stmt::Block(ref stmts) => try!(self.block(stmts)),
+            Stmt::Empty => {},
+            Stmt::Decl(ref name, len, ref init) => {
+                if let Some(init) = init.as_ref() {
+                    try!(self.expr(init));
+                }
+                let size = len.unwrap_or(1) as i64;
+                self.locals += size;
+                self.frame_size = self.frame_size.max(self.locals);
+                let offset = -self.locals;
+                if self.scopes.last_mut().unwrap().insert(name.clone(), Place::Local(offset, len.is_some())).is_some() {
+                    return Err(CompileError::Redefinition(line, name.clone()));
+                }
+                if init.is_some() {
+                    self.load_const("EBX", offset);
+                    self.emit("ADD EBX, EBP".to_string());
+                    self.emit("CALL __store".to_string());
+                }
+            },
+            Stmt::If(ref cond, ref then, ref otherwise) => {
+                let else_label = self.new_label("else");
+                let end_label = self.new_label("endif");
+                try!(self.expr(cond));
+                self.test();
+                self.emit(format!("JZ {}", else_label));
+                try!(self.statement(&then.0, then.1));
+                self.jump(&end_label);
+                self.label(&else_label);
+                if let Some(otherwise) = otherwise.as_ref() {
+                    try!(self.statement(&otherwise.0, otherwise.1));
+                }
+                self.label(&end_label);
+            },
+            Stmt::While(ref cond, ref body) => {
+                let top_label = self.new_label("while");
+                let end_label = self.new_label("endwhile");
+                self.label(&top_label);
+                try!(self.expr(cond));
+                self.test();
+                self.emit(format!("JZ {}", end_label));
+                try!(self.statement(&body.0, body.1));
+                self.jump(&top_label);
+                self.label(&end_label);
+            },
+            Stmt::Return(ref value) => {
+                match value.as_ref() {
+                    Some(value) => try!(self.expr(value)),
+                    None => self.load_const("EAX", 0),
+                }
+                let ret_label = self.ret_label.clone();
+                self.jump(&ret_label);
+            },
+            Stmt::Expr(ref expr) => try!(self.expr(expr)),
+        }
+        Ok(())
+    }
+
+    fn lookup(&self, name:&str) -> Result<Place, CompileError> {
+        for scope in self.scopes.iter().rev() {
+            if let Some(&place) = scope.get(name) {
+                return Ok(place);
+            }
+        }
+        self.globals.get(name).cloned().ok_or(CompileError::UndefinedVariable(self.line, name.to_string()))
+    }
+
+    //leaves the value of expr in EAX
+    fn expr(&mut self, expr:&Expr) -> Result<(), CompileError> {
+        match *expr {
+            Expr::Num(n) => self.load_const("EAX", n),
+            Expr::Var(ref name) => match try!(self.lookup(name)) {
+                Place::Global(false) => self.emit(format!("LD EAX, {}", name)),
+                //arrays stand for the address of their first element
+                Place::Global(true) | Place::Local(_, true) => try!(self.address(expr)),
+                Place::Local(_, false) => {
+                    try!(self.address(expr));
+                    self.emit("CALL __load".to_string());
+                },
+            },
+            Expr::Unary("-", ref operand) => {
+                try!(self.expr(operand));
+                self.load_const("ECX", -1);
+                self.emit("MUL EAX, ECX".to_string());
+            },
+            Expr::Unary("!", ref operand) => {
+                try!(self.expr(operand));
+                self.test();
+                let (true_label, false_label) = (self.new_label("true"), self.new_label("false"));
+                self.emit(format!("JZ {}", true_label));
+                self.jump(&false_label);
+                self.boolean(&true_label, &false_label);
+            },
+            Expr::Unary("&", ref operand) => try!(self.address(operand)),
+            Expr::Unary(_, _) | Expr::Index(_, _) => {
+                try!(self.address(expr));
+                self.emit("CALL __load".to_string());
+            },
+            Expr::Binary("&&", ref lhs, ref rhs) => {
+                let (true_label, false_label) = (self.new_label("true"), self.new_label("false"));
+                try!(self.expr(lhs));
+                self.test();
+                self.emit(format!("JZ {}", false_label));
+                try!(self.expr(rhs));
+                self.test();
+                self.emit(format!("JZ {}", false_label));
+                self.jump(&true_label);
+                self.boolean(&true_label, &false_label);
+            },
+            Expr::Binary("||", ref lhs, ref rhs) => {
+                let (true_label, false_label, rhs_label) = (self.new_label("true"), self.new_label("false"), self.new_label("or"));
+                try!(self.expr(lhs));
+                self.test();
+                self.emit(format!("JZ {}", rhs_label));
+                self.jump(&true_label);
+                self.label(&rhs_label);
+                try!(self.expr(rhs));
+                self.test();
+                self.emit(format!("JZ {}", false_label));
+                self.jump(&true_label);
+                self.boolean(&true_label, &false_label);
+            },
+            Expr::Binary(op, ref lhs, ref rhs) => {
+                try!(self.expr(lhs));
+                self.emit("PUSH EAX".to_string());
+                try!(self.expr(rhs));
+                self.emit("POP EBX".to_string());
+                match op {
+                    "+" => self.emit("ADD EAX, EBX".to_string()),
+                    "*" => self.emit("MUL EAX, EBX".to_string()),
+                    _ => {
+                        //EAX = EBX - EAX, which also sets the flags for the comparisons
+                        self.load_const("ECX", -1);
+                        self.emit("MUL EAX, ECX".to_string());
+                        self.emit("ADD EAX, EBX".to_string());
+                        if op != "-" {
+                            self.compare(op);
+                        }
+                    },
+                }
+            },
+            Expr::Assign(ref target, ref value) => {
+                match *target.as_ref() {
+                    Expr::Var(ref name) => match try!(self.lookup(name)) {
+                        Place::Global(false) => {
+                            try!(self.expr(value));
+                            self.emit(format!("SAV {}, EAX", name));
+                            return Ok(());
+                        },
+                        Place::Global(true) | Place::Local(_, true) => return Err(CompileError::InvalidLvalue(self.line)),
+                        Place::Local(_, false) => {},
+                    },
+                    _ => {},
+                }
+                try!(self.address(target));
+                self.emit("PUSH EAX".to_string());
+                try!(self.expr(value));
+                self.emit("POP EBX".to_string());
+                self.emit("CALL __store".to_string());
+            },
+            Expr::Call(ref name, ref args) => {
+                match self.functions.get(name) {
+                    Some(&n) if n == args.len() => {},
+                    Some(&n) => return Err(CompileError::WrongArgumentCount(self.line, name.clone(), n, args.len())),
+                    None => return Err(CompileError::UndefinedFunction(self.line, name.clone())),
+                }
+                for arg in args.iter().rev() {
+                    try!(self.expr(arg));
+                    self.emit("PUSH EAX".to_string());
+                }
+                self.emit(format!("CALL {}", name));
+                if !args.is_empty() {
+                    self.load_const("ECX", args.len() as i64);
+                    self.emit("ADD ESP, ECX".to_string());
+                }
+            },
+        }
+        Ok(())
+    }
+
+    //turns the flags of lhs - rhs into 0 or 1
+    fn compare(&mut self, op:&str) {
+        let (true_label, false_label) = (self.new_label("true"), self.new_label("false"));
+        match op {
+            "==" => self.emit(format!("JZ {}", true_label)),
+            "!=" => self.emit(format!("JZ {}", false_label)),
+            "<"  => self.emit(format!("JLZ {}", true_label)),
+            ">=" => self.emit(format!("JGZ {}", true_label)),
+            ">"  => {
+                self.emit(format!("JZ {}", false_label));
+                self.emit(format!("JGZ {}", true_label));
+            },
+            _    => {
+                self.emit(format!("JZ {}", true_label));
+                self.emit(format!("JLZ {}", true_label));
+            },
+        }
+        if op == "!=" {
+            self.jump(&true_label);
+        } else {
+            self.jump(&false_label);
+        }
+        self.boolean(&true_label, &false_label);
+    }
+
+    fn boolean(&mut self, true_label:&str, false_label:&str) {
+        let end_label = self.new_label("bool");
+        self.label(false_label);
+        self.load_const("EAX", 0);
+        self.jump(&end_label);
+        self.label(true_label);
+        self.load_const("EAX", 1);
+        self.label(&end_label);
+    }
+
+    //leaves the address of an lvalue in EAX
+    fn address(&mut self, expr:&Expr) -> Result<(), CompileError> {
+        match *expr {
+            Expr::Var(ref name) => match try!(self.lookup(name)) {
+                Place::Global(_) => {
+                    let next = self.addresses.len();
+                    let label = self.addresses.entry(name.clone()).or_insert(format!("__addr{}", next)).clone();
+                    self.emit(format!("LD EAX, {}", label));
+                },
+                Place::Local(offset, _) => {
+                    self.load_const("EAX", offset);
+                    self.emit("ADD EAX, EBP".to_string());
+                },
+            },
+            Expr::Unary("*", ref pointer) => try!(self.expr(pointer)),
+            Expr::Index(ref base, ref index) => {
+                try!(self.expr(base));
+                self.emit("PUSH EAX".to_string());
+                try!(self.expr(index));
+                self.emit("POP EBX".to_string());
+                self.emit("ADD EAX, EBX".to_string());
+            },
+            _ => return Err(CompileError::InvalidLvalue(self.line)),
+        }
+        Ok(())
+    }
+}
+
+fn global_data(global:&Global) -> String {
+    let len = global.len.unwrap_or(1) as usize;
+    let mut words:Vec<String> = global.init.iter().map(|v| v.to_string()).collect();
+    let rest = len - words.len();
+    if words.is_empty() {
+        return format!("{}: .zero {}", global.name, len);
+    }
+    if rest > 0 && rest <= 8 {
+        words.extend((0..rest).map(|_| "0".to_string()));
+    }
+    let mut data = format!("{}: .word {}", global.name, words.join(", "));
+    if rest > 8 {
+        data = data + &format!("\n.zero {}", rest);
+    }
+    data
+}
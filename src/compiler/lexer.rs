@@ -0,0 +1,99 @@
+use compiler::CompileError;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    Num(i64),
+    Ident(String),
+    Keyword(&'static str),
+    Punct(&'static str),
+    Eof,
+}
+
+const KEYWORDS:[&'static str; 6] = ["int", "void", "if", "else", "while", "return"];
+
+//longest first, so that "<=" is not read as "<" "="
+const PUNCTS:[&'static str; 24] = [
+    "==", "!=", "<=", ">=", "&&", "||",
+    "+", "-", "*", "/", "%", "&", "!", "<", ">", "=",
+    "(", ")", "{", "}", "[", "]", ";", ",",
+];
+
+//every token with the line it starts in
+pub fn tokenize(source:&str) -> Result<Vec<(Token, usize)>, CompileError> {
+    let mut tokens = Vec::new();
+    let chars:Vec<char> = source.chars().collect();
+    let mut line = 1;
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        if c == '\n' {
+            line += 1;
+            i += 1;
+        } else if c.is_whitespace() {
+            i += 1;
+        } else if c == '/' && chars.get(i + 1) == Some(&'/') {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+        } else if c == '/' && chars.get(i + 1) == Some(&'*') {
+            i += 2;
+            while i < chars.len() && !(chars[i] == '*' && chars.get(i + 1) == Some(&'/')) {
+                if chars[i] == '\n' {
+                    line += 1;
+                }
+                i += 1;
+            }
+            if i >= chars.len() {
+                return Err(CompileError::UnexpectedEof(line, "unterminated comment".to_string()));
+            }
+            i += 2;
+        } else if c.is_digit(10) {
+            let start = i;
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            let text:String = chars[start..i].iter().cloned().filter(|&c| c != '_').collect();
+            let value = if text.starts_with("0x") || text.starts_with("0X") {
+                u64::from_str_radix(&text[2..], 16)
+            } else {
+                text.parse::<u64>()
+            };
+            let value = try!(value.map_err(|_| CompileError::UnexpectedToken(line, text.clone())));
+            tokens.push((Token::Num(value as i64), line));
+        } else if c.is_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            let word:String = chars[start..i].iter().cloned().collect();
+            //the code generator names its labels __something
+            if word.starts_with("__") {
+                return Err(CompileError::ReservedName(line, word));
+            }
+            match KEYWORDS.iter().find(|&&k| k == word) {
+                Some(&keyword) => tokens.push((Token::Keyword(keyword), line)),
+                None => tokens.push((Token::Ident(word), line)),
+            }
+        } else if c == '\'' {
+            let (value, len) = match (chars.get(i + 1), chars.get(i + 2), chars.get(i + 3)) {
+                (Some(&'\\'), Some(&e), Some(&'\'')) => (match e { 'n' => '\n', 't' => '\t', '0' => '\0', e => e }, 4),
+                (Some(&c), Some(&'\''), _) if c != '\\' => (c, 3),
+                _ => return Err(CompileError::UnexpectedToken(line, "'".to_string())),
+            };
+            tokens.push((Token::Num(value as i64), line));
+            i += len;
+        } else {
+            let rest:String = chars[i..chars.len().min(i + 2)].iter().cloned().collect();
+            match PUNCTS.iter().find(|&&p| rest.starts_with(p)) {
+                Some(&punct) => {
+                    tokens.push((Token::Punct(punct), line));
+                    i += punct.len();
+                },
+                None => return Err(CompileError::UnexpectedToken(line, c.to_string())),
+            }
+        }
+    }
+    tokens.push((Token::Eof, line));
+    Ok(tokens)
+}
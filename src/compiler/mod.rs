@@ -0,0 +1,96 @@
+//compiles a small C-like language to assembly for the parser:
+//int and pointer variables, arrays, functions, if/else, while, return and the operators
+//+ - * == != < <= > >= && || ! unary - * & and [].
+mod lexer;
+mod ast;
+mod syntax;
+mod codegen;
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use diagnostic::Diagnostic;
+use parser::{Parser, MemoryImage};
+
+pub fn compile(_source:&str) -> Result<String, CompileError> {
+    let tokens = try!(lexer::tokenize(_source));
+    let program = try!(syntax::SyntaxParser::new(tokens).program());
+    codegen::CodeGen::new().program(&program)
+}
+
+//the image and its labels: the program starts at __start and ends in the loop at __halt
+//with the result of main in EAX
+pub fn compile_to_image(_source:&str) -> Result<(MemoryImage, HashMap<String, u64>), CompileError> {
+    let asm = try!(compile(_source));
+    let mut parser = Parser::new();
+    try!(parser.assemble(&asm).map_err(CompileError::Assembler));
+    Ok((parser.image().clone(), parser.labels().clone()))
+}
+
+#[derive(Debug)]
+pub enum CompileError {
+    UnexpectedToken(usize, String),
+    UnexpectedEof(usize, String),
+    InvalidDeclaration(usize, String),
+    NotConstant(usize),
+    Unsupported(usize, String),
+    UndefinedVariable(usize, String),
+    UndefinedFunction(usize, String),
+    Redefinition(usize, String),
+    ReservedName(usize, String),
+    InvalidLvalue(usize),
+    WrongArgumentCount(usize, String, usize, usize),
+    NoMain,
+    Assembler(Vec<Diagnostic>),
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CompileError::UnexpectedToken(line, ref s)      => write!(f, "UnexpectedToken: line {}: {}", line, s),
+            CompileError::UnexpectedEof(line, ref s)        => write!(f, "UnexpectedEof: line {}: {}", line, s),
+            CompileError::InvalidDeclaration(line, ref s)   => write!(f, "InvalidDeclaration: line {}: {}", line, s),
+            CompileError::NotConstant(line)                 => write!(f, "NotConstant: line {}: expression has to be constant", line),
+            CompileError::Unsupported(line, ref s)          => write!(f, "Unsupported: line {}: {} is not supported", line, s),
+            CompileError::UndefinedVariable(line, ref s)    => write!(f, "UndefinedVariable: line {}: {}", line, s),
+            CompileError::UndefinedFunction(line, ref s)    => write!(f, "UndefinedFunction: line {}: {}", line, s),
+            CompileError::Redefinition(line, ref s)         => write!(f, "Redefinition: line {}: {} is already defined", line, s),
+            CompileError::ReservedName(line, ref s)         => write!(f, "ReservedName: line {}: {} starts with __, which is kept for the generated code", line, s),
+            CompileError::InvalidLvalue(line)               => write!(f, "InvalidLvalue: line {}: cannot assign to this expression", line),
+            CompileError::WrongArgumentCount(line, ref s, expected, got)
+                => write!(f, "WrongArgumentCount: line {}: {} takes {} argument(s) but {} were given", line, s, expected, got),
+            CompileError::NoMain                            => write!(f, "NoMain: the program needs a function main without parameters"),
+            CompileError::Assembler(ref diagnostics)        => {
+                try!(write!(f, "Assembler: the generated code does not assemble"));
+                for diagnostic in diagnostics.iter() {
+                    try!(write!(f, "\n{}", diagnostic));
+                }
+                Ok(())
+            },
+        }
+    }
+}
+
+impl Error for CompileError {
+    fn description(&self) -> &str {
+        match *self {
+            CompileError::UnexpectedToken(_, _)         => "unexpected token",
+            CompileError::UnexpectedEof(_, _)           => "unexpected end of input",
+            CompileError::InvalidDeclaration(_, _)      => "invalid declaration",
+            CompileError::NotConstant(_)                => "expression is not constant",
+            CompileError::Unsupported(_, _)             => "unsupported feature",
+            CompileError::UndefinedVariable(_, _)       => "undefined variable",
+            CompileError::UndefinedFunction(_, _)       => "undefined function",
+            CompileError::Redefinition(_, _)            => "redefinition",
+            CompileError::ReservedName(_, _)            => "reserved name",
+            CompileError::InvalidLvalue(_)              => "invalid lvalue",
+            CompileError::WrongArgumentCount(_, _, _, _) => "wrong number of arguments",
+            CompileError::NoMain                        => "no main function",
+            CompileError::Assembler(_)                  => "assembler error",
+        }
+    }
+
+    fn cause(&self) -> Option<&Error> {
+        None
+    }
+}
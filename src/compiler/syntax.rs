@@ -0,0 +1,347 @@
+use compiler::CompileError;
+use compiler::lexer::Token;
+use compiler::ast::*;
+
+//recursive descent over the token list, one method per level of precedence
+pub struct SyntaxParser {
+    tokens:Vec<(Token, usize)>,
+    pos:usize,
+}
+
+impl SyntaxParser {
+
+    pub fn new(tokens:Vec<(Token, usize)>) -> SyntaxParser {
+        SyntaxParser{ tokens:tokens, pos:0 }
+    }
+
+    fn peek(&self) -> &Token {
+        &self.tokens[self.pos].0
+    }
+
+    fn line(&self) -> usize {
+        self.tokens[self.pos].1
+    }
+
+    fn next(&mut self) -> Token {
+        let token = self.tokens[self.pos].0.clone();
+        if token != Token::Eof {
+            self.pos += 1;
+        }
+        token
+    }
+
+    fn eat(&mut self, punct:&str) -> bool {
+        match *self.peek() {
+            Token::Punct(p) | Token::Keyword(p) if p == punct => { self.pos += 1; true },
+            _ => false,
+        }
+    }
+
+    fn unexpected(&self) -> CompileError {
+        match *self.peek() {
+            Token::Eof => CompileError::UnexpectedEof(self.line(), "unexpected end of input".to_string()),
+            ref token => CompileError::UnexpectedToken(self.line(), describe(token)),
+        }
+    }
+
+    fn expect(&mut self, punct:&str) -> Result<(), CompileError> {
+        if self.eat(punct) { Ok(()) } else { Err(self.unexpected()) }
+    }
+
+    fn ident(&mut self) -> Result<String, CompileError> {
+        match *self.peek() {
+            Token::Ident(_) => {},
+            _ => return Err(self.unexpected()),
+        }
+        match self.next() {
+            Token::Ident(name) => Ok(name),
+            _ => unreachable!(),
+        }
+    }
+
+    //"int", "void" and any number of "*"
+    fn type_name(&mut self) -> Result<(), CompileError> {
+        if !self.eat("int") && !self.eat("void") {
+            return Err(self.unexpected());
+        }
+        while self.eat("*") {}
+        Ok(())
+    }
+
+    pub fn program(&mut self) -> Result<Program, CompileError> {
+        let mut program = Program{ globals:Vec::new(), functions:Vec::new() };
+        while *self.peek() != Token::Eof {
+            let line = self.line();
+            try!(self.type_name());
+            let name = try!(self.ident());
+            if self.eat("(") {
+                program.functions.push(try!(self.function(name, line)));
+                continue;
+            }
+            let mut name = name;
+            loop {
+                program.globals.push(try!(self.global(name, line)));
+                if !self.eat(",") {
+                    break;
+                }
+                while self.eat("*") {}
+                name = try!(self.ident());
+            }
+            try!(self.expect(";"));
+        }
+        Ok(program)
+    }
+
+    fn function(&mut self, name:String, line:usize) -> Result<Function, CompileError> {
+        let mut params = Vec::new();
+        if !self.eat(")") {
+            //"f(void)" takes no parameters
+            if *self.peek() == Token::Keyword("void") && self.tokens[self.pos + 1].0 == Token::Punct(")") {
+                self.pos += 2;
+            } else {
+                loop {
+                    try!(self.type_name());
+                    params.push(try!(self.ident()));
+                    if !self.eat(",") {
+                        break;
+                    }
+                }
+                try!(self.expect(")"));
+            }
+        }
+        try!(self.expect("{"));
+        let body = try!(self.block());
+        Ok(Function{ name:name, params:params, body:body, line:line })
+    }
+
+    fn global(&mut self, name:String, line:usize) -> Result<Global, CompileError> {
+        let len = try!(self.array_len());
+        let mut init = Vec::new();
+        if self.eat("=") {
+            if len.is_some() && self.eat("{") {
+                loop {
+                    init.push(try!(self.constant()));
+                    if !self.eat(",") {
+                        break;
+                    }
+                }
+                try!(self.expect("}"));
+            } else {
+                init.push(try!(self.constant()));
+            }
+        }
+        if init.len() as u64 > len.unwrap_or(1) {
+            return Err(CompileError::InvalidDeclaration(line, format!("too many initializers for {}", name)));
+        }
+        Ok(Global{ name:name, len:len, init:init, line:line })
+    }
+
+    fn array_len(&mut self) -> Result<Option<u64>, CompileError> {
+        if !self.eat("[") {
+            return Ok(None);
+        }
+        let line = self.line();
+        let len = try!(self.constant());
+        try!(self.expect("]"));
+        if len <= 0 {
+            return Err(CompileError::InvalidDeclaration(line, format!("array length {}", len)));
+        }
+        Ok(Some(len as u64))
+    }
+
+    //globals and array lengths have to be known before the program runs
+    fn constant(&mut self) -> Result<i64, CompileError> {
+        let line = self.line();
+        let expr = try!(self.logic_or());
+        fold(&expr).ok_or(CompileError::NotConstant(line))
+    }
+
+    fn block(&mut self) -> Result<Vec<(Stmt, usize)>, CompileError> {
+        let mut stmts = Vec::new();
+        while !self.eat("}") {
+            if *self.peek() == Token::Eof {
+                return Err(self.unexpected());
+            }
+            stmts.push(try!(self.statement()));
+        }
+        Ok(stmts)
+    }
+
+    fn statement(&mut self) -> Result<(Stmt, usize), CompileError> {
+        let line = self.line();
+        let stmt = match *self.peek() {
+            Token::Punct("{") => { self.pos += 1; Stmt::Block(try!(self.block())) },
+            Token::Punct(";") => { self.pos += 1; Stmt::Empty },
+            Token::Keyword("int") | Token::Keyword("void") => {
+                try!(self.type_name());
+                let name = try!(self.ident());
+                let len = try!(self.array_len());
+                let init = if len.is_none() && self.eat("=") { Some(try!(self.expr())) } else { None };
+                try!(self.expect(";"));
+                Stmt::Decl(name, len, init)
+            },
+            Token::Keyword("if") => {
+                self.pos += 1;
+                try!(self.expect("("));
+                let cond = try!(self.expr());
+                try!(self.expect(")"));
+                let then = try!(self.statement());
+                let otherwise = if self.eat("else") { Some(Box::new(try!(self.statement()))) } else { None };
+                Stmt::If(cond, Box::new(then), otherwise)
+            },
+            Token::Keyword("while") => {
+                self.pos += 1;
+                try!(self.expect("("));
+                let cond = try!(self.expr());
+                try!(self.expect(")"));
+                Stmt::While(cond, Box::new(try!(self.statement())))
+            },
+            Token::Keyword("return") => {
+                self.pos += 1;
+                let value = if self.eat(";") { None } else {
+                    let value = try!(self.expr());
+                    try!(self.expect(";"));
+                    Some(value)
+                };
+                Stmt::Return(value)
+            },
+            _ => {
+                let expr = try!(self.expr());
+                try!(self.expect(";"));
+                Stmt::Expr(expr)
+            },
+        };
+        Ok((stmt, line))
+    }
+
+    pub fn expr(&mut self) -> Result<Expr, CompileError> {
+        let lhs = try!(self.logic_or());
+        if self.eat("=") {
+            let rhs = try!(self.expr());
+            return Ok(Expr::Assign(Box::new(lhs), Box::new(rhs)));
+        }
+        Ok(lhs)
+    }
+
+    fn binary(&mut self, ops:&[&'static str], operand:fn(&mut SyntaxParser) -> Result<Expr, CompileError>) -> Result<Expr, CompileError> {
+        let mut lhs = try!(operand(self));
+        loop {
+            let op = match *self.peek() {
+                Token::Punct(p) if ops.contains(&p) => p,
+                _ => return Ok(lhs),
+            };
+            //the ISA has no division, a library routine would be needed for it
+            if op == "/" || op == "%" {
+                return Err(CompileError::Unsupported(self.line(), format!("operator {}", op)));
+            }
+            self.pos += 1;
+            let rhs = try!(operand(self));
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+    }
+
+    fn logic_or(&mut self) -> Result<Expr, CompileError> {
+        self.binary(&["||"], SyntaxParser::logic_and)
+    }
+
+    fn logic_and(&mut self) -> Result<Expr, CompileError> {
+        self.binary(&["&&"], SyntaxParser::equality)
+    }
+
+    fn equality(&mut self) -> Result<Expr, CompileError> {
+        self.binary(&["==", "!="], SyntaxParser::relation)
+    }
+
+    fn relation(&mut self) -> Result<Expr, CompileError> {
+        self.binary(&["<", "<=", ">", ">="], SyntaxParser::sum)
+    }
+
+    fn sum(&mut self) -> Result<Expr, CompileError> {
+        self.binary(&["+", "-"], SyntaxParser::product)
+    }
+
+    fn product(&mut self) -> Result<Expr, CompileError> {
+        self.binary(&["*", "/", "%"], SyntaxParser::unary)
+    }
+
+    fn unary(&mut self) -> Result<Expr, CompileError> {
+        let op = match *self.peek() {
+            Token::Punct(p) if ["-", "!", "*", "&"].contains(&p) => p,
+            _ => return self.postfix(),
+        };
+        self.pos += 1;
+        Ok(Expr::Unary(op, Box::new(try!(self.unary()))))
+    }
+
+    fn postfix(&mut self) -> Result<Expr, CompileError> {
+        let mut expr = try!(self.primary());
+        while self.eat("[") {
+            let index = try!(self.expr());
+            try!(self.expect("]"));
+            expr = Expr::Index(Box::new(expr), Box::new(index));
+        }
+        Ok(expr)
+    }
+
+    fn primary(&mut self) -> Result<Expr, CompileError> {
+        if *self.peek() == Token::Eof {
+            return Err(self.unexpected());
+        }
+        match self.next() {
+            Token::Num(n) => Ok(Expr::Num(n)),
+            Token::Ident(name) => {
+                if !self.eat("(") {
+                    return Ok(Expr::Var(name));
+                }
+                let mut args = Vec::new();
+                if !self.eat(")") {
+                    loop {
+                        args.push(try!(self.expr()));
+                        if !self.eat(",") {
+                            break;
+                        }
+                    }
+                    try!(self.expect(")"));
+                }
+                Ok(Expr::Call(name, args))
+            },
+            Token::Punct("(") => {
+                let expr = try!(self.expr());
+                try!(self.expect(")"));
+                Ok(expr)
+            },
+            _ => { self.pos -= 1; Err(self.unexpected()) },
+        }
+    }
+}
+
+fn describe(token:&Token) -> String {
+    match *token {
+        Token::Num(n)           => n.to_string(),
+        Token::Ident(ref s)     => s.clone(),
+        Token::Keyword(k)       => k.to_string(),
+        Token::Punct(p)         => p.to_string(),
+        Token::Eof              => "end of input".to_string(),
+    }
+}
+
+//the value of an expression made of numbers only
+pub fn fold(expr:&Expr) -> Option<i64> {
+    match *expr {
+        Expr::Num(n) => Some(n),
+        Expr::Unary("-", ref e) => fold(e).map(|n| n.wrapping_neg()),
+        Expr::Binary(op, ref lhs, ref rhs) => {
+            let (lhs, rhs) = match (fold(lhs), fold(rhs)) {
+                (Some(lhs), Some(rhs)) => (lhs, rhs),
+                _ => return None,
+            };
+            match op {
+                "+" => Some(lhs.wrapping_add(rhs)),
+                "-" => Some(lhs.wrapping_sub(rhs)),
+                "*" => Some(lhs.wrapping_mul(rhs)),
+                _   => None,
+            }
+        },
+        _ => None,
+    }
+}
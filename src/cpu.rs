@@ -1,10 +1,14 @@
 use snowflake::ProcessUniqueId;
-use std::sync::mpsc::{Sender, Receiver, channel};
+use std::sync::mpsc::{Sender, Receiver, TryRecvError, channel};
+use std::thread;
 use utils::*;
+use std::sync::{Arc, Mutex};
+use cache;
+use coherence;
 
-//const CACHE_SIZE:usize = 0;
 const CORE_NUM:usize = 1;  //number of cores per cpu
 const PIPE_SIZE:usize = 8; //size of instruction pipeline
+const NO_PIPE:u64 = !0 - 2 * PIPE_SIZE as u64; //an address no instruction can reach, so the first fetch misses
 
 #[derive(Debug)]
 pub struct Core { //TODO: rewrite tests so that members don't need to be public
@@ -26,15 +30,46 @@ pub struct Core { //TODO: rewrite tests so that members don't need to be public
     pub SIGN:bool,
     pub CARRY:bool,
 
+    //CACHES, without them instructions are prefetched into the pipe
+    pub caches:Option<cache::Hierarchy>,
+    //or caches kept coherent with those of the other cores
+    pub coherence:Option<coherence::Port>,
+
     //CPU BUS
     pub tx:Sender<CPUBusOp>,
     pub rx:Receiver<CPUBusOp>,
 }
 
+//the memory below the caches of a core
+struct CoreBus<'a> {
+    tx:&'a Sender<CPUBusOp>,
+    rx:&'a Receiver<CPUBusOp>,
+}
+
+impl<'a> cache::Backing for CoreBus<'a> {
+    fn fetch(&mut self, addr:u64, n:usize) -> Vec<u64> {
+        request_block(self.tx, self.rx, addr, n).into_iter().map(|(_, word)| word).collect()
+    }
+
+    fn store(&mut self, values:Vec<(u64, u64)>) {
+        self.tx.send(CPUBusOp::GiveBlock(values));
+    }
+}
+
+fn request_block(tx:&Sender<CPUBusOp>, rx:&Receiver<CPUBusOp>, start_addr:u64, num:usize) -> Vec<(u64, u64)> {
+    tx.send(CPUBusOp::RequestBlock(start_addr,num));
+    match rx.recv().expect("CPUBus has disconnected unexpectedly") {
+        CPUBusOp::GiveBlock(res_vec) => res_vec,
+        CPUBusOp::RequestBlock(_,_) => panic!("Unexpectedly received RequestBlock in read_from_memory()"),
+        CPUBusOp::Error(err) => panic!("CPUBus returned error in read_from_memory(): {}", err),
+        op => panic!("Unimplemented CPUBusOp:{:?}", op),
+    }
+}
+
 impl Core {
     pub fn new(_tx:Sender<CPUBusOp>, _rx:Receiver<CPUBusOp>) -> Core {
         Core{   ID:ProcessUniqueId::new(), 
-                pipe:[(0, 0); PIPE_SIZE],
+                pipe:[(NO_PIPE, 0); PIPE_SIZE],
                 EAX:0,
                 EBX:0,
                 ECX:0,
@@ -46,6 +81,8 @@ impl Core {
                 ZERO:false,
                 SIGN:false,
                 CARRY:false,
+                caches:None,
+                coherence:None,
                 tx:_tx,
                 rx:_rx,
         }
@@ -77,7 +114,8 @@ impl Core {
     fn read_from_pipe(&self, addr:u64) -> Result<u64, ()> {
         let pipe_start = self.pipe[0].0;
         let pipe_end = pipe_start + PIPE_SIZE as u64;
-        if pipe_start <= addr && addr <= pipe_end {
+        //a block cut short at the end of memory leaves the rest of the pipe behind
+        if pipe_start <= addr && addr < pipe_end && self.pipe[(addr - pipe_start) as usize].0 == addr {
             let offset = addr - pipe_start;
             Ok(self.pipe[offset as usize].1)
         } else {
@@ -95,13 +133,9 @@ impl Core {
             self.CARRY = false;
         }
 
-        if res < 0 {
-            self.SIGN = true;
-            self.ZERO = false;
-        } else if res == 0 {
-            self.ZERO = true;
-            self.SIGN = false;
-        }
+        //results are two's complement, so the top bit is the sign
+        self.SIGN = (res as i64) < 0;
+        self.ZERO = res == 0;
     }
 
     fn reset_flags(&mut self) {
@@ -117,50 +151,49 @@ impl Core {
         let cur_instr = self.read_instr_at(cur_addr); 
         self.ISP += 1;
 
-        println!("exec_instr: (cur_addr, cur_instr) = ({},{})", cur_addr, cur_instr);   //DEBUG
+        self.execute(cur_instr);
+        let _ = self.tx.send(CPUBusOp::Tick);
+    }
 
+    //runs a single instruction that has already been fetched, ISP has to point behind it
+    pub fn execute(&mut self, cur_instr:Instruction) {
         match cur_instr.opcode() {
             Opcode::Add => {
-                let (mut n,of) = self.read_reg(cur_instr.reg1()).overflowing_add(self.read_reg(cur_instr.reg2()));
-                if of {
-                    n = n.wrapping_add(self.read_reg(cur_instr.reg1()));
-                }
+                let (n,of) = self.read_reg(cur_instr.reg1()).overflowing_add(self.read_reg(cur_instr.reg2()));
                 self.write_reg(cur_instr.reg1(), n);
                 self.set_flags(n,of);
             }, 
 
             Opcode::Mul => {
-                let (mut n,of) = self.read_reg(cur_instr.reg1()).overflowing_mul(self.read_reg(cur_instr.reg2()));
-                if of {
-                    n = n.wrapping_mul(self.read_reg(cur_instr.reg1()));
-                }
+                let (n,of) = self.read_reg(cur_instr.reg1()).overflowing_mul(self.read_reg(cur_instr.reg2()));
                 self.write_reg(cur_instr.reg1(), n);
                 self.set_flags(n,of);
             }, 
 
             Opcode::Ld  => {
-                let n = self.read_from_memory(cur_instr.addr(), 1).pop().expect("Received empty block from read_from_memory()").1;
+                let n = self.load(cur_instr.addr());
                 self.write_reg(cur_instr.reg1(), n);
                 self.set_flags(n,false);
             }, 
 
             Opcode::Sav  => {
                 let n = self.read_reg(cur_instr.reg1());
-                self.write_to_memory(vec![(cur_instr.addr(), n)]);
+                self.store(cur_instr.addr(), n);
                 self.set_flags(n,false);
             },
 
             Opcode::Push => {
                 self.ESP -= 1; 
                 let n = self.read_reg(cur_instr.reg1());
-                self.write_to_memory(vec![(self.ESP, n)]);
+                let addr = self.ESP;
+                self.store(addr, n);
                 self.set_flags(n,false);
             },
 
             Opcode::Pop => {
+                //the inverse of PUSH: read the top of the stack, then shrink it
+                let n = self.load(self.ESP);
                 self.ESP += 1;
-                assert!(self.ESP >= 0);
-                let n = self.read_from_memory(self.ESP, 1).pop().expect("Received empty block from read_from_memory()").1;
                 self.write_reg(cur_instr.reg1(), n);
                 self.set_flags(n,false);
             },
@@ -175,7 +208,19 @@ impl Core {
             Opcode::Jlz => if self.SIGN {
                 self.ISP = cur_instr.addr();
                 self.set_flags(0,false);
-            }, 
+            },
+            Opcode::Call => {
+                self.ESP -= 1;
+                let ret_addr = self.ISP;
+                let addr = self.ESP;
+                self.store(addr, ret_addr);
+                self.ISP = cur_instr.addr();
+            },
+            Opcode::Ret => {
+                let n = self.load(self.ESP);
+                self.ESP += 1;
+                self.ISP = n;
+            },
             Opcode::Nop => {
                 self.OVERFLOW = false;
                 self.ZERO = false;
@@ -186,38 +231,74 @@ impl Core {
     }
 
     fn read_instr_at(&mut self, addr:u64) -> Instruction {
+        if let Some(ref port) = self.coherence {
+            return Instruction(port.fetch_instr(addr, &mut CoreBus { tx:&self.tx, rx:&self.rx }));
+        }
+        if let Some(ref mut caches) = self.caches {
+            return Instruction(caches.fetch_instr(addr, &mut CoreBus { tx:&self.tx, rx:&self.rx }));
+        }
         if let Ok(opcode) = self.read_from_pipe(addr) {
             Instruction(opcode)
         }
         else {
-            let mut mem_block = self.read_from_memory(addr, PIPE_SIZE);
-            let mut i:usize = PIPE_SIZE-1;
-            while let Some(item) = mem_block.pop(){
+            let mem_block = self.read_from_memory(addr, PIPE_SIZE);
+            self.pipe = [(NO_PIPE, 0); PIPE_SIZE];
+            for (i, item) in mem_block.into_iter().enumerate().take(PIPE_SIZE) {
                 self.pipe[i] = item;
-                i -= 1;
             }
             Instruction(self.pipe[0].1)
         }
     }
 
     fn read_from_memory(&self, start_addr:u64, num:usize) -> Vec<(u64, u64)> {
-        self.tx. send(CPUBusOp::RequestBlock(start_addr,num));
-        match self.rx.recv().expect("CPUBus has disconnected unexpectedly") {
-            CPUBusOp::GiveBlock(res_vec) => res_vec,
-            CPUBusOp::RequestBlock(_,_) => panic!("Unexpectedly received RequestBlock in read_from_memory()"),
-            CPUBusOp::Error(err) => panic!("CPUBus returned error in read_from_memory(): {}", err),
-            op => panic!("Unimplemented CPUBusOp:{:?}", op),
+        request_block(&self.tx, &self.rx, start_addr, num)
+    }
+
+    fn load(&mut self, addr:u64) -> u64 {
+        if let Some(ref port) = self.coherence {
+            return port.read(addr, &mut CoreBus { tx:&self.tx, rx:&self.rx });
+        }
+        match self.caches {
+            Some(ref mut caches) => caches.read(addr, &mut CoreBus { tx:&self.tx, rx:&self.rx }),
+            None => self.read_from_memory(addr, 1).pop().expect("Received empty block from read_from_memory()").1,
+        }
+    }
+
+    fn store(&mut self, addr:u64, word:u64) {
+        if let Some(ref port) = self.coherence {
+            return port.write(addr, word, &mut CoreBus { tx:&self.tx, rx:&self.rx });
+        }
+        match self.caches {
+            Some(ref mut caches) => caches.write(addr, word, &mut CoreBus { tx:&self.tx, rx:&self.rx }),
+            None => self.write_to_memory(vec![(addr, word)]),
         }
     }
 
-    fn write_to_memory(&self, values:Vec<(u64, u64)>) {
+    //dirty lines are written back, so that memory holds what the core has written
+    pub fn flush(&mut self) {
+        if let Some(ref port) = self.coherence {
+            port.flush(&mut CoreBus { tx:&self.tx, rx:&self.rx });
+        }
+        if let Some(ref mut caches) = self.caches {
+            caches.flush(&mut CoreBus { tx:&self.tx, rx:&self.rx });
+        }
+    }
+
+    //writes go through the pipe as well, so that code which patches itself sees the new words
+    fn write_to_memory(&mut self, values:Vec<(u64, u64)>) {
+        for &(addr, word) in values.iter() {
+            if let Some(entry) = self.pipe.iter_mut().find(|entry| entry.0 == addr) {
+                entry.1 = word;
+            }
+        }
         self.tx.send(CPUBusOp::GiveBlock(values));
     }
 }
 
 pub struct CPU {
-    //cache:[u64, CACHE_SIZE],
-    cores:Vec<(Core, Sender<CPUBusOp>, Receiver<CPUBusOp>)>,
+    cores:Vec<(ProcessUniqueId, Option<Core>, Sender<CPUBusOp>, Receiver<CPUBusOp>)>, //None while the core runs
+    coherence:Option<Arc<Mutex<coherence::Bus>>>,
+    uncached:Vec<(u64, u64)>, //start and length of the ranges no cache may hold
     //BUS
     tx:Sender<MemBusOp>,
     rx:Receiver<MemBusOp>,
@@ -225,38 +306,167 @@ pub struct CPU {
 
 impl CPU {
     pub fn new(_tx:Sender<MemBusOp>, _rx:Receiver<MemBusOp>) -> CPU {
-        let mut _cores:Vec<(Core, Sender<CPUBusOp>, Receiver<CPUBusOp>)> = Vec::new();
-        for i in 0..CORE_NUM {
-            let (tx_cpu, rx_core) = channel();
-            let (tx_core, rx_cpu) = channel();
-            _cores.push((Core::new(tx_core, rx_core), tx_cpu, rx_cpu));
+        let mut cpu = CPU{  cores:Vec::new(),
+                            coherence:None,
+                            uncached:Vec::new(),
+                            tx:_tx,
+                            rx:_rx,};
+        for _ in 0..CORE_NUM {
+            cpu.add_core();
         }
-        CPU{cores:_cores,
-            tx:_tx,
-            rx:_rx,}
+        cpu
+    }
+
+    //returns the index of the new core, which starts at address 0 without caches
+    pub fn add_core(&mut self) -> usize {
+        let (tx_cpu, rx_core) = channel();
+        let (tx_core, rx_cpu) = channel();
+        let core = Core::new(tx_core, rx_core);
+        self.cores.push((core.ID, Some(core), tx_cpu, rx_cpu));
+        self.cores.len() - 1
+    }
+
+    //every core starts executing at addr
+    pub fn set_entry(&mut self, addr:u64) {
+        for &mut (_, ref mut core, _, _) in self.cores.iter_mut() {
+            if let Some(ref mut core) = *core {
+                core.ISP = addr;
+            }
+        }
+    }
+
+    //words in [start, start + len) bypass the caches set from now on, like memory mapped devices
+    pub fn add_uncached(&mut self, start:u64, len:u64) {
+        self.uncached.push((start, len));
+    }
+
+    fn configure(&self, caches:&cache::Hierarchy) -> cache::Hierarchy {
+        let mut caches = caches.clone();
+        for &(start, len) in self.uncached.iter() {
+            caches.add_uncached(start, len);
+        }
+        caches
+    }
+
+    //every core gets caches of its own, configured like these
+    pub fn set_caches(&mut self, caches:&cache::Hierarchy) {
+        let caches = self.configure(caches);
+        for &mut (_, ref mut core, _, _) in self.cores.iter_mut() {
+            if let Some(ref mut core) = *core {
+                core.caches = Some(caches.clone());
+            }
+        }
+    }
+
+    //like set_caches, but the data caches snoop on each other to keep them coherent (MESI). Cores
+    //added later are left out.
+    pub fn set_coherent_caches(&mut self, caches:&cache::Hierarchy) {
+        let caches = self.configure(caches);
+        let bus = Arc::new(Mutex::new(coherence::Bus::new(vec![caches; self.cores.len()])));
+        for (i, &mut (_, ref mut core, _, _)) in self.cores.iter_mut().enumerate() {
+            if let Some(ref mut core) = *core {
+                core.caches = None;
+                core.coherence = Some(coherence::Port { bus:bus.clone(), core:i });
+            }
+        }
+        self.coherence = Some(bus);
+    }
+
+    //hits, misses and evictions of every cache level of every core
+    pub fn cache_report(&self) -> String {
+        let bus = self.coherence.as_ref().map(|bus| bus.lock().expect("A core failed while it owned the bus"));
+        let mut res = String::new();
+        for (i, &(_, ref core, _, _)) in self.cores.iter().enumerate() {
+            let report = match (core, &bus) {
+                (&Some(ref core), _) if core.caches.is_some() => core.caches.as_ref().unwrap().report(),
+                (&Some(ref core), &Some(ref bus)) if core.coherence.is_some() => bus.caches(i).report(),
+                _ => continue,
+            };
+            for line in report.lines() {
+                res.push_str(&format!("core {} {}\n", i, line));
+            }
+        }
+        res
+    }
+
+    //state transitions and bus traffic per line, None without coherent caches
+    pub fn coherence_report(&self) -> Option<String> {
+        self.coherence.as_ref().map(|bus| bus.lock().expect("A core failed while it owned the bus").report())
+    }
+
+    pub fn core(&self, i:usize) -> Option<&Core> {
+        self.cores.get(i).and_then(|&(_, ref core, _, _)| core.as_ref())
+    }
+
+    pub fn core_mut(&mut self, i:usize) -> Option<&mut Core> {
+        self.cores.get_mut(i).and_then(|&mut (_, ref mut core, _, _)| core.as_mut())
     }
 
     pub fn exec(&mut self) {
         
         loop {
-            while let Ok(op) = self.rx.try_recv() {
-                if let MemBusOp::GiveBlock(id, block) = op {
-                    if let Err(_) = self.cores.iter().find(|&&(ref c, _, _)| id == c.ID).expect("Unexpected ProcessorID in cpu::exec()!").1.send(CPUBusOp::GiveBlock(block)) {
-                        panic!("Channel from CPU to Core has closed unexpectedly in CPU::exec()");
+            self.forward();
+        }
+    }
+
+    //runs n instructions on every core, each on a thread of its own while this one carries their
+    //memory traffic. A core that fails is lost, the error is its panic message.
+    pub fn run(&mut self, n:usize) -> Result<(), String> {
+        let (done_tx, done_rx) = channel();
+        let mut handles = Vec::new();
+        for (i, &mut (_, ref mut core, _, _)) in self.cores.iter_mut().enumerate() {
+            if let Some(mut core) = core.take() {
+                let done_tx = done_tx.clone();
+                handles.push(thread::spawn(move || {
+                    for _ in 0..n {
+                        core.exec_instr();
                     }
-                } else {
-                    panic!("Unexpected MemBusOp in cpu::exec()");
-                }
+                    core.flush();
+                    let _ = done_tx.send((i, core));
+                }));
             }
-            for &(ref core, ref tx, ref rx) in self.cores.iter() {
-                while let Ok(op) = rx.try_recv() {
-                    if let CPUBusOp::RequestBlock(addr, size) = op {
-                        if let Err(_) = self.tx.send(MemBusOp::RequestBlock(core.ID, addr, size)) {
-                            panic!("Channel from CPU to Motherboard has closed unexpectedly in CPU::exec()");
-                        }
-                    } else {
-                        panic!("Unexpected MemBusOp while processing memory requests of cores in cpu::exec()");
-                    }
+        }
+        drop(done_tx);
+        loop {
+            self.forward();
+            match done_rx.try_recv() {
+                Ok((i, core)) => self.cores[i].1 = Some(core),
+                Err(TryRecvError::Empty) => thread::yield_now(),
+                Err(TryRecvError::Disconnected) => break,
+            }
+        }
+        for handle in handles {
+            if let Err(err) = handle.join() {
+                return Err(err.downcast_ref::<String>().cloned()
+                    .or(err.downcast_ref::<&str>().map(|s| s.to_string()))
+                    .unwrap_or("core stopped".to_string()));
+            }
+        }
+        Ok(())
+    }
+
+    //passes everything that is waiting on the buses along without blocking
+    fn forward(&mut self) {
+        while let Ok(op) = self.rx.try_recv() {
+            let (id, op) = match op {
+                MemBusOp::GiveBlock(id, block)  => (id, CPUBusOp::GiveBlock(block)),
+                MemBusOp::Error(id, err)        => (id, CPUBusOp::Error(err)),
+                _                               => panic!("Unexpected MemBusOp in cpu::exec()"),
+            };
+            if let Err(_) = self.cores.iter().find(|&&(core_id, _, _, _)| id == core_id).expect("Unexpected ProcessorID in cpu::exec()!").2.send(op) {
+                panic!("Channel from CPU to Core has closed unexpectedly in CPU::exec()");
+            }
+        }
+        for &(id, _, _, ref rx) in self.cores.iter() {
+            while let Ok(op) = rx.try_recv() {
+                let op = match op {
+                    CPUBusOp::RequestBlock(addr, size)  => MemBusOp::RequestBlock(id, addr, size),
+                    CPUBusOp::GiveBlock(values)         => MemBusOp::GiveBlock(id, values),
+                    CPUBusOp::Tick                      => MemBusOp::Tick,
+                    _                                   => panic!("Unexpected MemBusOp while processing memory requests of cores in cpu::exec()"),
+                };
+                if let Err(_) = self.tx.send(op) {
+                    panic!("Channel from CPU to Motherboard has closed unexpectedly in CPU::exec()");
                 }
             }
         }
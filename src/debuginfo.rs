@@ -0,0 +1,106 @@
+use std::collections::{HashMap, BTreeMap};
+use std::io::{Write, BufRead};
+use std::io;
+use std::fmt;
+
+const MAGIC:&'static str = "COMPSIM-DBG 1";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceLocation {
+    pub file:String,
+    pub line:usize,
+}
+
+impl fmt::Display for SourceLocation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.file, self.line)
+    }
+}
+
+//maps addresses of an assembled image back to the source line and the label they belong to
+#[derive(Debug, Clone, PartialEq)]
+pub struct DebugInfo {
+    lines:BTreeMap<u64, SourceLocation>,
+    symbols:BTreeMap<u64, String>,
+}
+
+impl DebugInfo {
+
+    pub fn new() -> DebugInfo {
+        DebugInfo{ lines:BTreeMap::new(), symbols:BTreeMap::new() }
+    }
+
+    pub fn add_line(&mut self, addr:u64, file:&str, line:usize) {
+        self.lines.insert(addr, SourceLocation{ file:file.to_string(), line:line });
+    }
+
+    //several labels at the same address: the alphabetically first one wins, like in the disassembler
+    pub fn add_symbol(&mut self, addr:u64, name:&str) {
+        let replace = self.symbols.get(&addr).map(|existing| name < &**existing).unwrap_or(true);
+        if replace {
+            self.symbols.insert(addr, name.to_string());
+        }
+    }
+
+    pub fn symbols(&self) -> HashMap<String, u64> {
+        self.symbols.iter().map(|(&addr, name)| (name.clone(), addr)).collect()
+    }
+
+    pub fn location(&self, addr:u64) -> Option<&SourceLocation> {
+        self.lines.get(&addr)
+    }
+
+    //the closest label at or below addr and how far addr is behind it
+    pub fn symbol(&self, addr:u64) -> Option<(&str, u64)> {
+        self.symbols.range(..=addr).next_back().map(|(&start, name)| (&**name, addr - start))
+    }
+
+    //"loop.asm:42 (in sum_array)", falls back to the bare address for words without debug info
+    pub fn describe(&self, addr:u64) -> String {
+        let location = match self.location(addr) {
+            Some(location) => location.to_string(),
+            None => format!("{:#x}", addr),
+        };
+        match self.symbol(addr) {
+            Some((name, _)) => format!("{} (in {})", location, name),
+            None => location,
+        }
+    }
+
+    //  LINE <addr> <line> <file>
+    //  SYMBOL <addr> <name>
+    pub fn write_to<W: Write>(&self, out:&mut W) -> io::Result<()> {
+        try!(writeln!(out, "{}", MAGIC));
+        for (addr, location) in self.lines.iter() {
+            try!(writeln!(out, "LINE {:#x} {} {}", addr, location.line, location.file));
+        }
+        for (addr, name) in self.symbols.iter() {
+            try!(writeln!(out, "SYMBOL {:#x} {}", addr, name));
+        }
+        Ok(())
+    }
+
+    pub fn read_from<R: BufRead>(input:R) -> Result<DebugInfo, String> {
+        let mut info = DebugInfo::new();
+        let mut lines = input.lines().enumerate();
+        match lines.next() {
+            Some((_, Ok(ref line))) if line.trim() == MAGIC => {},
+            _ => return Err("not a compsim debug info file".to_string()),
+        }
+        for (i, line) in lines {
+            let line = try!(line.map_err(|e| e.to_string()));
+            let fields:Vec<&str> = line.trim().splitn(4, ' ').collect();
+            let addr = fields.get(1).and_then(|addr| u64::from_str_radix(addr.trim_left_matches("0x"), 16).ok());
+            match (fields[0], addr, fields.len()) {
+                ("", _, _) => {},
+                ("LINE", Some(addr), 4) => {
+                    let line_no = try!(fields[2].parse().map_err(|_| format!("line {}: invalid line number", i + 1)));
+                    info.add_line(addr, fields[3], line_no);
+                },
+                ("SYMBOL", Some(addr), 3) => info.add_symbol(addr, fields[2]),
+                _ => return Err(format!("line {}: invalid record", i + 1)),
+            }
+        }
+        Ok(info)
+    }
+}
@@ -0,0 +1,188 @@
+use std::error::Error;
+use std::fmt;
+use expr::MAX_ADDR;
+
+//a memory mapped device sees word offsets relative to the start of its range
+pub trait Device: Send {
+    fn read(&mut self, offset:u64) -> u64;
+    fn write(&mut self, offset:u64, word:u64);
+
+    //called once for every instruction a core executes
+    fn tick(&mut self) {}
+
+    //devices without an interrupt line never raise it. The cores do not take interrupts, there is
+    //no way to enter or leave a handler in the ISA, so the line can only be inspected from outside.
+    fn irq(&self) -> bool {
+        false
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mapping {
+    pub name:String,
+    pub start:u64,
+    pub len:u64,
+}
+
+impl Mapping {
+    pub fn end(&self) -> u64 {
+        self.start + self.len
+    }
+
+    pub fn contains(&self, addr:u64) -> bool {
+        self.start <= addr && addr < self.end()
+    }
+}
+
+impl fmt::Display for Mapping {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} [{:#x}, {:#x})", self.name, self.start, self.end())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MapError {
+    Empty(String),
+    OutOfRange(Mapping),
+    Overlap(Mapping, Mapping),
+}
+
+impl fmt::Display for MapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MapError::Empty(ref name)           => write!(f, "Empty: {} maps no words", name),
+            MapError::OutOfRange(ref m)         => write!(f, "OutOfRange: {} does not fit into the address space", m),
+            MapError::Overlap(ref a, ref b)     => write!(f, "Overlap: {} overlaps {}", a, b),
+        }
+    }
+}
+
+impl Error for MapError {
+    fn description(&self) -> &str {
+        match *self {
+            MapError::Empty(_)          => "empty mapping",
+            MapError::OutOfRange(_)     => "mapping out of range",
+            MapError::Overlap(_, _)     => "overlapping mappings",
+        }
+    }
+
+    fn cause(&self) -> Option<&Error> {
+        None
+    }
+}
+
+//decides which device answers an address. Devices shadow the ram below them, addresses that no
+//device maps are left to the ram.
+pub struct Decoder {
+    devices:Vec<(Mapping, Box<Device>)>, //sorted by start
+}
+
+impl Decoder {
+
+    pub fn new() -> Decoder {
+        Decoder { devices:Vec::new() }
+    }
+
+    pub fn map(&mut self, name:&str, start:u64, len:u64, device:Box<Device>) -> Result<(), MapError> {
+        let mapping = Mapping { name:name.to_string(), start:start, len:len };
+        if len == 0 {
+            return Err(MapError::Empty(mapping.name));
+        }
+        if start > MAX_ADDR || len > MAX_ADDR + 1 - start {
+            return Err(MapError::OutOfRange(mapping));
+        }
+        if let Some(&(ref other, _)) = self.devices.iter().find(|&&(ref other, _)| other.start < mapping.end() && mapping.start < other.end()) {
+            return Err(MapError::Overlap(mapping, other.clone()));
+        }
+        let pos = self.devices.iter().position(|&(ref other, _)| other.start > start).unwrap_or(self.devices.len());
+        self.devices.insert(pos, (mapping, device));
+        Ok(())
+    }
+
+    pub fn mappings(&self) -> Vec<&Mapping> {
+        self.devices.iter().map(|&(ref mapping, _)| mapping).collect()
+    }
+
+    pub fn is_mapped(&self, addr:u64) -> bool {
+        self.find(addr).is_some()
+    }
+
+    //None if no device maps addr
+    pub fn read(&mut self, addr:u64) -> Option<u64> {
+        self.find(addr).map(|i| {
+            let (ref mapping, ref mut device) = self.devices[i];
+            device.read(addr - mapping.start)
+        })
+    }
+
+    //false if no device maps addr
+    pub fn write(&mut self, addr:u64, word:u64) -> bool {
+        match self.find(addr) {
+            Some(i) => {
+                let (ref mapping, ref mut device) = self.devices[i];
+                device.write(addr - mapping.start, word);
+                true
+            },
+            None => false,
+        }
+    }
+
+    pub fn tick(&mut self) {
+        for &mut (_, ref mut device) in self.devices.iter_mut() {
+            device.tick();
+        }
+    }
+
+    //the names of the devices that raise their interrupt line
+    pub fn pending_irqs(&self) -> Vec<&str> {
+        self.devices.iter().filter(|&&(_, ref device)| device.irq()).map(|&(ref mapping, _)| &mapping.name[..]).collect()
+    }
+
+    fn find(&self, addr:u64) -> Option<usize> {
+        match self.devices.binary_search_by(|&(ref mapping, _)| mapping.start.cmp(&addr)) {
+            Ok(i) => Some(i),
+            Err(0) => None,
+            Err(i) => if self.devices[i - 1].0.contains(addr) { Some(i - 1) } else { None },
+        }
+    }
+}
+
+//counts executed instructions. Offset 0 is the count, writing it sets it; offset 1 is the compare value,
+//the interrupt line is raised while the count has reached a compare value other than 0.
+#[derive(Debug, Clone, Default)]
+pub struct Timer {
+    count:u64,
+    compare:u64,
+}
+
+impl Timer {
+    pub fn new() -> Timer {
+        Timer::default()
+    }
+}
+
+impl Device for Timer {
+    fn read(&mut self, offset:u64) -> u64 {
+        match offset {
+            0 => self.count,
+            1 => self.compare,
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, offset:u64, word:u64) {
+        match offset {
+            0 => self.count = word,
+            1 => self.compare = word,
+            _ => {},
+        }
+    }
+
+    fn tick(&mut self) {
+        self.count = self.count.wrapping_add(1);
+    }
+
+    fn irq(&self) -> bool {
+        self.compare != 0 && self.count >= self.compare
+    }
+}
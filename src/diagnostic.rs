@@ -0,0 +1,119 @@
+use std::fmt;
+use parser::{ParseError, is_label_char, split_label};
+use preprocessor::SourceLine;
+
+//an assembler error together with the place in the source it belongs to
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub error:ParseError,
+    pub file:String,
+    pub line:usize,
+    pub column:usize, //1-based, in characters
+    pub width:usize,
+    pub source:String,
+    pub notes:Vec<String>,
+}
+
+impl Diagnostic {
+
+    pub fn new(location:&SourceLine, error:ParseError) -> Diagnostic {
+        let (column, width) = locate(&location.raw, innermost(&error));
+        let notes = location.expanded_from.iter().rev()
+            .map(|call| format!("in expansion of macro {} called at {}:{}", call.name, call.file, call.call_line))
+            .collect();
+        Diagnostic {
+            error:location.wrap_error(error),
+            file:location.file.clone(),
+            line:location.line,
+            column:column,
+            width:width,
+            source:location.raw.clone(),
+            notes:notes,
+        }
+    }
+
+    //for errors that do not belong to any line, like a missing input file
+    pub fn without_line(file:&str, error:ParseError) -> Diagnostic {
+        Diagnostic {
+            error:error,
+            file:file.to_string(),
+            line:0,
+            column:0,
+            width:0,
+            source:String::new(),
+            notes:Vec::new(),
+        }
+    }
+}
+
+fn innermost(error:&ParseError) -> &ParseError {
+    match *error {
+        ParseError::InMacro(_, _, _, ref err) => innermost(err),
+        ref err => err,
+    }
+}
+
+//finds the offending token in the line, or falls back to the first non-blank character. Labels and
+//registers are looked for among the operands first, so that "a" is not found inside "EAX".
+fn locate(raw:&str, error:&ParseError) -> (usize, usize) {
+    let from = match *error {
+        ParseError::UndefinedLabel(_) | ParseError::UnkownReg(_) => operands(raw),
+        _ => 0,
+    };
+    let found = error.token()
+        .filter(|t| !t.is_empty())
+        .and_then(|t| find_word(raw, t, from).or(find_word(raw, t, 0)).map(|pos| (pos, t.chars().count())));
+    let (pos, width) = match found {
+        Some(res) => res,
+        None => {
+            let pos = raw.len() - raw.trim_left().len();
+            (pos, raw.trim().chars().count())
+        },
+    };
+    (raw[..pos].chars().count() + 1, if width == 0 { 1 } else { width })
+}
+
+//the byte offset of the first occurrence of word at or after from that is not part of a longer
+//label, ignoring case
+fn find_word(raw:&str, word:&str, from:usize) -> Option<usize> {
+    let lower = raw.to_ascii_lowercase();
+    let word = word.to_ascii_lowercase();
+    let starts_label = word.chars().next().map(is_label_char).unwrap_or(false);
+    let ends_label = word.chars().next_back().map(is_label_char).unwrap_or(false);
+    lower[from..].match_indices(&*word).map(|(pos, _)| from + pos).find(|&pos| {
+        let before = lower[..pos].chars().next_back().map(is_label_char).unwrap_or(false);
+        let after = lower[pos + word.len()..].chars().next().map(is_label_char).unwrap_or(false);
+        !(starts_label && before) && !(ends_label && after)
+    })
+}
+
+//the byte offset behind the labels and the mnemonic of a line
+fn operands(raw:&str) -> usize {
+    let mut rest = raw.trim_left();
+    while let Some((_, tail)) = split_label(rest) {
+        rest = tail;
+    }
+    let rest = rest.trim_left();
+    let mnemonic = rest.find(char::is_whitespace).unwrap_or(rest.len());
+    rest.as_ptr() as usize - raw.as_ptr() as usize + mnemonic
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.line == 0 {
+            return write!(f, "{}: error: {}", self.file, innermost(&self.error));
+        }
+        try!(writeln!(f, "{}:{}:{}: error: {}", self.file, self.line, self.column, innermost(&self.error)));
+
+        let gutter = self.line.to_string().len();
+        //keep tabs so that the caret lines up with the excerpt
+        let padding:String = self.source.chars().take(self.column - 1).map(|c| if c == '\t' { '\t' } else { ' ' }).collect();
+        try!(writeln!(f, "{:w$} |", "", w = gutter));
+        try!(writeln!(f, "{} | {}", self.line, self.source));
+        try!(write!(f, "{:w$} | {}{}", "", padding, "^".repeat(self.width), w = gutter));
+        for note in self.notes.iter() {
+            try!(write!(f, "\n{:w$} = note: {}", "", note, w = gutter));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,90 @@
+use std::collections::{HashMap, BTreeMap};
+use std::ops::Range;
+use parser::MemoryImage;
+use utils::Instruction;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DisassembledLine {
+    pub addr:u64,
+    pub word:u64,
+    pub label:Option<String>,
+    pub text:String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Disassembler {
+    names:BTreeMap<u64, String>,
+}
+
+impl Disassembler {
+
+    pub fn new() -> Disassembler {
+        Disassembler{ names:BTreeMap::new() }
+    }
+
+    //if several symbols share an address the alphabetically first one is used
+    pub fn with_symbols(symbols:&HashMap<String, u64>) -> Disassembler {
+        let mut names:BTreeMap<u64, String> = BTreeMap::new();
+        for (name, &addr) in symbols.iter() {
+            let replace = match names.get(&addr) {
+                Some(existing) => name < existing,
+                None => true,
+            };
+            if replace {
+                names.insert(addr, name.clone());
+            }
+        }
+        Disassembler{ names:names }
+    }
+
+    pub fn symbol_at(&self, addr:u64) -> Option<&str> {
+        self.names.get(&addr).map(|name| &**name)
+    }
+
+    pub fn render(&self, instr:Instruction) -> String {
+        instr.render(&|addr| match self.names.get(&addr) {
+            Some(name) => name.clone(),
+            None => addr.to_string(),
+        })
+    }
+
+    //words that are not valid instructions, like data or strings, come out as .word
+    pub fn disassemble(&self, image:&MemoryImage, range:Range<u64>) -> Vec<DisassembledLine> {
+        image.range(range).map(|(&addr, &word)| {
+            DisassembledLine {
+                addr:addr,
+                word:word,
+                label:self.names.get(&addr).cloned(),
+                text:self.render(Instruction(word)),
+            }
+        }).collect()
+    }
+
+    //source that assembles back into exactly the words of the range
+    pub fn to_source(&self, image:&MemoryImage, range:Range<u64>) -> String {
+        let lines = self.disassemble(image, range);
+        let mut source = String::new();
+        //symbols that do not label a word of the range still have to be known when reassembling
+        for (&addr, name) in self.names.iter() {
+            if !lines.iter().any(|line| line.addr == addr) {
+                source = source + &format!(".equ {}, {}\n", name, addr);
+            }
+        }
+        let mut next_addr = None;
+        for line in lines {
+            if next_addr != Some(line.addr) {
+                source = source + &format!(".org {}\n", line.addr);
+            }
+            if let Some(label) = line.label {
+                source = source + &label + ":\n";
+            }
+            source = source + &line.text + "\n";
+            next_addr = Some(line.addr + 1);
+        }
+        source
+    }
+}
+
+pub fn disassemble(image:&MemoryImage, range:Range<u64>) -> Vec<DisassembledLine> {
+    Disassembler::new().disassemble(image, range)
+}
@@ -0,0 +1,290 @@
+use std::collections::HashMap;
+use std::iter::Peekable;
+use std::str::Chars;
+use parser::ParseError;
+
+pub const MAX_ADDR:u64 = 0x00_0f_ff_ff_ff_ff_ff_ffu64; //width of the address field of an instruction
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Num(i64),
+    Ident(String),
+    Op(&'static str),
+    LParen,
+    RParen,
+}
+
+//evaluates a constant expression like "table+8*3" or "(buf_end-1) & ~0xf"
+pub fn eval(_s:&str, symbols:&HashMap<String, u64>) -> Result<i64, ParseError> {
+    eval_with(_s, &|name| symbols.get(name).map(|&v| v as i64))
+}
+
+//like eval, but symbols are looked up through a function
+pub fn eval_with(_s:&str, lookup:&Fn(&str) -> Option<i64>) -> Result<i64, ParseError> {
+    let tokens = try!(tokenize(_s));
+    let mut parser = ExprParser{ tokens:tokens, pos:0, lookup:lookup, source:_s };
+    let value = try!(parser.or());
+    if parser.pos != parser.tokens.len() {
+        return Err(parser.error("unexpected token"));
+    }
+    Ok(value)
+}
+
+//all symbols an expression refers to
+pub fn symbols(_s:&str) -> Result<Vec<String>, ParseError> {
+    let mut names = Vec::new();
+    for token in try!(tokenize(_s)) {
+        if let Token::Ident(name) = token {
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        }
+    }
+    Ok(names)
+}
+
+pub fn check_addr(value:i64) -> Result<u64, ParseError> {
+    if value < 0 || value as u64 > MAX_ADDR {
+        return Err(ParseError::ValueOutOfRange(value));
+    }
+    Ok(value as u64)
+}
+
+//evaluates an expression that has to fit into the address field of an instruction
+pub fn eval_addr(_s:&str, symbols:&HashMap<String, u64>) -> Result<u64, ParseError> {
+    check_addr(try!(eval(_s, symbols)))
+}
+
+fn tokenize(_s:&str) -> Result<Vec<Token>, ParseError> {
+    let mut tokens = Vec::new();
+    let mut chars = _s.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c.is_digit(10) {
+            tokens.push(Token::Num(try!(read_number(&mut chars))));
+        } else if c.is_alphabetic() || c == '_' || c == '.' {
+            let mut ident = String::new();
+            while let Some(&c) = chars.peek() {
+                if !(c.is_alphanumeric() || c == '_' || c == '.') {
+                    break;
+                }
+                ident.push(c);
+                chars.next();
+            }
+            tokens.push(Token::Ident(ident));
+        } else if c == '\'' {
+            tokens.push(Token::Num(try!(read_char(&mut chars, _s))));
+        } else {
+            chars.next();
+            let token = match c {
+                '(' => Token::LParen,
+                ')' => Token::RParen,
+                '+' => Token::Op("+"),
+                '-' => Token::Op("-"),
+                '*' => Token::Op("*"),
+                '/' => Token::Op("/"),
+                '%' => Token::Op("%"),
+                '&' => Token::Op("&"),
+                '|' => Token::Op("|"),
+                '^' => Token::Op("^"),
+                '~' => Token::Op("~"),
+                '<' if chars.peek() == Some(&'<') => { chars.next(); Token::Op("<<") },
+                '>' if chars.peek() == Some(&'>') => { chars.next(); Token::Op(">>") },
+                '<' if chars.peek() == Some(&'=') => { chars.next(); Token::Op("<=") },
+                '>' if chars.peek() == Some(&'=') => { chars.next(); Token::Op(">=") },
+                '=' if chars.peek() == Some(&'=') => { chars.next(); Token::Op("==") },
+                '!' if chars.peek() == Some(&'=') => { chars.next(); Token::Op("!=") },
+                '<' => Token::Op("<"),
+                '>' => Token::Op(">"),
+                _   => return Err(ParseError::InvalidExpression(format!("unexpected character '{}' in {}", c, _s))),
+            };
+            tokens.push(token);
+        }
+    }
+    Ok(tokens)
+}
+
+fn read_number(chars:&mut Peekable<Chars>) -> Result<i64, ParseError> {
+    let mut digits = String::new();
+    while let Some(&c) = chars.peek() {
+        if !(c.is_alphanumeric() || c == '_') {
+            break;
+        }
+        if c != '_' {
+            digits.push(c);
+        }
+        chars.next();
+    }
+    let lower = digits.to_lowercase();
+    let (radix, body) = if lower.starts_with("0x") {
+        (16, &digits[2..])
+    } else if lower.starts_with("0b") {
+        (2, &digits[2..])
+    } else if lower.starts_with("0o") {
+        (8, &digits[2..])
+    } else {
+        (10, &digits[..])
+    };
+    let value = try!(u64::from_str_radix(body, radix).map_err(ParseError::InvalidMemAddress));
+    Ok(value as i64)
+}
+
+fn read_char(chars:&mut Peekable<Chars>, _s:&str) -> Result<i64, ParseError> {
+    chars.next();
+    let c = match chars.next() {
+        Some('\\') => match chars.next() {
+            Some('n')   => '\n',
+            Some('t')   => '\t',
+            Some('0')   => '\0',
+            Some('\\')  => '\\',
+            Some('\'')  => '\'',
+            _           => return Err(ParseError::InvalidExpression(format!("invalid escape sequence in {}", _s))),
+        },
+        Some(c) => c,
+        None => return Err(ParseError::InvalidExpression(format!("unterminated character literal in {}", _s))),
+    };
+    if chars.next() != Some('\'') {
+        return Err(ParseError::InvalidExpression(format!("unterminated character literal in {}", _s)));
+    }
+    Ok(c as i64)
+}
+
+struct ExprParser<'a> {
+    tokens:Vec<Token>,
+    pos:usize,
+    lookup:&'a Fn(&str) -> Option<i64>,
+    source:&'a str,
+}
+
+impl<'a> ExprParser<'a> {
+
+    fn error(&self, msg:&str) -> ParseError {
+        ParseError::InvalidExpression(format!("{} in {}", msg, self.source))
+    }
+
+    fn eat(&mut self, ops:&[&'static str]) -> Option<&'static str> {
+        if let Some(&Token::Op(op)) = self.tokens.get(self.pos) {
+            if ops.contains(&op) {
+                self.pos += 1;
+                return Some(op);
+            }
+        }
+        None
+    }
+
+    fn or(&mut self) -> Result<i64, ParseError> {
+        let mut lhs = try!(self.xor());
+        while let Some(_) = self.eat(&["|"]) {
+            lhs |= try!(self.xor());
+        }
+        Ok(lhs)
+    }
+
+    fn xor(&mut self) -> Result<i64, ParseError> {
+        let mut lhs = try!(self.and());
+        while let Some(_) = self.eat(&["^"]) {
+            lhs ^= try!(self.and());
+        }
+        Ok(lhs)
+    }
+
+    fn and(&mut self) -> Result<i64, ParseError> {
+        let mut lhs = try!(self.equality());
+        while let Some(_) = self.eat(&["&"]) {
+            lhs &= try!(self.equality());
+        }
+        Ok(lhs)
+    }
+
+    //comparisons evaluate to 1 or 0 so that .if can use them
+    fn equality(&mut self) -> Result<i64, ParseError> {
+        let mut lhs = try!(self.relation());
+        while let Some(op) = self.eat(&["==", "!="]) {
+            let rhs = try!(self.relation());
+            lhs = ((lhs == rhs) == (op == "==")) as i64;
+        }
+        Ok(lhs)
+    }
+
+    fn relation(&mut self) -> Result<i64, ParseError> {
+        let mut lhs = try!(self.shift());
+        while let Some(op) = self.eat(&["<", "<=", ">", ">="]) {
+            let rhs = try!(self.shift());
+            lhs = match op {
+                "<"     => lhs < rhs,
+                "<="    => lhs <= rhs,
+                ">"     => lhs > rhs,
+                _       => lhs >= rhs,
+            } as i64;
+        }
+        Ok(lhs)
+    }
+
+    fn shift(&mut self) -> Result<i64, ParseError> {
+        let mut lhs = try!(self.sum());
+        while let Some(op) = self.eat(&["<<", ">>"]) {
+            let rhs = try!(self.sum());
+            if rhs < 0 || rhs >= 64 {
+                return Err(self.error("shift amount out of range"));
+            }
+            lhs = if op == "<<" { lhs << rhs } else { lhs >> rhs };
+        }
+        Ok(lhs)
+    }
+
+    fn sum(&mut self) -> Result<i64, ParseError> {
+        let mut lhs = try!(self.product());
+        while let Some(op) = self.eat(&["+", "-"]) {
+            let rhs = try!(self.product());
+            let res = if op == "+" { lhs.checked_add(rhs) } else { lhs.checked_sub(rhs) };
+            lhs = try!(res.ok_or(self.error("arithmetic overflow")));
+        }
+        Ok(lhs)
+    }
+
+    fn product(&mut self) -> Result<i64, ParseError> {
+        let mut lhs = try!(self.unary());
+        while let Some(op) = self.eat(&["*", "/", "%"]) {
+            let rhs = try!(self.unary());
+            if op != "*" && rhs == 0 {
+                return Err(self.error("division by zero"));
+            }
+            let res = match op {
+                "*" => lhs.checked_mul(rhs),
+                "/" => lhs.checked_div(rhs),
+                _   => lhs.checked_rem(rhs),
+            };
+            lhs = try!(res.ok_or(self.error("arithmetic overflow")));
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<i64, ParseError> {
+        match self.eat(&["-", "~", "+"]) {
+            Some("-")   => Ok(try!(self.unary()).wrapping_neg()),
+            Some("~")   => Ok(!try!(self.unary())),
+            Some(_)     => self.unary(),
+            None        => self.primary(),
+        }
+    }
+
+    fn primary(&mut self) -> Result<i64, ParseError> {
+        let token = try!(self.tokens.get(self.pos).cloned().ok_or(self.error("unexpected end of expression")));
+        self.pos += 1;
+        match token {
+            Token::Num(n)       => Ok(n),
+            Token::Ident(name)  => (self.lookup)(&name).ok_or(ParseError::UndefinedLabel(name)),
+            Token::LParen       => {
+                let value = try!(self.or());
+                if self.tokens.get(self.pos) != Some(&Token::RParen) {
+                    return Err(self.error("missing ')'"));
+                }
+                self.pos += 1;
+                Ok(value)
+            },
+            _                   => Err(self.error("unexpected token")),
+        }
+    }
+}
@@ -0,0 +1,162 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use parser::{Parser, ParseError, split_label};
+use preprocessor::split_directive;
+use utils::{Reg, strip_comment, split_operants, operant_count};
+use diagnostic::Diagnostic;
+
+//canonical layout of assembly source: labels in the first column, statements indented, operants
+//and trailing comments starting at fixed columns. Only whitespace, the separators between
+//operants and the casing of mnemonics, registers and directives change, so the formatted source
+//assembles to the same program.
+#[derive(Debug, Clone)]
+pub struct Formatter {
+    indent:usize,
+    operant_column:usize,
+    comment_column:usize,
+}
+
+impl Formatter {
+
+    pub fn new() -> Formatter {
+        Formatter { indent:4, operant_column:9, comment_column:40 }
+    }
+
+    pub fn set_indent(&mut self, _indent:usize) -> &mut Formatter {
+        self.indent = _indent;
+        self
+    }
+
+    pub fn set_operant_column(&mut self, _column:usize) -> &mut Formatter {
+        self.operant_column = _column;
+        self
+    }
+
+    pub fn set_comment_column(&mut self, _column:usize) -> &mut Formatter {
+        self.comment_column = _column;
+        self
+    }
+
+    //sources that do not assemble are not touched, a misspelled mnemonic would just be moved around.
+    //Objects may refer to symbols of other files, so the source is assembled as one.
+    pub fn format_source(&self, source:&str) -> Result<String, Vec<Diagnostic>> {
+        try!(Parser::relocatable().assemble(source));
+        Ok(self.format(source))
+    }
+
+    //includes are found relative to the file, like when it is assembled
+    pub fn format_file<P: AsRef<Path>>(&self, path:P) -> Result<String, Vec<Diagnostic>> {
+        let name = path.as_ref().display().to_string();
+        let mut source = String::new();
+        if let Err(err) = File::open(path.as_ref()).and_then(|mut f| f.read_to_string(&mut source)) {
+            return Err(vec![Diagnostic::without_line(&name, ParseError::Io(err.to_string()))]);
+        }
+        try!(Parser::relocatable().assemble_file(path.as_ref()));
+        Ok(self.format(&source))
+    }
+
+    //runs of blank lines shrink to one, blank lines at the start and the end are dropped
+    pub fn format(&self, source:&str) -> String {
+        let mut lines:Vec<String> = Vec::new();
+        for raw in source.lines() {
+            let line = self.line(raw);
+            if !line.is_empty() || lines.last().map(|last| !last.is_empty()).unwrap_or(false) {
+                lines.push(line);
+            }
+        }
+        while lines.last().map(|last| last.is_empty()).unwrap_or(false) {
+            lines.pop();
+        }
+        lines.into_iter().map(|line| line + "\n").collect()
+    }
+
+    fn line(&self, raw:&str) -> String {
+        let code = strip_comment(raw);
+        let comment = raw[code.len()..].trim_right();
+        let mut rest = code.trim();
+        let mut labels = Vec::new();
+        while let Some((label, tail)) = split_label(rest) {
+            labels.push(format!("{}:", label));
+            rest = tail;
+        }
+
+        let mut line = labels.join(" ");
+        if !rest.is_empty() {
+            //labels that do not fit in front of the statement get a line of their own
+            if !line.is_empty() && line.chars().count() >= self.indent {
+                line.push('\n');
+            }
+            pad(&mut line, self.indent);
+            line.push_str(&self.statement(rest));
+        }
+        if !comment.is_empty() {
+            if !line.is_empty() {
+                pad(&mut line, self.comment_column);
+            } else if raw.starts_with(char::is_whitespace) {
+                pad(&mut line, self.indent);
+            }
+            line.push_str(comment);
+        }
+        line
+    }
+
+    fn statement(&self, text:&str) -> String {
+        let (name, args) = split_directive(text);
+        if name.starts_with('.') {
+            let name = name.to_lowercase();
+            //the other directives take expressions, strings or file names that are kept as written
+            let args = match &*name {
+                ".word" | ".equ" | ".global"    => split_operants(args).join(", "),
+                _                               => args.to_string(),
+            };
+            return self.with_operants(&name, &args);
+        }
+
+        let mnemonic = name.to_uppercase();
+        let operants = split_operants(args);
+        match operant_count(&mnemonic) {
+            Some(n) if n == operants.len() => {
+                let operants:Vec<String> = operants.iter().enumerate().map(|(i, operant)| {
+                    if is_reg_operant(&mnemonic, i) && operant.parse::<Reg>().is_ok() {
+                        operant.to_uppercase()
+                    } else {
+                        operant.to_string()
+                    }
+                }).collect();
+                self.with_operants(&mnemonic, &operants.join(", "))
+            },
+            //macro calls and anything else that is not an instruction are left as they are
+            _ => self.with_operants(name, args),
+        }
+    }
+
+    fn with_operants(&self, name:&str, args:&str) -> String {
+        let mut res = name.to_string();
+        if !args.is_empty() {
+            pad(&mut res, self.operant_column.saturating_sub(self.indent));
+            res.push_str(args);
+        }
+        res
+    }
+}
+
+//labels in address operants may be called like registers, only register operants are upper cased
+fn is_reg_operant(mnemonic:&str, i:usize) -> bool {
+    match (mnemonic, i) {
+        ("ADD", _) | ("MUL", _)     => true,
+        ("LD", 0) | ("SAV", 1)      => true,
+        ("PUSH", 0) | ("POP", 0)    => true,
+        _                           => false,
+    }
+}
+
+//fills the last line up to the column, or separates with a single space if it is already past it
+fn pad(line:&mut String, column:usize) {
+    let width = line.rsplit('\n').next().unwrap_or("").chars().count();
+    if width < column {
+        line.extend(::std::iter::repeat(' ').take(column - width));
+    } else if width > 0 {
+        line.push(' ');
+    }
+}
@@ -0,0 +1,322 @@
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::{Read, Write, BufRead, BufReader};
+use std::path::Path;
+use parser::MemoryImage;
+
+const NATIVE_MAGIC:&'static [u8; 8] = b"CSIMIMG\0";
+const NATIVE_VERSION:u64 = 1;
+const HEX_RECORD_BYTES:usize = 16;
+
+//a run of consecutive words and the address they are loaded to
+#[derive(Debug, Clone, PartialEq)]
+pub struct Segment {
+    pub addr:u64,
+    pub words:Vec<u64>,
+}
+
+//what a program looks like once it has been assembled and linked: its segments and where to start it
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoadImage {
+    pub entry:u64,
+    pub segments:Vec<Segment>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImageFormat {
+    Raw,        //little-endian words starting at address 0, gaps are filled with zeros
+    IntelHex,   //every word is 8 little-endian bytes at byte address 8 * addr
+    Native,     //magic, version, entry and the segments with their load addresses
+}
+
+impl ImageFormat {
+
+    //.bin, .hex/.ihex and .img
+    pub fn from_path<P: AsRef<Path>>(path:P) -> Option<ImageFormat> {
+        match path.as_ref().extension().and_then(|ext| ext.to_str()).map(|ext| ext.to_lowercase()) {
+            Some(ref ext) if ext == "bin"                   => Some(ImageFormat::Raw),
+            Some(ref ext) if ext == "hex" || ext == "ihex"  => Some(ImageFormat::IntelHex),
+            Some(ref ext) if ext == "img"                   => Some(ImageFormat::Native),
+            _                                               => None,
+        }
+    }
+
+    //raw images have no header, so everything that is not recognized is one. Intel HEX needs a
+    //whole first record, a raw image may well start with the byte ':'.
+    pub fn detect(data:&[u8]) -> ImageFormat {
+        let first_line = data.split(|&b| b == b'\r' || b == b'\n').next().unwrap_or(&[]);
+        if data.starts_with(NATIVE_MAGIC) {
+            ImageFormat::Native
+        } else if first_line.len() >= 11 && first_line.len() % 2 == 1 && first_line[0] == b':'
+                && first_line[1..].iter().all(|b| (*b as char).is_digit(16)) {
+            ImageFormat::IntelHex
+        } else {
+            ImageFormat::Raw
+        }
+    }
+}
+
+impl LoadImage {
+
+    //consecutive addresses are merged into one segment
+    pub fn from_memory_image(image:&MemoryImage, entry:u64) -> LoadImage {
+        let mut segments:Vec<Segment> = Vec::new();
+        for (&addr, &word) in image.iter() {
+            match segments.last_mut() {
+                Some(ref mut seg) if seg.addr + seg.words.len() as u64 == addr => { seg.words.push(word); continue },
+                _ => {},
+            }
+            segments.push(Segment{ addr:addr, words:vec![word] });
+        }
+        LoadImage{ entry:entry, segments:segments }
+    }
+
+    pub fn to_memory_image(&self) -> MemoryImage {
+        let mut image = MemoryImage::new();
+        for seg in self.segments.iter() {
+            for (i, &word) in seg.words.iter().enumerate() {
+                image.insert(seg.addr + i as u64, word);
+            }
+        }
+        image
+    }
+
+    pub fn write_to<W: Write>(&self, out:&mut W, format:ImageFormat) -> Result<(), ImageError> {
+        match format {
+            ImageFormat::Raw        => self.write_raw(out),
+            ImageFormat::IntelHex   => self.write_intel_hex(out),
+            ImageFormat::Native     => self.write_native(out),
+        }
+    }
+
+    pub fn read_from<R: Read>(input:&mut R, format:ImageFormat) -> Result<LoadImage, ImageError> {
+        let mut data = Vec::new();
+        try!(input.read_to_end(&mut data));
+        match format {
+            ImageFormat::Raw        => LoadImage::read_raw(&data),
+            ImageFormat::IntelHex   => LoadImage::read_intel_hex(&data),
+            ImageFormat::Native     => LoadImage::read_native(&data),
+        }
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path:P) -> Result<(), ImageError> {
+        let format = try!(ImageFormat::from_path(&path).ok_or(ImageError::UnknownFormat(path.as_ref().display().to_string())));
+        let mut file = try!(File::create(path));
+        self.write_to(&mut file, format)
+    }
+
+    //the format is recognized by the content, not by the name
+    pub fn load<P: AsRef<Path>>(path:P) -> Result<LoadImage, ImageError> {
+        let mut data = Vec::new();
+        try!(File::open(path).and_then(|mut f| f.read_to_end(&mut data)));
+        LoadImage::read_from(&mut &data[..], ImageFormat::detect(&data))
+    }
+
+    fn write_raw<W: Write>(&self, out:&mut W) -> Result<(), ImageError> {
+        let image = self.to_memory_image();
+        let end = image.keys().next_back().map(|&addr| addr + 1).unwrap_or(0);
+        for addr in 0..end {
+            try!(out.write_all(&to_bytes(image.get(&addr).cloned().unwrap_or(0))));
+        }
+        Ok(())
+    }
+
+    fn read_raw(data:&[u8]) -> Result<LoadImage, ImageError> {
+        if data.len() % 8 != 0 {
+            return Err(ImageError::Malformed(format!("{} bytes are no whole number of words", data.len())));
+        }
+        let words = data.chunks(8).map(from_bytes).collect();
+        Ok(LoadImage{ entry:0, segments:vec![Segment{ addr:0, words:words }] })
+    }
+
+    //data records of 16 bytes, an extended linear address record whenever the upper 16 bits of the
+    //byte address change and a start linear address record for the entry
+    fn write_intel_hex<W: Write>(&self, out:&mut W) -> Result<(), ImageError> {
+        let mut upper = 0;
+        for seg in self.segments.iter().filter(|seg| !seg.words.is_empty()) {
+            let bytes:Vec<u8> = seg.words.iter().flat_map(|&word| to_bytes(word).to_vec().into_iter()).collect();
+            let start = try!(byte_addr(seg.addr));
+            try!(byte_addr(seg.addr + seg.words.len() as u64 - 1));
+            let mut offset = 0;
+            while offset < bytes.len() {
+                let addr = start + offset as u32;
+                //records must not cross a 64k boundary
+                let len = HEX_RECORD_BYTES.min(bytes.len() - offset).min(0x10000 - (addr & 0xffff) as usize);
+                if addr >> 16 != upper {
+                    upper = addr >> 16;
+                    try!(write_hex_record(out, 0, 0x04, &[(upper >> 8) as u8, upper as u8]));
+                }
+                try!(write_hex_record(out, addr as u16, 0x00, &bytes[offset..offset + len]));
+                offset += len;
+            }
+        }
+        let entry = try!(byte_addr(self.entry));
+        try!(write_hex_record(out, 0, 0x05, &[(entry >> 24) as u8, (entry >> 16) as u8, (entry >> 8) as u8, entry as u8]));
+        try!(write_hex_record(out, 0, 0x01, &[]));
+        Ok(())
+    }
+
+    fn read_intel_hex(data:&[u8]) -> Result<LoadImage, ImageError> {
+        let mut bytes:Vec<(u32, u8)> = Vec::new();
+        let mut upper = 0u32;
+        let mut entry = 0u32;
+        for (i, line) in BufReader::new(data).lines().enumerate() {
+            let line = try!(line);
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let malformed = |msg:&str| ImageError::Malformed(format!("line {}: {}", i + 1, msg));
+            if !line.starts_with(':') || line.len() % 2 != 1 || line.len() < 11 {
+                return Err(malformed("not a record"));
+            }
+            let mut record = Vec::new();
+            for pos in (1..line.len()).step_by(2) {
+                record.push(try!(u8::from_str_radix(&line[pos..pos + 2], 16).map_err(|_| malformed("invalid hex digit"))));
+            }
+            let len = record[0] as usize;
+            if record.len() != len + 5 {
+                return Err(malformed("wrong record length"));
+            }
+            if record.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)) != 0 {
+                return Err(ImageError::Checksum(i + 1));
+            }
+            let addr = (record[1] as u32) << 8 | record[2] as u32;
+            let payload = &record[4..4 + len];
+            match record[3] {
+                0x00 => for (j, &b) in payload.iter().enumerate() {
+                    let addr = try!((upper << 16 | addr).checked_add(j as u32).ok_or(malformed("data beyond the 32 bit address space")));
+                    bytes.push((addr, b));
+                },
+                0x01 => break,
+                0x04 if len == 2 => upper = (payload[0] as u32) << 8 | payload[1] as u32,
+                0x05 if len == 4 => entry = payload.iter().fold(0, |acc, &b| acc << 8 | b as u32),
+                _ => return Err(malformed("unsupported record type")),
+            }
+        }
+
+        let mut image = MemoryImage::new();
+        for (addr, byte) in bytes {
+            let word = image.entry(addr as u64 / 8).or_insert(0);
+            *word |= (byte as u64) << (8 * (addr % 8));
+        }
+        Ok(LoadImage::from_memory_image(&image, entry as u64 / 8))
+    }
+
+    fn write_native<W: Write>(&self, out:&mut W) -> Result<(), ImageError> {
+        try!(out.write_all(NATIVE_MAGIC));
+        try!(out.write_all(&to_bytes(NATIVE_VERSION)));
+        try!(out.write_all(&to_bytes(self.entry)));
+        try!(out.write_all(&to_bytes(self.segments.len() as u64)));
+        for seg in self.segments.iter() {
+            try!(out.write_all(&to_bytes(seg.addr)));
+            try!(out.write_all(&to_bytes(seg.words.len() as u64)));
+            for &word in seg.words.iter() {
+                try!(out.write_all(&to_bytes(word)));
+            }
+        }
+        Ok(())
+    }
+
+    fn read_native(data:&[u8]) -> Result<LoadImage, ImageError> {
+        if !data.starts_with(NATIVE_MAGIC) {
+            return Err(ImageError::Malformed("missing magic number".to_string()));
+        }
+        let mut words = data[NATIVE_MAGIC.len()..].chunks(8);
+        let mut next = || words.next().filter(|w| w.len() == 8).map(from_bytes).ok_or(ImageError::Malformed("image ends early".to_string()));
+        let version = try!(next());
+        if version != NATIVE_VERSION {
+            return Err(ImageError::Malformed(format!("unsupported version {}", version)));
+        }
+        let entry = try!(next());
+        let count = try!(next());
+        let mut segments = Vec::new();
+        for _ in 0..count {
+            let addr = try!(next());
+            let len = try!(next());
+            let mut seg = Segment{ addr:addr, words:Vec::new() };
+            for _ in 0..len {
+                seg.words.push(try!(next()));
+            }
+            segments.push(seg);
+        }
+        Ok(LoadImage{ entry:entry, segments:segments })
+    }
+}
+
+fn to_bytes(word:u64) -> [u8; 8] {
+    let mut bytes = [0; 8];
+    for i in 0..8 {
+        bytes[i] = (word >> (8 * i)) as u8;
+    }
+    bytes
+}
+
+fn from_bytes(bytes:&[u8]) -> u64 {
+    bytes.iter().rev().fold(0, |acc, &b| acc << 8 | b as u64)
+}
+
+//Intel HEX only knows 32 bit byte addresses
+fn byte_addr(addr:u64) -> Result<u32, ImageError> {
+    if addr > u32::max_value() as u64 / 8 {
+        return Err(ImageError::AddressOutOfRange(addr));
+    }
+    Ok((addr * 8) as u32)
+}
+
+fn write_hex_record<W: Write>(out:&mut W, addr:u16, kind:u8, data:&[u8]) -> io::Result<()> {
+    let mut record = vec![data.len() as u8, (addr >> 8) as u8, addr as u8, kind];
+    record.extend_from_slice(data);
+    let checksum = record.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)).wrapping_neg();
+    record.push(checksum);
+    let hex:Vec<String> = record.iter().map(|b| format!("{:02X}", b)).collect();
+    writeln!(out, ":{}", hex.concat())
+}
+
+#[derive(Debug)]
+pub enum ImageError {
+    Io(io::Error),
+    UnknownFormat(String),
+    Malformed(String),
+    Checksum(usize),
+    AddressOutOfRange(u64),
+}
+
+impl From<io::Error> for ImageError {
+    fn from(err:io::Error) -> ImageError {
+        ImageError::Io(err)
+    }
+}
+
+impl fmt::Display for ImageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ImageError::Io(ref err)             => write!(f, "Io: {}", err),
+            ImageError::UnknownFormat(ref s)    => write!(f, "UnknownFormat: {} (expected .bin, .hex or .img)", s),
+            ImageError::Malformed(ref s)        => write!(f, "Malformed: {}", s),
+            ImageError::Checksum(line)          => write!(f, "Checksum: record in line {} is corrupted", line),
+            ImageError::AddressOutOfRange(addr) => write!(f, "AddressOutOfRange: {:#x} cannot be stored in this format", addr),
+        }
+    }
+}
+
+impl Error for ImageError {
+    fn description(&self) -> &str {
+        match *self {
+            ImageError::Io(_)                   => "i/o error",
+            ImageError::UnknownFormat(_)        => "unknown image format",
+            ImageError::Malformed(_)            => "malformed image",
+            ImageError::Checksum(_)             => "checksum mismatch",
+            ImageError::AddressOutOfRange(_)    => "address out of range",
+        }
+    }
+
+    fn cause(&self) -> Option<&Error> {
+        match *self {
+            ImageError::Io(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,238 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+//just enough JSON for the language server
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Num(f64),
+    Str(String),
+    Arr(Vec<Json>),
+    Obj(BTreeMap<String, Json>),
+}
+
+impl Json {
+
+    //builds an object from pairs, obj(vec![("id", Json::Num(1.0))])
+    pub fn obj(pairs:Vec<(&str, Json)>) -> Json {
+        Json::Obj(pairs.into_iter().map(|(key, value)| (key.to_string(), value)).collect())
+    }
+
+    pub fn str(s:&str) -> Json {
+        Json::Str(s.to_string())
+    }
+
+    //looks up a path of keys, get(&["params", "textDocument", "uri"])
+    pub fn get(&self, path:&[&str]) -> Option<&Json> {
+        let mut cur = self;
+        for key in path {
+            cur = match *cur {
+                Json::Obj(ref map) => match map.get(*key) {
+                    Some(value) => value,
+                    None => return None,
+                },
+                _ => return None,
+            };
+        }
+        Some(cur)
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match *self {
+            Json::Str(ref s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_u64(&self) -> Option<u64> {
+        match *self {
+            Json::Num(n) if n >= 0.0 && n.fract() == 0.0 => Some(n as u64),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match *self {
+            Json::Bool(b) => Some(b),
+            _ => None,
+        }
+    }
+
+    pub fn parse(_s:&str) -> Result<Json, String> {
+        let chars:Vec<char> = _s.chars().collect();
+        let mut pos = 0;
+        let value = try!(parse_value(&chars, &mut pos));
+        skip_whitespace(&chars, &mut pos);
+        if pos != chars.len() {
+            return Err(format!("trailing characters at {}", pos));
+        }
+        Ok(value)
+    }
+}
+
+fn skip_whitespace(chars:&[char], pos:&mut usize) {
+    while *pos < chars.len() && chars[*pos].is_whitespace() {
+        *pos += 1;
+    }
+}
+
+fn expect_word(chars:&[char], pos:&mut usize, word:&str, value:Json) -> Result<Json, String> {
+    let end = *pos + word.len();
+    if end <= chars.len() && chars[*pos..end].iter().cloned().collect::<String>() == word {
+        *pos = end;
+        Ok(value)
+    } else {
+        Err(format!("unexpected character at {}", pos))
+    }
+}
+
+fn parse_value(chars:&[char], pos:&mut usize) -> Result<Json, String> {
+    skip_whitespace(chars, pos);
+    match chars.get(*pos).cloned() {
+        Some('n') => expect_word(chars, pos, "null", Json::Null),
+        Some('t') => expect_word(chars, pos, "true", Json::Bool(true)),
+        Some('f') => expect_word(chars, pos, "false", Json::Bool(false)),
+        Some('"') => parse_string(chars, pos).map(Json::Str),
+        Some('[') => {
+            *pos += 1;
+            let mut items = Vec::new();
+            skip_whitespace(chars, pos);
+            if chars.get(*pos) == Some(&']') {
+                *pos += 1;
+                return Ok(Json::Arr(items));
+            }
+            loop {
+                items.push(try!(parse_value(chars, pos)));
+                skip_whitespace(chars, pos);
+                match chars.get(*pos) {
+                    Some(&',') => *pos += 1,
+                    Some(&']') => { *pos += 1; return Ok(Json::Arr(items)) },
+                    _ => return Err(format!("expected , or ] at {}", pos)),
+                }
+            }
+        },
+        Some('{') => {
+            *pos += 1;
+            let mut map = BTreeMap::new();
+            skip_whitespace(chars, pos);
+            if chars.get(*pos) == Some(&'}') {
+                *pos += 1;
+                return Ok(Json::Obj(map));
+            }
+            loop {
+                skip_whitespace(chars, pos);
+                if chars.get(*pos) != Some(&'"') {
+                    return Err(format!("expected a key at {}", pos));
+                }
+                let key = try!(parse_string(chars, pos));
+                skip_whitespace(chars, pos);
+                if chars.get(*pos) != Some(&':') {
+                    return Err(format!("expected : at {}", pos));
+                }
+                *pos += 1;
+                map.insert(key, try!(parse_value(chars, pos)));
+                skip_whitespace(chars, pos);
+                match chars.get(*pos) {
+                    Some(&',') => *pos += 1,
+                    Some(&'}') => { *pos += 1; return Ok(Json::Obj(map)) },
+                    _ => return Err(format!("expected , or }} at {}", pos)),
+                }
+            }
+        },
+        Some(c) if c == '-' || c.is_digit(10) => {
+            let start = *pos;
+            while *pos < chars.len() && (chars[*pos].is_digit(10) || "+-.eE".contains(chars[*pos])) {
+                *pos += 1;
+            }
+            let text:String = chars[start..*pos].iter().cloned().collect();
+            text.parse().map(Json::Num).map_err(|_| format!("invalid number {}", text))
+        },
+        _ => Err(format!("unexpected character at {}", pos)),
+    }
+}
+
+fn parse_string(chars:&[char], pos:&mut usize) -> Result<String, String> {
+    *pos += 1;
+    let mut s = String::new();
+    let mut pending_surrogate:Option<u32> = None;
+    loop {
+        let c = match chars.get(*pos) {
+            Some(&c) => c,
+            None => return Err("unterminated string".to_string()),
+        };
+        *pos += 1;
+        match c {
+            '"' => return Ok(s),
+            '\\' => {
+                let escape = match chars.get(*pos) {
+                    Some(&e) => e,
+                    None => return Err("unterminated string".to_string()),
+                };
+                *pos += 1;
+                match escape {
+                    'n' => s.push('\n'),
+                    't' => s.push('\t'),
+                    'r' => s.push('\r'),
+                    'b' => s.push('\u{8}'),
+                    'f' => s.push('\u{c}'),
+                    'u' => {
+                        let hex:String = chars[*pos..(*pos + 4).min(chars.len())].iter().cloned().collect();
+                        let unit = try!(u32::from_str_radix(&hex, 16).map_err(|_| format!("invalid escape \\u{}", hex)));
+                        *pos += 4;
+                        //characters outside the basic plane come as two escapes
+                        match (pending_surrogate.take(), unit) {
+                            (None, 0xd800...0xdbff) => pending_surrogate = Some(unit),
+                            (Some(high), 0xdc00...0xdfff) => {
+                                s.push(::std::char::from_u32(0x10000 + ((high - 0xd800) << 10) + (unit - 0xdc00)).unwrap_or('\u{fffd}'));
+                            },
+                            (_, unit) => s.push(::std::char::from_u32(unit).unwrap_or('\u{fffd}')),
+                        }
+                    },
+                    e => s.push(e),
+                }
+            },
+            c => s.push(c),
+        }
+    }
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Json::Null              => write!(f, "null"),
+            Json::Bool(b)           => write!(f, "{}", b),
+            Json::Num(n) if n.fract() == 0.0 && n.abs() < 1e15 => write!(f, "{}", n as i64),
+            Json::Num(n)            => write!(f, "{}", n),
+            Json::Str(ref s)        => {
+                try!(write!(f, "\""));
+                for c in s.chars() {
+                    match c {
+                        '"'     => try!(write!(f, "\\\"")),
+                        '\\'    => try!(write!(f, "\\\\")),
+                        '\n'    => try!(write!(f, "\\n")),
+                        '\r'    => try!(write!(f, "\\r")),
+                        '\t'    => try!(write!(f, "\\t")),
+                        c if (c as u32) < 0x20 => try!(write!(f, "\\u{:04x}", c as u32)),
+                        c       => try!(write!(f, "{}", c)),
+                    }
+                }
+                write!(f, "\"")
+            },
+            Json::Arr(ref items)    => {
+                try!(write!(f, "["));
+                for (i, item) in items.iter().enumerate() {
+                    try!(write!(f, "{}{}", if i > 0 { "," } else { "" }, item));
+                }
+                write!(f, "]")
+            },
+            Json::Obj(ref map)      => {
+                try!(write!(f, "{{"));
+                for (i, (key, value)) in map.iter().enumerate() {
+                    try!(write!(f, "{}{}:{}", if i > 0 { "," } else { "" }, Json::Str(key.clone()), value));
+                }
+                write!(f, "}}")
+            },
+        }
+    }
+}
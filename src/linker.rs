@@ -0,0 +1,196 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use expr;
+use parser::MemoryImage;
+use object::{ObjectFile, RelocKind, RelocTarget};
+use utils::strip_comment;
+
+//one line per output section, sections without an address follow the previous one:
+//  ENTRY start
+//  .text 0x0
+//  .data 0x1000
+//  .bss
+#[derive(Debug, Clone, PartialEq)]
+pub struct LinkerScript {
+    placements:Vec<(String, Option<u64>)>,
+    entry:Option<String>,
+}
+
+impl LinkerScript {
+
+    //.text at address 0, everything else behind it in the order it shows up
+    pub fn new() -> LinkerScript {
+        LinkerScript{ placements:vec![(".text".to_string(), Some(0))], entry:None }
+    }
+
+    pub fn parse(_s:&str) -> Result<LinkerScript, LinkError> {
+        let mut script = LinkerScript{ placements:Vec::new(), entry:None };
+        for (i, line) in _s.lines().enumerate() {
+            let fields:Vec<&str> = strip_comment(line).split_whitespace().collect();
+            match (fields.get(0).cloned(), fields.len()) {
+                (None, _) => {},
+                (Some("ENTRY"), 2) => script.entry = Some(fields[1].to_string()),
+                (Some(name), 1) => script.placements.push((name.to_string(), None)),
+                (Some(name), 2) => {
+                    let addr = try!(expr::eval(fields[1], &HashMap::new())
+                        .and_then(expr::check_addr)
+                        .map_err(|e| LinkError::InvalidScript(format!("line {}: {}", i + 1, e))));
+                    script.placements.push((name.to_string(), Some(addr)));
+                },
+                _ => return Err(LinkError::InvalidScript(format!("line {}: {}", i + 1, line))),
+            }
+        }
+        Ok(script)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LinkedImage {
+    pub image:MemoryImage,
+    pub entry:Option<u64>,
+    pub symbols:HashMap<String, u64>, //all global symbols with their final address
+}
+
+#[derive(Debug, Clone)]
+pub struct Linker {
+    modules:Vec<ObjectFile>,
+    script:LinkerScript,
+}
+
+impl Linker {
+
+    pub fn new(script:LinkerScript) -> Linker {
+        Linker{ modules:Vec::new(), script:script }
+    }
+
+    pub fn add(&mut self, module:ObjectFile) {
+        self.modules.push(module);
+    }
+
+    pub fn link(&self) -> Result<LinkedImage, LinkError> {
+        let bases = try!(self.layout());
+
+        //the final address of every global symbol
+        let mut globals:HashMap<String, u64> = HashMap::new();
+        for (i, module) in self.modules.iter().enumerate() {
+            for symbol in module.symbols.iter().filter(|s| s.global) {
+                let addr = try!(self.symbol_addr(&bases, i, symbol.section.as_ref(), symbol.value));
+                if globals.insert(symbol.name.clone(), addr).is_some() {
+                    return Err(LinkError::DuplicateSymbol(symbol.name.clone()));
+                }
+            }
+        }
+
+        let mut image = MemoryImage::new();
+        for (i, module) in self.modules.iter().enumerate() {
+            for section in module.sections.iter() {
+                let base = bases[&(i, section.name.clone())];
+                let mut words = section.words.clone();
+                for reloc in section.relocations.iter() {
+                    let target = match reloc.target {
+                        RelocTarget::Section(ref name) => try!(self.symbol_addr(&bases, i, Some(name), 0)),
+                        RelocTarget::Symbol(ref name) => try!(globals.get(name).cloned().ok_or(LinkError::UndefinedSymbol(name.clone()))),
+                    };
+                    let value = (target as i64).wrapping_add(reloc.addend);
+                    let word = try!(words.get_mut(reloc.offset as usize)
+                        .ok_or(LinkError::MalformedObject(format!("relocation outside of section {}", section.name))));
+                    *word = match reloc.kind {
+                        RelocKind::Addr => {
+                            let addr = try!(expr::check_addr(value).map_err(|_| LinkError::AddressOutOfRange(value)));
+                            *word & !expr::MAX_ADDR | addr
+                        },
+                        RelocKind::Word => value as u64,
+                    };
+                }
+                for (offset, word) in words.into_iter().enumerate() {
+                    if image.insert(base + offset as u64, word).is_some() {
+                        return Err(LinkError::Overlap(base + offset as u64));
+                    }
+                }
+            }
+        }
+
+        let entry = match self.script.entry {
+            Some(ref name) => Some(try!(globals.get(name).cloned().ok_or(LinkError::UndefinedSymbol(name.clone())))),
+            None => None,
+        };
+        Ok(LinkedImage{ image:image, entry:entry, symbols:globals })
+    }
+
+    //start address of every (module, section): sections of the same name are placed back to back
+    //in module order, the groups in the order of the linker script. Every section has to end inside
+    //of the address space.
+    fn layout(&self) -> Result<HashMap<(usize, String), u64>, LinkError> {
+        let mut placements = self.script.placements.clone();
+        for module in self.modules.iter() {
+            for section in module.sections.iter() {
+                if !placements.iter().any(|p| p.0 == section.name) {
+                    placements.push((section.name.clone(), None));
+                }
+            }
+        }
+
+        let mut bases = HashMap::new();
+        let mut cursor = 0;
+        for (name, addr) in placements {
+            if let Some(addr) = addr {
+                cursor = addr;
+            }
+            for (i, module) in self.modules.iter().enumerate() {
+                if let Some(section) = module.section(&name) {
+                    bases.insert((i, name.clone()), cursor);
+                    cursor = try!(cursor.checked_add(section.words.len() as u64)
+                        .filter(|&end| end <= expr::MAX_ADDR + 1)
+                        .ok_or(LinkError::AddressOutOfRange((cursor as i64).wrapping_add(section.words.len() as i64))));
+                }
+            }
+        }
+        Ok(bases)
+    }
+
+    fn symbol_addr(&self, bases:&HashMap<(usize, String), u64>, module:usize, section:Option<&String>, value:u64) -> Result<u64, LinkError> {
+        match section {
+            Some(name) => bases.get(&(module, name.clone()))
+                .map(|base| base + value)
+                .ok_or(LinkError::MalformedObject(format!("reference to missing section {}", name))),
+            None => Ok(value),
+        }
+    }
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum LinkError {
+    UndefinedSymbol(String),
+    DuplicateSymbol(String),
+    Overlap(u64),
+    AddressOutOfRange(i64),
+    InvalidScript(String),
+    MalformedObject(String),
+}
+
+impl fmt::Display for LinkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LinkError::UndefinedSymbol(ref s)   => write!(f, "UndefinedSymbol: {}", s),
+            LinkError::DuplicateSymbol(ref s)   => write!(f, "DuplicateSymbol: {}", s),
+            LinkError::Overlap(addr)            => write!(f, "Overlap: {:#x} is used by two sections", addr),
+            LinkError::AddressOutOfRange(n)     => write!(f, "AddressOutOfRange: {} does not fit into the address field", n),
+            LinkError::InvalidScript(ref s)     => write!(f, "InvalidScript: {}", s),
+            LinkError::MalformedObject(ref s)   => write!(f, "MalformedObject: {}", s),
+        }
+    }
+}
+
+impl Error for LinkError {
+    fn description(&self) -> &str {
+        match *self {
+            LinkError::UndefinedSymbol(_)       => "undefined symbol",
+            LinkError::DuplicateSymbol(_)       => "symbol defined twice",
+            LinkError::Overlap(_)               => "overlapping sections",
+            LinkError::AddressOutOfRange(_)     => "address out of range",
+            LinkError::InvalidScript(_)         => "invalid linker script",
+            LinkError::MalformedObject(_)       => "malformed object file",
+        }
+    }
+}
@@ -0,0 +1,343 @@
+use std::collections::{HashMap, HashSet, BTreeMap, VecDeque};
+use std::fmt;
+use expr::MAX_ADDR;
+use optimizer::Word;
+use utils::{Instruction, Opcode, Reg};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LintKind {
+    TargetOutOfRange(u64),
+    Unreachable(u64),           //number of instructions in a row
+    UninitializedRead(Reg),
+    StackImbalance(i64, i64),   //words on the stack on two paths that meet
+    UnbalancedReturn(i64),
+    IspWrite,
+    FallsOffEnd(u64),           //where execution would go on
+}
+
+impl fmt::Display for LintKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LintKind::TargetOutOfRange(addr)    => write!(f, "TargetOutOfRange: {:#x} is outside of memory", addr),
+            LintKind::Unreachable(n)            => write!(f, "Unreachable: {} instruction(s) can never run", n),
+            LintKind::UninitializedRead(reg)    => write!(f, "UninitializedRead: {} is read before anything writes it", reg),
+            LintKind::StackImbalance(a, b)      => write!(f, "StackImbalance: the stack holds {} word(s) on one path and {} on another", a, b),
+            LintKind::UnbalancedReturn(n)       => write!(f, "UnbalancedReturn: RET with {} word(s) left on the stack", n),
+            LintKind::IspWrite                  => write!(f, "IspWrite: ISP is used as a general register"),
+            LintKind::FallsOffEnd(addr)         => write!(f, "FallsOffEnd: execution runs on to {:#x}, which holds no instruction", addr),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lint {
+    pub addr:u64,
+    pub kind:LintKind,
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:#014x}: warning: {}", self.addr, self.kind)
+    }
+}
+
+//follows every path from the entry through the assembled program; jumps go both ways no matter
+//the flags, except for the JGZ X / JLZ X pair that stands for an unconditional jump, and RET
+//may return behind any CALL
+pub struct Linter {
+    words:BTreeMap<u64, Word>,
+    entry:u64,
+    memory_size:u64,
+    targets:HashSet<u64>, //labels and addresses something jumps to
+    patched:HashSet<u64>, //words the program overwrites, they could become any instruction
+    return_sites:Vec<u64>,
+}
+
+impl Linter {
+
+    //starts at the label start if there is one, like the loader, and knows no memory limit
+    pub fn new(_words:BTreeMap<u64, Word>, labels:&HashMap<String, u64>) -> Linter {
+        let entry = labels.get("start").cloned().or(_words.keys().next().cloned()).unwrap_or(0);
+        let mut linter = Linter {
+            words:_words,
+            entry:entry,
+            memory_size:MAX_ADDR + 1,
+            targets:labels.values().cloned().collect(),
+            patched:HashSet::new(),
+            return_sites:Vec::new(),
+        };
+        for (&addr, &word) in linter.words.iter() {
+            let instr = match word {
+                Word::Instr(instr) if instr.is_valid() => instr,
+                Word::Addr(target) => { linter.targets.insert(target); continue },
+                _ => continue,
+            };
+            match instr.opcode() {
+                Opcode::Sav => { linter.patched.insert(instr.addr()); },
+                Opcode::Jz | Opcode::Jgz | Opcode::Jlz => { linter.targets.insert(instr.addr()); },
+                Opcode::Call => {
+                    linter.targets.insert(instr.addr());
+                    linter.return_sites.push(addr + 1);
+                },
+                _ => {},
+            }
+        }
+        linter
+    }
+
+    pub fn set_entry(&mut self, addr:u64) -> &mut Linter {
+        self.entry = addr;
+        self
+    }
+
+    pub fn set_memory_size(&mut self, size:u64) -> &mut Linter {
+        self.memory_size = size;
+        self
+    }
+
+    //all findings ordered by address
+    pub fn run(&self) -> Vec<Lint> {
+        let mut lints = Vec::new();
+        for (&addr, _) in self.words.iter() {
+            if let Some(instr) = self.instr(addr) {
+                if has_target(instr) && instr.addr() >= self.memory_size {
+                    lints.push(Lint{ addr:addr, kind:LintKind::TargetOutOfRange(instr.addr()) });
+                }
+                if writes(instr) == Some(Reg::ISP) {
+                    lints.push(Lint{ addr:addr, kind:LintKind::IspWrite });
+                }
+            }
+        }
+        let reachable = self.reachable(&mut lints);
+        self.unreachable(&reachable, &mut lints);
+        self.registers(&mut lints);
+        self.stack(&reachable, &mut lints);
+        lints.sort_by_key(|lint| lint.addr);
+        lints
+    }
+
+    //valid instructions that the program does not patch
+    fn instr(&self, addr:u64) -> Option<Instruction> {
+        match self.words.get(&addr) {
+            Some(&Word::Instr(instr)) if instr.is_valid() && !self.patched.contains(&addr) => Some(instr),
+            _ => None,
+        }
+    }
+
+    fn is_code(&self, addr:u64) -> bool {
+        self.instr(addr).is_some() || (self.patched.contains(&addr) && self.words.contains_key(&addr))
+    }
+
+    //JLZ X right behind JGZ X (or the other way around) never falls through, unless something jumps
+    //to the second one directly
+    fn unconditional(&self, addr:u64, instr:Instruction) -> bool {
+        let partner = match instr.opcode() {
+            Opcode::Jgz => Opcode::Jlz,
+            Opcode::Jlz => Opcode::Jgz,
+            _ => return false,
+        };
+        match addr.checked_sub(1).and_then(|prev| self.instr(prev)) {
+            Some(prev) => prev.opcode() == partner && prev.addr() == instr.addr() && !self.targets.contains(&addr),
+            None => false,
+        }
+    }
+
+    //where execution can go after addr and whether it falls through to the next word;
+    //None if ISP is written, then nothing is known about the next instruction
+    fn successors(&self, addr:u64) -> Option<(Vec<u64>, bool)> {
+        let instr = match self.instr(addr) {
+            Some(instr) => instr,
+            None => return Some((Vec::new(), true)), //a patched word
+        };
+        if writes(instr) == Some(Reg::ISP) {
+            return None;
+        }
+        let jump = |target:u64| if target < self.memory_size { vec![target] } else { Vec::new() };
+        Some(match instr.opcode() {
+            Opcode::Jz | Opcode::Jgz | Opcode::Jlz => (jump(instr.addr()), !self.unconditional(addr, instr)),
+            Opcode::Call => (jump(instr.addr()), true),
+            Opcode::Ret => (self.return_sites.clone(), false),
+            _ => (Vec::new(), true),
+        })
+    }
+
+    //every word that can run, false for the ones reached only through a write to ISP
+    fn reachable(&self, lints:&mut Vec<Lint>) -> HashMap<u64, bool> {
+        let mut reachable = HashMap::new();
+        let mut queue = VecDeque::new();
+        queue.push_back(self.entry);
+        while let Some(addr) = queue.pop_front() {
+            if reachable.contains_key(&addr) || !self.is_code(addr) {
+                continue;
+            }
+            reachable.insert(addr, true);
+            let (mut next, falls) = match self.successors(addr) {
+                Some(succ) => succ,
+                None => { reachable.insert(addr, false); continue },
+            };
+            if falls {
+                if self.is_code(addr + 1) {
+                    next.push(addr + 1);
+                } else {
+                    lints.push(Lint{ addr:addr, kind:LintKind::FallsOffEnd(addr + 1) });
+                }
+            }
+            queue.extend(next);
+        }
+        reachable
+    }
+
+    fn unreachable(&self, reachable:&HashMap<u64, bool>, lints:&mut Vec<Lint>) {
+        //a write to ISP can go anywhere
+        if reachable.values().any(|&known| !known) {
+            return;
+        }
+        let mut run:Option<(u64, u64)> = None;
+        for (&addr, _) in self.words.iter() {
+            let dead = self.instr(addr).is_some() && !reachable.contains_key(&addr);
+            run = match (run, dead) {
+                (Some((start, n)), true) if start + n == addr => Some((start, n + 1)),
+                (run, dead) => {
+                    if let Some((start, n)) = run {
+                        lints.push(Lint{ addr:start, kind:LintKind::Unreachable(n) });
+                    }
+                    if dead { Some((addr, 1)) } else { None }
+                },
+            };
+        }
+        if let Some((start, n)) = run {
+            lints.push(Lint{ addr:start, kind:LintKind::Unreachable(n) });
+        }
+    }
+
+    //registers that may have been written on some path to every instruction, as a bit set
+    fn registers(&self, lints:&mut Vec<Lint>) {
+        let mut written:HashMap<u64, u8> = HashMap::new();
+        let mut queue = VecDeque::new();
+        queue.push_back((self.entry, 1 << Reg::ISP as u8));
+        while let Some((addr, state)) = queue.pop_front() {
+            if !self.is_code(addr) {
+                continue;
+            }
+            let known = written.get(&addr).cloned();
+            let state = state | known.unwrap_or(0);
+            if known == Some(state) {
+                continue;
+            }
+            written.insert(addr, state);
+            let out = match self.instr(addr).and_then(writes) {
+                Some(reg) => state | 1 << reg as u8,
+                None => state,
+            };
+            if let Some((next, falls)) = self.successors(addr) {
+                queue.extend(next.into_iter().map(|next| (next, out)));
+                if falls {
+                    queue.push_back((addr + 1, out));
+                }
+            }
+        }
+        for (&addr, &state) in written.iter() {
+            let instr = match self.instr(addr) {
+                Some(instr) => instr,
+                None => continue,
+            };
+            for reg in reads(instr) {
+                if state & 1 << reg as u8 == 0 {
+                    lints.push(Lint{ addr:addr, kind:LintKind::UninitializedRead(reg) });
+                }
+            }
+        }
+    }
+
+    //words pushed since the function was entered; CALL is taken to leave the stack as it was and
+    //any other write to ESP makes the depth unknown
+    fn stack(&self, reachable:&HashMap<u64, bool>, lints:&mut Vec<Lint>) {
+        let mut functions:Vec<u64> = vec![self.entry];
+        for (&addr, _) in self.words.iter() {
+            match self.instr(addr) {
+                Some(instr) if instr.opcode() == Opcode::Call && reachable.contains_key(&addr) => functions.push(instr.addr()),
+                _ => {},
+            }
+        }
+        functions.sort();
+        functions.dedup();
+
+        let mut reported = HashSet::new();
+        for function in functions {
+            let mut depths:HashMap<u64, Option<i64>> = HashMap::new();
+            let mut queue = VecDeque::new();
+            queue.push_back((function, Some(0)));
+            while let Some((addr, depth)) = queue.pop_front() {
+                if !self.is_code(addr) {
+                    continue;
+                }
+                match (depths.get(&addr).cloned(), depth) {
+                    (Some(Some(known)), Some(depth)) if known != depth => {
+                        if reported.insert(addr) {
+                            lints.push(Lint{ addr:addr, kind:LintKind::StackImbalance(known, depth) });
+                        }
+                        continue;
+                    },
+                    (Some(_), _) => continue,
+                    (None, _) => { depths.insert(addr, depth); },
+                }
+                let instr = match self.instr(addr) {
+                    Some(instr) => instr,
+                    None => { queue.push_back((addr + 1, depth)); continue },
+                };
+                let out = match (instr.opcode(), writes(instr)) {
+                    (_, Some(Reg::ESP)) => None,
+                    (Opcode::Push, _) => depth.map(|d| d + 1),
+                    (Opcode::Pop, _) => depth.map(|d| d - 1),
+                    _ => depth,
+                };
+                match instr.opcode() {
+                    Opcode::Ret => {
+                        match depth {
+                            Some(depth) if depth != 0 && reported.insert(addr) => {
+                                lints.push(Lint{ addr:addr, kind:LintKind::UnbalancedReturn(depth) });
+                            },
+                            _ => {},
+                        }
+                    },
+                    Opcode::Call => queue.push_back((addr + 1, out)),
+                    _ => if let Some((next, falls)) = self.successors(addr) {
+                        queue.extend(next.into_iter().map(|next| (next, out)));
+                        if falls {
+                            queue.push_back((addr + 1, out));
+                        }
+                    },
+                }
+            }
+        }
+    }
+}
+
+fn has_target(instr:Instruction) -> bool {
+    match instr.opcode() {
+        Opcode::Ld | Opcode::Sav | Opcode::Jz | Opcode::Jgz | Opcode::Jlz | Opcode::Call => true,
+        _ => false,
+    }
+}
+
+fn writes(instr:Instruction) -> Option<Reg> {
+    match instr.opcode() {
+        Opcode::Add | Opcode::Mul | Opcode::Ld | Opcode::Pop => Some(instr.reg1()),
+        _ => None,
+    }
+}
+
+//registers named as operants, the stack pointer that PUSH, POP, CALL and RET use is not counted
+fn reads(instr:Instruction) -> Vec<Reg> {
+    match instr.opcode() {
+        Opcode::Add | Opcode::Mul => {
+            let mut regs = vec![instr.reg1()];
+            if instr.reg2() != instr.reg1() {
+                regs.push(instr.reg2());
+            }
+            regs
+        },
+        Opcode::Sav | Opcode::Push => vec![instr.reg1()],
+        _ => Vec::new(),
+    }
+}
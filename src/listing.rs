@@ -0,0 +1,130 @@
+use std::collections::{HashMap, BTreeMap};
+use std::fmt;
+use parser::split_label;
+use preprocessor::{SourceLine, split_directive};
+use utils::split_operants;
+use expr;
+
+//a source line together with the words it was assembled into
+#[derive(Debug, Clone, PartialEq)]
+pub struct ListingLine {
+    pub addr:Option<u64>,
+    pub words:Vec<u64>,
+    pub source:SourceLine,
+}
+
+impl ListingLine {
+    pub fn new(source:SourceLine) -> ListingLine {
+        ListingLine{ addr:None, words:Vec::new(), source:source }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ListedSymbol {
+    pub value:u64,
+    pub section:Option<String>,           //None for constants
+    pub defined_at:Option<(String, usize)>, //None for constants from the command line
+    pub references:Vec<(String, usize)>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Listing {
+    pub lines:Vec<ListingLine>,
+    pub symbols:BTreeMap<String, ListedSymbol>,
+}
+
+//lines that came out of a macro are listed under the line that called it
+fn origin(line:&SourceLine) -> (String, usize) {
+    let (file, line) = line.origin();
+    (file.to_string(), line)
+}
+
+impl Listing {
+
+    pub fn new(lines:Vec<ListingLine>, labels:&HashMap<String, u64>, sections:&HashMap<String, String>) -> Listing {
+        let mut symbols:BTreeMap<String, ListedSymbol> = labels.iter().map(|(name, &value)| {
+            (name.clone(), ListedSymbol{ value:value, section:sections.get(name).cloned(), defined_at:None, references:Vec::new() })
+        }).collect();
+
+        for line in lines.iter() {
+            let location = origin(&line.source);
+            let mut rest = &*line.source.text;
+            while let Some((label, tail)) = split_label(rest) {
+                if let Some(symbol) = symbols.get_mut(label) {
+                    symbol.defined_at = Some(location.clone());
+                }
+                rest = tail;
+            }
+
+            let (name, args) = split_directive(rest);
+            let mut operants = split_operants(args);
+            if name.to_lowercase() == ".equ" && !operants.is_empty() {
+                if let Some(symbol) = symbols.get_mut(operants[0]) {
+                    symbol.defined_at = Some(location.clone());
+                }
+                operants.remove(0);
+            }
+            //operants that are no expressions, like strings, cannot refer to symbols
+            for name in operants.into_iter().filter_map(|op| expr::symbols(op).ok()).flat_map(|names| names.into_iter()) {
+                if let Some(symbol) = symbols.get_mut(&name) {
+                    if !symbol.references.contains(&location) {
+                        symbol.references.push(location.clone());
+                    }
+                }
+            }
+        }
+        Listing{ lines:lines, symbols:symbols }
+    }
+}
+
+//  <input>
+//  000000000000  1000000000000005      1  start: LD EAX 5
+//                                      2  square EAX
+//  000000000001  2000000000000000      2+     MUL EAX EAX
+//expanded macro lines are marked with one + per level of nesting
+impl fmt::Display for Listing {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut file:Option<String> = None;
+        let mut last_origin = None;
+        for line in self.lines.iter() {
+            let source = &line.source;
+            let (origin_file, origin_line) = origin(source);
+            if file.as_ref() != Some(&origin_file) {
+                try!(writeln!(f, "{}", origin_file));
+                file = Some(origin_file.clone());
+            }
+            //the call of a macro does not come out of the preprocessor, so show it before its expansion
+            if !source.expanded_from.is_empty() && last_origin != Some((origin_file.clone(), origin_line)) {
+                try!(writeln!(f, "{:12}  {:16}  {:>5}  {}", "", "", origin_line, source.expanded_from[0].raw));
+            }
+            last_origin = Some((origin_file, origin_line));
+
+            let marker = "+".repeat(source.expanded_from.len());
+            let number = format!("{}{}", origin_line, marker);
+            match (line.addr, line.words.first()) {
+                (Some(addr), Some(word)) => try!(writeln!(f, "{:012x}  {:016x}  {:>5}  {}", addr, word, number, source.raw)),
+                (Some(addr), None) => try!(writeln!(f, "{:012x}  {:16}  {:>5}  {}", addr, "", number, source.raw)),
+                (None, _) => try!(writeln!(f, "{:12}  {:16}  {:>5}  {}", "", "", number, source.raw)),
+            }
+            for (i, word) in line.words.iter().enumerate().skip(1) {
+                try!(writeln!(f, "{:012x}  {:016x}", line.addr.unwrap_or(0) + i as u64, word));
+            }
+        }
+
+        try!(writeln!(f, "\nSYMBOLS"));
+        for (name, symbol) in self.symbols.iter() {
+            let defined = match symbol.defined_at {
+                Some((ref file, line)) => format!("{}:{}", file, line),
+                None => "-D".to_string(),
+            };
+            try!(writeln!(f, "{:20} {:012x}  {:10} {}", name, symbol.value, symbol.section.as_ref().map(|s| &**s).unwrap_or("*"), defined));
+        }
+
+        try!(write!(f, "\nCROSS REFERENCE"));
+        for (name, symbol) in self.symbols.iter() {
+            let references:Vec<String> = symbol.references.iter().map(|&(ref file, line)| format!("{}:{}", file, line)).collect();
+            try!(write!(f, "\n{}", format!("{:20} {}", name, references.join(" ")).trim_right()));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,393 @@
+use std::collections::HashMap;
+use std::io;
+use std::io::{BufRead, Read, Write};
+use std::path::Path;
+use json::Json;
+use parser::{Parser, is_label, is_label_char, split_label};
+use utils::strip_comment;
+
+//mnemonic, operants and what it does; completion and hover are built from it
+const MNEMONICS:[(&'static str, &'static str, &'static str); 12] = [
+    ("NOP",  "",            "does nothing and clears all flags"),
+    ("ADD",  "REG1, REG2",  "REG1 = REG1 + REG2, sets the flags"),
+    ("MUL",  "REG1, REG2",  "REG1 = REG1 * REG2, sets the flags"),
+    ("LD",   "REG, ADDR",   "loads the word at ADDR into REG"),
+    ("SAV",  "ADDR, REG",   "stores REG at ADDR"),
+    ("PUSH", "REG",         "ESP = ESP - 1, then stores REG at ESP"),
+    ("POP",  "REG",         "loads the word at ESP into REG, then ESP = ESP + 1"),
+    ("JZ",   "ADDR",        "jumps to ADDR if ZERO is set"),
+    ("JGZ",  "ADDR",        "jumps to ADDR if SIGN is not set"),
+    ("JLZ",  "ADDR",        "jumps to ADDR if SIGN is set"),
+    ("CALL", "ADDR",        "pushes the address of the next instruction and jumps to ADDR"),
+    ("RET",  "",            "pops an address and jumps to it"),
+];
+
+const REGISTERS:[(&'static str, &'static str); 7] = [
+    ("EAX", "general purpose register"),
+    ("EBX", "general purpose register"),
+    ("ECX", "general purpose register"),
+    ("EDX", "general purpose register"),
+    ("ESP", "stack pointer, PUSH, POP, CALL and RET move it"),
+    ("EBP", "base pointer, general purpose by convention used for stack frames"),
+    ("ISP", "instruction pointer, the address of the next instruction"),
+];
+
+//LSP CompletionItemKind
+const KIND_VARIABLE:u64 = 6;
+const KIND_KEYWORD:u64 = 14;
+const KIND_REFERENCE:u64 = 18;
+
+//JSON-RPC error codes
+const PARSE_ERROR:f64 = -32700.0;
+const INVALID_REQUEST:f64 = -32600.0;
+const METHOD_NOT_FOUND:f64 = -32601.0;
+
+const MAX_MESSAGE:usize = 1 << 24; //bytes, longer messages are skipped unread
+
+//the open documents by uri; every request works on the latest text of a document
+pub struct Server {
+    documents:HashMap<String, String>,
+}
+
+impl Server {
+
+    pub fn new() -> Server {
+        Server{ documents:HashMap::new() }
+    }
+
+    //the messages to send back, None once the client asks the server to exit
+    pub fn handle(&mut self, msg:&Json) -> Option<Vec<Json>> {
+        let method = msg.get(&["method"]).and_then(Json::as_str).unwrap_or("");
+        let params = msg.get(&["params"]).cloned().unwrap_or(Json::Null);
+        let uri = params.get(&["textDocument", "uri"]).and_then(Json::as_str).unwrap_or("").to_string();
+        let result = match method {
+            "initialize" => Json::obj(vec![
+                ("capabilities", Json::obj(vec![
+                    ("textDocumentSync", Json::Num(1.0)), //the full text with every change
+                    ("hoverProvider", Json::Bool(true)),
+                    ("definitionProvider", Json::Bool(true)),
+                    ("referencesProvider", Json::Bool(true)),
+                    ("completionProvider", Json::obj(vec![])),
+                ])),
+                ("serverInfo", Json::obj(vec![("name", Json::str("compsim-lsp"))])),
+            ]),
+            "shutdown" => Json::Null,
+            "exit" => return None,
+            "textDocument/didOpen" => {
+                let text = params.get(&["textDocument", "text"]).and_then(Json::as_str).unwrap_or("");
+                self.documents.insert(uri.clone(), text.to_string());
+                return Some(vec![self.diagnostics(&uri)]);
+            },
+            "textDocument/didChange" => {
+                if let Some(&Json::Arr(ref changes)) = params.get(&["contentChanges"]) {
+                    if let Some(text) = changes.last().and_then(|change| change.get(&["text"])).and_then(Json::as_str) {
+                        self.documents.insert(uri.clone(), text.to_string());
+                    }
+                }
+                return Some(vec![self.diagnostics(&uri)]);
+            },
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                return Some(vec![publish(&uri, Vec::new())]);
+            },
+            "textDocument/hover" => self.hover(&uri, position(self.text(&uri), &params)),
+            "textDocument/definition" => self.definition(&uri, position(self.text(&uri), &params)),
+            "textDocument/references" => {
+                let declaration = params.get(&["context", "includeDeclaration"]).and_then(Json::as_bool).unwrap_or(true);
+                self.references(&uri, position(self.text(&uri), &params), declaration)
+            },
+            "textDocument/completion" => self.completion(&uri),
+            _ => match msg.get(&["id"]) {
+                Some(id) => return Some(vec![failure(id.clone(), METHOD_NOT_FOUND, &format!("unknown method {}", method))]),
+                None => return Some(Vec::new()), //notifications the server does not care about
+            },
+        };
+        match msg.get(&["id"]) {
+            Some(id) => Some(vec![Json::obj(vec![("jsonrpc", Json::str("2.0")), ("id", id.clone()), ("result", result)])]),
+            None => Some(Vec::new()),
+        }
+    }
+
+    fn text(&self, uri:&str) -> &str {
+        self.documents.get(uri).map(|text| &**text).unwrap_or("")
+    }
+
+    //assembles the document as if it was a file, so that includes next to it are found
+    fn assemble(&self, uri:&str) -> (Parser, Vec<::diagnostic::Diagnostic>) {
+        let path = uri_to_path(uri);
+        let mut parser = Parser::new();
+        if let Some(dir) = Path::new(&path).parent() {
+            parser.add_include_path(dir);
+        }
+        let diagnostics = parser.read_from_reader(self.text(uri).as_bytes(), &path).err().unwrap_or(Vec::new());
+        (parser, diagnostics)
+    }
+
+    fn diagnostics(&self, uri:&str) -> Json {
+        let path = uri_to_path(uri);
+        let diagnostics = self.assemble(uri).1.into_iter().map(|diagnostic| {
+            let mut message = diagnostic.error.to_string();
+            for note in diagnostic.notes.iter() {
+                message = message + "\n" + note;
+            }
+            //errors in included files are shown at the top of the document
+            let (line, column, width) = if diagnostic.file == path && diagnostic.line > 0 {
+                (diagnostic.line - 1, diagnostic.column - 1, diagnostic.width)
+            } else {
+                if diagnostic.line > 0 {
+                    message = format!("{}:{}: {}", diagnostic.file, diagnostic.line, message);
+                }
+                (0, 0, 0)
+            };
+            Json::obj(vec![
+                ("range", range(self.text(uri), line, column, column + width)),
+                ("severity", Json::Num(1.0)),
+                ("source", Json::str("compsim")),
+                ("message", Json::Str(message)),
+            ])
+        }).collect();
+        publish(uri, diagnostics)
+    }
+
+    fn hover(&self, uri:&str, pos:(usize, usize)) -> Json {
+        let word = match word_at(self.text(uri), pos) {
+            Some(word) => word,
+            None => return Json::Null,
+        };
+        let upper = word.to_uppercase();
+        let doc = if let Some(&(name, operants, what)) = MNEMONICS.iter().find(|m| m.0 == upper) {
+            format!("```\n{} {}\n```\n{}", name, operants, what)
+        } else if let Some(&(name, what)) = REGISTERS.iter().find(|r| r.0 == upper) {
+            format!("**{}**: {}", name, what)
+        } else {
+            match self.assemble(uri).0.labels().get(&word) {
+                Some(&addr) => format!("**{}** = {:#x}", word, addr),
+                None => return Json::Null,
+            }
+        };
+        Json::obj(vec![("contents", Json::obj(vec![("kind", Json::str("markdown")), ("value", Json::Str(doc))]))])
+    }
+
+    fn definition(&self, uri:&str, pos:(usize, usize)) -> Json {
+        let word = match word_at(self.text(uri), pos) {
+            Some(word) => word,
+            None => return Json::Null,
+        };
+        match definitions(self.text(uri)).into_iter().find(|def| def.0 == word) {
+            Some((_, line, column)) => location(self.text(uri), uri, line, column, column + word.chars().count()),
+            None => Json::Null,
+        }
+    }
+
+    fn references(&self, uri:&str, pos:(usize, usize), declaration:bool) -> Json {
+        let text = self.text(uri);
+        let word = match word_at(text, pos) {
+            Some(word) => word,
+            None => return Json::Null,
+        };
+        let defs:Vec<(usize, usize)> = definitions(text).into_iter().filter(|def| def.0 == word).map(|def| (def.1, def.2)).collect();
+        if defs.is_empty() {
+            return Json::Null;
+        }
+        let len = word.chars().count();
+        Json::Arr(tokens(text).into_iter()
+            .filter(|&(ref token, line, column)| *token == word && (declaration || !defs.contains(&(line, column))))
+            .map(|(_, line, column)| location(text, uri, line, column, column + len))
+            .collect())
+    }
+
+    fn completion(&self, uri:&str) -> Json {
+        let mut items = Vec::new();
+        for &(name, operants, what) in MNEMONICS.iter() {
+            items.push(completion_item(name, KIND_KEYWORD, &format!("{} {} - {}", name, operants, what)));
+        }
+        for &(name, what) in REGISTERS.iter() {
+            items.push(completion_item(name, KIND_VARIABLE, what));
+        }
+        for (name, _, _) in definitions(self.text(uri)) {
+            items.push(completion_item(&name, KIND_REFERENCE, "label"));
+        }
+        Json::Arr(items)
+    }
+}
+
+fn completion_item(label:&str, kind:u64, detail:&str) -> Json {
+    Json::obj(vec![("label", Json::str(label)), ("kind", Json::Num(kind as f64)), ("detail", Json::str(detail))])
+}
+
+fn publish(uri:&str, diagnostics:Vec<Json>) -> Json {
+    Json::obj(vec![
+        ("jsonrpc", Json::str("2.0")),
+        ("method", Json::str("textDocument/publishDiagnostics")),
+        ("params", Json::obj(vec![("uri", Json::str(uri)), ("diagnostics", Json::Arr(diagnostics))])),
+    ])
+}
+
+//the protocol counts columns in UTF-16 code units, the server in chars
+fn to_utf16(text:&str, line:usize, column:usize) -> usize {
+    match text.lines().nth(line) {
+        Some(line) => line.chars().take(column).map(char::len_utf16).sum::<usize>() + column.saturating_sub(line.chars().count()),
+        None => column,
+    }
+}
+
+fn from_utf16(text:&str, line:usize, units:usize) -> usize {
+    let chars:Vec<char> = text.lines().nth(line).map(|line| line.chars().collect()).unwrap_or(Vec::new());
+    let mut seen = 0;
+    for (i, c) in chars.iter().enumerate() {
+        if seen >= units {
+            return i;
+        }
+        seen += c.len_utf16();
+    }
+    chars.len() + units.saturating_sub(seen)
+}
+
+fn range(text:&str, line:usize, start:usize, end:usize) -> Json {
+    let pos = |character:usize| Json::obj(vec![("line", Json::Num(line as f64)), ("character", Json::Num(to_utf16(text, line, character) as f64))]);
+    Json::obj(vec![("start", pos(start)), ("end", pos(end))])
+}
+
+fn location(text:&str, uri:&str, line:usize, start:usize, end:usize) -> Json {
+    Json::obj(vec![("uri", Json::str(uri)), ("range", range(text, line, start, end))])
+}
+
+fn position(text:&str, params:&Json) -> (usize, usize) {
+    let get = |key| params.get(&["position", key]).and_then(Json::as_u64).unwrap_or(0) as usize;
+    (get("line"), from_utf16(text, get("line"), get("character")))
+}
+
+//"file:///home/x/a%20b.asm" is /home/x/a b.asm, anything else is used as it is
+fn uri_to_path(uri:&str) -> String {
+    if !uri.starts_with("file://") {
+        return uri.to_string();
+    }
+    let mut path = Vec::new();
+    let bytes = uri["file://".len()..].as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = if bytes[i] == b'%' && i + 2 < bytes.len() { ::std::str::from_utf8(&bytes[i + 1..i + 3]).ok() } else { None };
+        match hex.and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+            Some(byte) => { path.push(byte); i += 3 },
+            None => { path.push(bytes[i]); i += 1 },
+        }
+    }
+    String::from_utf8_lossy(&path).into_owned()
+}
+
+//every name in the code part of a line with line and column, comments are skipped
+fn tokens(text:&str) -> Vec<(String, usize, usize)> {
+    let mut tokens = Vec::new();
+    for (line_no, line) in text.lines().enumerate() {
+        let chars:Vec<char> = strip_comment(line).chars().collect();
+        let mut i = 0;
+        while i < chars.len() {
+            if !is_label_char(chars[i]) || (i > 0 && is_label_char(chars[i - 1])) {
+                i += 1;
+                continue;
+            }
+            let start = i;
+            while i < chars.len() && is_label_char(chars[i]) {
+                i += 1;
+            }
+            let token:String = chars[start..i].iter().cloned().collect();
+            if is_label(&token) {
+                tokens.push((token, line_no, start));
+            }
+        }
+    }
+    tokens
+}
+
+//labels and .equ names with the place they are defined
+fn definitions(text:&str) -> Vec<(String, usize, usize)> {
+    let mut defs = Vec::new();
+    for (line_no, line) in text.lines().enumerate() {
+        let code = strip_comment(line);
+        let mut rest = code;
+        while let Some((label, tail)) = split_label(rest) {
+            defs.push((label.to_string(), line_no, column_of(code, label)));
+            rest = tail;
+        }
+        let trimmed = rest.trim_left();
+        if trimmed.len() > 4 && trimmed.get(..4).map(|start| start.eq_ignore_ascii_case(".equ")).unwrap_or(false) {
+            if let Some(name) = trimmed[4..].split(',').next().map(str::trim).filter(|name| is_label(name)) {
+                defs.push((name.to_string(), line_no, column_of(code, name)));
+            }
+        }
+    }
+    defs
+}
+
+//the column of part, which has to be a slice of line
+fn column_of(line:&str, part:&str) -> usize {
+    let offset = part.as_ptr() as usize - line.as_ptr() as usize;
+    line[..offset].chars().count()
+}
+
+fn word_at(text:&str, (line, character):(usize, usize)) -> Option<String> {
+    let chars:Vec<char> = match text.lines().nth(line) {
+        Some(line) => line.chars().collect(),
+        None => return None,
+    };
+    let mut start = character.min(chars.len());
+    while start > 0 && is_label_char(chars[start - 1]) {
+        start -= 1;
+    }
+    let mut end = character.min(chars.len());
+    while end < chars.len() && is_label_char(chars[end]) {
+        end += 1;
+    }
+    let word:String = chars[start..end].iter().cloned().collect();
+    if word.is_empty() { None } else { Some(word) }
+}
+
+//an error response, with a null id if the request could not be read
+fn failure(id:Json, code:f64, message:&str) -> Json {
+    Json::obj(vec![
+        ("jsonrpc", Json::str("2.0")),
+        ("id", id),
+        ("error", Json::obj(vec![("code", Json::Num(code)), ("message", Json::str(message))])),
+    ])
+}
+
+//"Content-Length: N" headers followed by N bytes of JSON in both directions
+pub fn run<R: BufRead, W: Write>(mut input:R, out:&mut W) -> io::Result<()> {
+    let mut server = Server::new();
+    loop {
+        let mut length = None;
+        loop {
+            let mut header = String::new();
+            if try!(input.read_line(&mut header)) == 0 {
+                return Ok(());
+            }
+            let header = header.trim();
+            if header.is_empty() {
+                break;
+            }
+            if header.to_lowercase().starts_with("content-length:") {
+                length = header["content-length:".len()..].trim().parse::<usize>().ok();
+            }
+        }
+        let length = match length { Some(n) => n, None => continue };
+        let replies = if length > MAX_MESSAGE {
+            try!(io::copy(&mut (&mut input).take(length as u64), &mut io::sink()));
+            vec![failure(Json::Null, INVALID_REQUEST, &format!("message of {} bytes is too long", length))]
+        } else {
+            let mut body = vec![0; length];
+            try!(input.read_exact(&mut body));
+            match Json::parse(&String::from_utf8_lossy(&body)) {
+                Ok(msg) => match server.handle(&msg) {
+                    Some(replies) => replies,
+                    None => return Ok(()),
+                },
+                Err(err) => vec![failure(Json::Null, PARSE_ERROR, &err)],
+            }
+        };
+        for reply in replies {
+            let text = reply.to_string();
+            try!(write!(out, "Content-Length: {}\r\n\r\n{}", text.len(), text));
+        }
+        try!(out.flush());
+    }
+}
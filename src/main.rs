@@ -6,15 +6,47 @@ extern crate num;
 mod cpu;
 mod utils;
 mod parser;
+mod expr;
+mod preprocessor;
+mod diagnostic;
+mod disassembler;
+mod object;
+mod linker;
+mod listing;
+mod image;
+mod repl;
+mod debuginfo;
+mod compiler;
+mod optimizer;
+mod lint;
+mod cfg;
+mod json;
+mod lsp;
+mod formatter;
+mod symbolic;
+mod memory;
+mod device;
+mod cache;
+mod coherence;
 mod test;
 
 use std::sync::mpsc::{Sender,Receiver, channel};
+use std::collections::HashMap;
+use std::env;
+use std::process;
+use std::fs::File;
+use std::path::Path;
+use std::io;
+use std::io::{Read, Write, BufReader};
+use std::thread;
 use utils::MemBusOp;
 
-const RAM_SIZE:usize = 1_000_000;
+const RAM_SIZE:u64 = expr::MAX_ADDR + 1;
+const BOOT_STEPS:usize = 10000; //instructions every core runs after booting, unless -n says otherwise
 
 struct Ram {
-    memory:[i64; RAM_SIZE],
+    memory:memory::SparseMemory,
+    devices:device::Decoder,
     tx:Sender<MemBusOp>,
     rx:Receiver<MemBusOp>,
 }
@@ -22,7 +54,74 @@ struct Ram {
 impl Ram {
 
     pub fn new(_tx:Sender<MemBusOp>, _rx:Receiver<MemBusOp>) -> Ram {
-       Ram { memory:[0;RAM_SIZE], tx:_tx, rx:_rx}
+       let mut memory = memory::SparseMemory::new();
+       memory.set_size(RAM_SIZE);
+       Ram { memory:memory, devices:device::Decoder::new(), tx:_tx, rx:_rx}
+    }
+
+    //words mapped by a device go to the device, all others to memory
+    fn is_valid(&self, addr:u64) -> bool {
+        addr < self.memory.size() || self.devices.is_mapped(addr)
+    }
+
+    //reads are answered with the words, writes are applied without an answer. A write with an
+    //address outside of memory is rejected as a whole, a read only if it starts outside; blocks
+    //that run past the end are cut short so that the pipeline can be filled from the last words.
+    //Reading a device may have side effects, so a block that starts in a device gets only that
+    //word and one that starts in memory stops before the first device. Ticks clock the devices.
+    pub fn handle(&mut self, op:MemBusOp) -> Option<MemBusOp> {
+        match op {
+            MemBusOp::RequestBlock(id, addr, n) => {
+                if !self.is_valid(addr) {
+                    return Some(MemBusOp::Error(id, format!("read from {:#x} outside of memory", addr)));
+                }
+                if n > 0 && self.devices.is_mapped(addr) {
+                    return Some(MemBusOp::GiveBlock(id, self.devices.read(addr).map(|word| (addr, word)).into_iter().collect()));
+                }
+                let mut block = Vec::new();
+                let mut a = addr;
+                while block.len() < n && self.is_valid(a) && !self.devices.is_mapped(a) {
+                    block.push((a, self.memory[a]));
+                    a = match a.checked_add(1) { Some(a) => a, None => break };
+                }
+                Some(MemBusOp::GiveBlock(id, block))
+            },
+            MemBusOp::GiveBlock(id, values) => {
+                if let Some(&(addr, _)) = values.iter().find(|&&(addr, _)| !self.is_valid(addr)) {
+                    return Some(MemBusOp::Error(id, format!("write to {:#x} outside of memory", addr)));
+                }
+                for (addr, word) in values {
+                    if !self.devices.write(addr, word) {
+                        let _ = self.memory.write(addr, word);
+                    }
+                }
+                None
+            },
+            MemBusOp::Tick => {
+                self.devices.tick();
+                None
+            },
+            MemBusOp::Error(id, err) => Some(MemBusOp::Error(id, format!("unexpected error on the memory bus: {}", err))),
+        }
+    }
+
+    //services the bus until the motherboard goes away
+    pub fn serve(mut self) {
+        while let Ok(op) = self.rx.recv() {
+            if let Some(reply) = self.handle(op) {
+                if self.tx.send(reply).is_err() {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+fn route(rx:Receiver<MemBusOp>, tx:Sender<MemBusOp>) {
+    while let Ok(op) = rx.recv() {
+        if tx.send(op).is_err() {
+            break;
+        }
     }
 }
 
@@ -47,9 +146,390 @@ impl Motherboard {
                         memory_bus:(m_mem_tx, m_mem_rx),
         }
     }
+
+    //copies the segments into ram and points the cpu at the entry
+    pub fn load(&mut self, program:&image::LoadImage) -> Result<(), image::ImageError> {
+        for seg in program.segments.iter() {
+            let end = try!(seg.addr.checked_add(seg.words.len() as u64).ok_or(image::ImageError::AddressOutOfRange(seg.addr)));
+            if end > self.memory.memory.size() {
+                return Err(image::ImageError::AddressOutOfRange(end - 1));
+            }
+            for (i, &word) in seg.words.iter().enumerate() {
+                let _ = self.memory.memory.write(seg.addr + i as u64, word);
+            }
+        }
+        self.processor.set_entry(program.entry);
+        Ok(())
+    }
+
+    //the size limit and fill value of the ram have to be set before anything is loaded
+    pub fn memory(&self) -> &memory::SparseMemory {
+        &self.memory.memory
+    }
+
+    pub fn memory_mut(&mut self) -> &mut memory::SparseMemory {
+        &mut self.memory.memory
+    }
+
+    //devices may be mapped anywhere in the address space, also beyond the end of the ram
+    pub fn map_device(&mut self, name:&str, start:u64, len:u64, dev:Box<device::Device>) -> Result<(), device::MapError> {
+        self.memory.devices.map(name, start, len, dev)
+    }
+
+    //raw, Intel HEX and native images are told apart by their content
+    pub fn boot<P: AsRef<Path>>(&mut self, path:P) -> Result<(), image::ImageError> {
+        let program = try!(image::LoadImage::load(path));
+        self.load(&program)
+    }
+
+    //the ram is served on a thread of its own and the bus is routed between it and the cpu until
+    //either side shuts down; the cpu is handed back to run the program. Caches it is given later
+    //leave the devices out.
+    pub fn start(self) -> cpu::CPU {
+        let Motherboard { mut processor, processor_bus:(to_cpu, from_cpu), memory_bus:(to_memory, from_memory), memory } = self;
+        for mapping in memory.devices.mappings() {
+            processor.add_uncached(mapping.start, mapping.len);
+        }
+        thread::spawn(move || memory.serve());
+        thread::spawn(move || route(from_cpu, to_memory));
+        thread::spawn(move || route(from_memory, to_cpu));
+        processor
+    }
+}
+
+//splits "-D NAME=VALUE" into its parts, a missing value defines NAME as 1
+fn parse_define(arg:&str) -> Result<(String, u64), parser::ParseError> {
+    match arg.find('=') {
+        Some(pos) => Ok((arg[..pos].to_string(), try!(expr::eval(&arg[pos+1..], &HashMap::new())) as u64)),
+        None => Ok((arg.to_string(), 1)),
+    }
+}
+
+//"compsim-rs link [-T SCRIPT] [-o OUT] OBJECT..." links object files into a memory image
+fn link(args:Vec<String>) -> Result<(), String> {
+    let mut script = linker::LinkerScript::new();
+    let mut output = None;
+    let mut linker_args = args.into_iter();
+    let mut objects = Vec::new();
+    while let Some(arg) = linker_args.next() {
+        match &*arg {
+            "-T" => {
+                let path = try!(linker_args.next().ok_or("-T needs a file".to_string()));
+                let mut source = String::new();
+                try!(File::open(&path).and_then(|mut f| f.read_to_string(&mut source)).map_err(|e| format!("{}: {}", path, e)));
+                script = try!(linker::LinkerScript::parse(&source).map_err(|e| format!("{}: {}", path, e)));
+            },
+            "-o" => output = Some(try!(linker_args.next().ok_or("-o needs a file".to_string()))),
+            _ => objects.push(arg),
+        }
+    }
+
+    let mut linker = linker::Linker::new(script);
+    for path in objects {
+        let file = try!(File::open(&path).map_err(|e| format!("{}: {}", path, e)));
+        linker.add(try!(object::ObjectFile::read_from(BufReader::new(file)).map_err(|e| format!("{}: {}", path, e))));
+    }
+    let linked = try!(linker.link().map_err(|e| e.to_string()));
+    if let Some(path) = output {
+        let program = image::LoadImage::from_memory_image(&linked.image, linked.entry.unwrap_or(0));
+        return program.save(&path).map_err(|e| format!("{}: {}", path, e));
+    }
+    if let Some(entry) = linked.entry {
+        println!("entry: {:#014x}", entry);
+    }
+    for (addr, word) in linked.image.iter() {
+        println!("{:#014x}: {:#018x}", addr, word);
+    }
+    Ok(())
+}
+
+fn read_debug_info(path:&str) -> Result<debuginfo::DebugInfo, String> {
+    let file = try!(File::open(path).map_err(|e| format!("{}: {}", path, e)));
+    debuginfo::DebugInfo::read_from(BufReader::new(file)).map_err(|e| format!("{}: {}", path, e))
+}
+
+//"compsim-rs boot [-n STEPS] IMAGE [DEBUGINFO]" loads a raw, Intel HEX or native image into a fresh
+//motherboard and runs STEPS instructions on every core, then prints the registers
+fn boot(mut args:&[String]) -> Result<(), String> {
+    let mut steps = BOOT_STEPS;
+    if args.len() > 2 && args[0] == "-n" {
+        steps = try!(args[1].parse().map_err(|_| format!("-n needs a number of steps, not {}", args[1])));
+        args = &args[2..];
+    }
+    let (path, debug_info) = match args.len() {
+        1 => (&args[0], None),
+        2 => (&args[0], Some(try!(read_debug_info(&args[1])))),
+        _ => return Err("boot needs an image and optionally its debug info".to_string()),
+    };
+    let describe = |addr:u64| match debug_info {
+        Some(ref info) => info.describe(addr),
+        None => format!("{:#014x}", addr),
+    };
+    let program = try!(image::LoadImage::load(path).map_err(|e| format!("{}: {}", path, e)));
+    let mut board = Motherboard::new();
+    try!(board.load(&program).map_err(|e| format!("{}: {}", path, e)));
+    let words = program.segments.iter().fold(0, |n, seg| n + seg.words.len());
+    println!("loaded {} words in {} segment(s), entry {}", words, program.segments.len(), describe(program.entry));
+    println!("memory: {}", board.memory().stats());
+
+    let mut cpu = board.start();
+    try!(cpu.run(steps));
+    let mut i = 0;
+    while let Some(core) = cpu.core(i) {
+        let registers:Vec<String> = repl::snapshot(core).into_iter().map(|(name, value)| format!("{}={}", name, value)).collect();
+        println!("core {} after {} step(s) at {}: {}", i, steps, describe(core.ISP), registers.join(" "));
+        i += 1;
+    }
+    Ok(())
+}
+
+//"compsim-rs addr2line DEBUGINFO ADDR..." prints file, line and label of every address
+fn addr2line(args:&[String]) -> Result<(), String> {
+    let info = try!(read_debug_info(&args[0]));
+    for arg in args[1..].iter() {
+        let addr = try!(expr::eval(arg, &info.symbols()).map_err(|e| e.to_string()));
+        println!("{:#014x}: {}", addr, info.describe(addr as u64));
+    }
+    Ok(())
+}
+
+//"compsim-rs cc FILE [-o IMAGE]" compiles a C-like program, without -o the assembly is printed
+fn cc(args:&[String]) -> Result<(), String> {
+    let path = &args[0];
+    let mut source = String::new();
+    try!(File::open(path).and_then(|mut f| f.read_to_string(&mut source)).map_err(|e| format!("{}: {}", path, e)));
+    match args.get(1).map(|a| &**a) {
+        Some("-o") if args.len() == 3 => {
+            let (image, labels) = try!(compiler::compile_to_image(&source).map_err(|e| format!("{}: {}", path, e)));
+            let program = image::LoadImage::from_memory_image(&image, labels["__start"]);
+            program.save(&args[2]).map_err(|e| format!("{}: {}", args[2], e))
+        },
+        None => {
+            print!("{}", try!(compiler::compile(&source).map_err(|e| format!("{}: {}", path, e))));
+            Ok(())
+        },
+        _ => Err("usage: compsim-rs cc FILE [-o IMAGE]".to_string()),
+    }
+}
+
+//"compsim-rs fmt [--check] FILE..." rewrites the files in canonical layout, - formats stdin to
+//stdout. With --check nothing is written and the first line that would change is reported.
+fn fmt(args:&[String]) -> Result<(), String> {
+    let check = args.iter().any(|a| a == "--check");
+    let formatter = formatter::Formatter::new();
+    let mut unformatted = 0;
+    for path in args.iter().filter(|a| *a != "--check") {
+        let mut source = String::new();
+        let formatted = if path == "-" {
+            try!(io::stdin().read_to_string(&mut source).map_err(|e| format!("<stdin>: {}", e)));
+            formatter.format_source(&source)
+        } else {
+            try!(File::open(path).and_then(|mut f| f.read_to_string(&mut source)).map_err(|e| format!("{}: {}", path, e)));
+            formatter.format_file(path)
+        };
+        let formatted = try!(formatted.map_err(|diagnostics| {
+            let messages:Vec<String> = diagnostics.iter().map(|d| d.to_string()).collect();
+            format!("{}\n\n{} error(s) in {}", messages.join("\n\n"), diagnostics.len(), path)
+        }));
+        if check {
+            if formatted != source {
+                let line = source.lines().zip(formatted.lines()).take_while(|&(a, b)| a == b).count() + 1;
+                println!("{}:{}: not formatted", path, line);
+                unformatted += 1;
+            }
+        } else if path == "-" {
+            print!("{}", formatted);
+        } else if formatted != source {
+            try!(File::create(path).and_then(|mut f| f.write_all(formatted.as_bytes())).map_err(|e| format!("{}: {}", path, e)));
+        }
+    }
+    if unformatted > 0 {
+        return Err(format!("{} file(s) not formatted", unformatted));
+    }
+    Ok(())
 }
 
 fn main() {
+    let mut args:Vec<String> = env::args().skip(1).collect();
+    let command = match args.get(0).map(|a| &**a) {
+        Some("link") => Some(link(args.split_off(1))),
+        Some("boot") if args.len() >= 2 => Some(boot(&args[1..])),
+        Some("addr2line") if args.len() >= 2 => Some(addr2line(&args[1..])),
+        Some("cc") if args.len() >= 2 => Some(cc(&args[1..])),
+        Some("fmt") if args.len() >= 2 => Some(fmt(&args[1..])),
+        Some("lsp") => {
+            let stdin = io::stdin();
+            let stdout = io::stdout();
+            Some(lsp::run(stdin.lock(), &mut stdout.lock()).map_err(|e| e.to_string()))
+        },
+        Some("repl") => {
+            let stdin = io::stdin();
+            let stdout = io::stdout();
+            let result = repl::Repl::new().run(stdin.lock(), &mut stdout.lock());
+            Some(result.map_err(|e| e.to_string()))
+        },
+        _ => None,
+    };
+    if let Some(result) = command {
+        if let Err(err) = result {
+            println!("error: {}", err);
+            process::exit(1);
+        }
+        return;
+    }
 
+    //-c has to be known before anything is assembled
+    let object_path = args.iter().position(|a| a == "-c").map(|pos| {
+        args.remove(pos);
+        if pos < args.len() { args.remove(pos) } else { String::new() }
+    });
+    let mut parser = if object_path.is_some() { parser::Parser::relocatable() } else { parser::Parser::new() };
+    let mut listing_path = None;
+    let mut image_path = None;
+    let mut debug_path = None;
+    let mut optimize = false;
+    let mut lint = false;
+    let mut explore = false;
+    let mut graph_path = None;
+    let mut files = Vec::new();
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        let result = if arg.starts_with("-D") {
+            let define = if arg.len() > 2 { arg[2..].to_string() } else { args.next().unwrap_or(String::new()) };
+            parse_define(&define).and_then(|(name, value)| parser.define_constant(&name, value))
+        } else if arg.starts_with("-I") {
+            let dir = if arg.len() > 2 { arg[2..].to_string() } else { args.next().unwrap_or(String::new()) };
+            parser.add_include_path(dir);
+            Ok(())
+        } else if arg == "-g" {
+            debug_path = args.next();
+            Ok(())
+        } else if arg == "-o" {
+            image_path = args.next();
+            Ok(())
+        } else if arg == "-O" {
+            optimize = true;
+            Ok(())
+        } else if arg == "-G" {
+            graph_path = args.next();
+            Ok(())
+        } else if arg == "-W" {
+            lint = true;
+            Ok(())
+        } else if arg == "-S" {
+            explore = true;
+            Ok(())
+        } else if arg == "-l" {
+            parser.enable_listing();
+            listing_path = args.next();
+            Ok(())
+        } else {
+            files.push(arg);
+            Ok(())
+        };
+        if let Err(err) = result {
+            println!("error: {}", err);
+            process::exit(1);
+        }
+    }
 
+    //listings, debug info and objects would still describe the program before it was optimized
+    let optimize_conflict = optimize && (listing_path.is_some() || debug_path.is_some() || graph_path.is_some() || object_path.is_some());
+    if files.is_empty() || object_path == Some(String::new()) || optimize_conflict {
+        println!("usage: compsim-rs [-D NAME[=VALUE]]... [-I DIR]... [-l LISTING] [-g DEBUGINFO] [-G DOTFILE] [-c OBJECT | -o IMAGE] [-O] [-W] [-S] FILE...");
+        println!("       compsim-rs link [-T SCRIPT] [-o IMAGE] OBJECT...");
+        println!("       compsim-rs boot [-n STEPS] IMAGE [DEBUGINFO]");
+        println!("       compsim-rs addr2line DEBUGINFO ADDR...");
+        println!("       compsim-rs repl");
+        println!("       compsim-rs cc FILE [-o IMAGE]");
+        println!("       compsim-rs fmt [--check] FILE...");
+        println!("       compsim-rs lsp                      language server on stdin and stdout");
+        println!("FILE - reads the program from stdin");
+        println!("images are raw (.bin), Intel HEX (.hex) or native (.img)");
+        println!("-O runs the peephole optimizer, it cannot be combined with -l, -g, -G or -c");
+        println!("-G writes the control-flow graph in Graphviz DOT format");
+        println!("-W warns about suspicious code like unreachable instructions or jumps outside of memory");
+        println!("-S executes the program symbolically and prints inputs for every path it can take");
+        process::exit(1);
+    }
+    for file in files.iter() {
+        let result = if file == "-" { parser.read_from_input() } else { parser.assemble_file(file) };
+        if let Err(diagnostics) = result {
+            for diagnostic in diagnostics.iter() {
+                println!("{}\n", diagnostic);
+            }
+            println!("{} error(s) in {}", diagnostics.len(), file);
+            process::exit(1);
+        }
+    }
+    if lint && object_path.is_none() {
+        let info = parser.debug_info();
+        let mut linter = lint::Linter::new(parser.words(), parser.labels());
+        for lint in linter.set_memory_size(RAM_SIZE).run() {
+            println!("{}: warning: {}", info.describe(lint.addr), lint.kind);
+        }
+    }
+    if explore && object_path.is_none() {
+        let info = parser.debug_info();
+        let entry = parser.labels().get("start").cloned().or(parser.image().keys().next().cloned()).unwrap_or(0);
+        let mut executor = symbolic::Executor::new(parser.image(), entry);
+        let paths = executor.set_memory_size(RAM_SIZE).run();
+        for (i, path) in paths.iter().enumerate() {
+            let inputs:Vec<String> = path.inputs.iter().map(|&(ref name, value)| format!("{}={}", name, value as i64)).collect();
+            let inputs = if inputs.is_empty() { "any input".to_string() } else { inputs.join(" ") };
+            println!("path {}: {}: {} after {} step(s) with {}", i + 1, info.describe(path.end.addr()), path.end, path.steps, inputs);
+        }
+    }
+    if let Some(path) = graph_path {
+        let entry = parser.labels().get("start").cloned().or(parser.image().keys().next().cloned()).unwrap_or(0);
+        let graph = cfg::Cfg::new(&parser.words(), parser.labels(), entry);
+        if let Err(err) = File::create(&path).and_then(|mut f| f.write_all(graph.to_dot().as_bytes())) {
+            println!("error: {}: {}", path, err);
+            process::exit(1);
+        }
+    }
+    if let (Some(path), Some(listing)) = (listing_path, parser.listing()) {
+        if let Err(err) = File::create(&path).and_then(|mut f| writeln!(f, "{}", listing)) {
+            println!("error: {}: {}", path, err);
+            process::exit(1);
+        }
+    }
+    if let Some(path) = debug_path {
+        if let Err(err) = File::create(&path).and_then(|mut f| parser.debug_info().write_to(&mut f)) {
+            println!("error: {}: {}", path, err);
+            process::exit(1);
+        }
+    }
+    if let Some(path) = object_path {
+        let written = File::create(&path).and_then(|mut f| parser.into_object().write_to(&mut f));
+        if let Err(err) = written {
+            println!("error: {}: {}", path, err);
+            process::exit(1);
+        }
+        return;
+    }
+    let (program, labels) = if optimize {
+        let mut optimizer = optimizer::Optimizer::new(parser.words(), parser.labels().clone());
+        for optimization in optimizer.run() {
+            println!("{}", optimization);
+        }
+        (optimizer.image(), optimizer.labels().clone())
+    } else {
+        (parser.image().clone(), parser.labels().clone())
+    };
+    if let Some(path) = image_path {
+        //programs start at the label start if there is one, otherwise at their lowest address
+        let entry = labels.get("start").cloned()
+            .or(program.keys().next().cloned())
+            .unwrap_or(0);
+        if let Err(err) = image::LoadImage::from_memory_image(&program, entry).save(&path) {
+            println!("error: {}: {}", path, err);
+            process::exit(1);
+        }
+        return;
+    }
+    for (addr, word) in program.iter() {
+        println!("{:#014x}: {:#018x}", addr, word);
+    }
 }
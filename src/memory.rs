@@ -0,0 +1,140 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::ops::Index;
+use expr::MAX_ADDR;
+
+pub const PAGE_WORDS:u64 = 4096;
+
+//word addressed memory that only allocates the pages that were written to, so that the whole
+//52 bit address space of the instructions can be used. Untouched words read as the fill value.
+#[derive(Debug, Clone)]
+pub struct SparseMemory {
+    pages:BTreeMap<u64, Box<[u64]>>,
+    size:u64,
+    fill:u64,
+    peak_pages:usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MemoryStats {
+    pub resident_pages:usize,
+    pub peak_pages:usize,
+    pub resident_bytes:u64,
+}
+
+impl fmt::Display for MemoryStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} resident page(s) ({} bytes), at most {}", self.resident_pages, self.resident_bytes, self.peak_pages)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MemoryError {
+    OutOfRange(u64),
+}
+
+impl fmt::Display for MemoryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MemoryError::OutOfRange(addr) => write!(f, "OutOfRange: {:#x} is outside of memory", addr),
+        }
+    }
+}
+
+impl Error for MemoryError {
+    fn description(&self) -> &str {
+        match *self {
+            MemoryError::OutOfRange(_) => "address out of range",
+        }
+    }
+
+    fn cause(&self) -> Option<&Error> {
+        None
+    }
+}
+
+impl SparseMemory {
+
+    pub fn new() -> SparseMemory {
+        SparseMemory { pages:BTreeMap::new(), size:MAX_ADDR + 1, fill:0, peak_pages:0 }
+    }
+
+    //pages beyond the new size are dropped
+    pub fn set_size(&mut self, _size:u64) -> &mut SparseMemory {
+        self.size = _size.min(MAX_ADDR + 1);
+        let first_outside = (self.size + PAGE_WORDS - 1) / PAGE_WORDS;
+        self.pages.split_off(&first_outside);
+        if let Some(page) = self.pages.get_mut(&(self.size / PAGE_WORDS)) {
+            for word in page[(self.size % PAGE_WORDS) as usize..].iter_mut() {
+                *word = self.fill;
+            }
+        }
+        self
+    }
+
+    //only affects pages that are not resident yet
+    pub fn set_fill(&mut self, _fill:u64) -> &mut SparseMemory {
+        self.fill = _fill;
+        self
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn read(&self, addr:u64) -> Result<u64, MemoryError> {
+        if addr >= self.size {
+            return Err(MemoryError::OutOfRange(addr));
+        }
+        Ok(self.pages.get(&(addr / PAGE_WORDS)).map(|page| page[(addr % PAGE_WORDS) as usize]).unwrap_or(self.fill))
+    }
+
+    //even writing the fill value makes the page resident, the fill may change later on
+    pub fn write(&mut self, addr:u64, word:u64) -> Result<(), MemoryError> {
+        if addr >= self.size {
+            return Err(MemoryError::OutOfRange(addr));
+        }
+        let page = addr / PAGE_WORDS;
+        if !self.pages.contains_key(&page) {
+            self.pages.insert(page, vec![self.fill; PAGE_WORDS as usize].into_boxed_slice());
+            self.peak_pages = self.peak_pages.max(self.pages.len());
+        }
+        self.pages.get_mut(&page).unwrap()[(addr % PAGE_WORDS) as usize] = word;
+        Ok(())
+    }
+
+    //pages that only hold the fill value any more are given back
+    pub fn trim(&mut self) {
+        let fill = self.fill;
+        let unused:Vec<u64> = self.pages.iter().filter(|&(_, page)| page.iter().all(|&word| word == fill)).map(|(&n, _)| n).collect();
+        for n in unused {
+            self.pages.remove(&n);
+        }
+    }
+
+    //the first address of every resident page
+    pub fn resident(&self) -> Vec<u64> {
+        self.pages.keys().map(|n| n * PAGE_WORDS).collect()
+    }
+
+    pub fn stats(&self) -> MemoryStats {
+        MemoryStats {
+            resident_pages:self.pages.len(),
+            peak_pages:self.peak_pages,
+            resident_bytes:self.pages.len() as u64 * PAGE_WORDS * 8,
+        }
+    }
+}
+
+//like a slice, indexing outside of memory panics
+impl Index<u64> for SparseMemory {
+    type Output = u64;
+
+    fn index(&self, addr:u64) -> &u64 {
+        if addr >= self.size {
+            panic!("index {:#x} is outside of memory of size {:#x}", addr, self.size);
+        }
+        self.pages.get(&(addr / PAGE_WORDS)).map(|page| &page[(addr % PAGE_WORDS) as usize]).unwrap_or(&self.fill)
+    }
+}
@@ -0,0 +1,152 @@
+use std::io::{Write, BufRead};
+use std::io;
+use linker::LinkError;
+
+const MAGIC:&'static str = "COMPSIM-OBJ 1";
+
+//what a relocation patches: the 52 bit address field of an instruction or a whole .word
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RelocKind {
+    Addr,
+    Word,
+}
+
+//a relocation is either relative to the start of a section of the same module or to a
+//symbol that another module has to define
+#[derive(Debug, Clone, PartialEq)]
+pub enum RelocTarget {
+    Section(String),
+    Symbol(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Relocation {
+    pub offset:u64,
+    pub kind:RelocKind,
+    pub target:RelocTarget,
+    pub addend:i64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Section {
+    pub name:String,
+    pub words:Vec<u64>,
+    pub relocations:Vec<Relocation>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Symbol {
+    pub name:String,
+    pub section:Option<String>, //None for absolute constants
+    pub value:u64,              //offset into the section
+    pub global:bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ObjectFile {
+    pub sections:Vec<Section>,
+    pub symbols:Vec<Symbol>,
+}
+
+impl ObjectFile {
+
+    pub fn new() -> ObjectFile {
+        ObjectFile{ sections:Vec::new(), symbols:Vec::new() }
+    }
+
+    pub fn section(&self, name:&str) -> Option<&Section> {
+        self.sections.iter().find(|s| s.name == name)
+    }
+
+    //a line based text format so that students can read object files with any editor:
+    //  SECTION <name> <number of words>, followed by one hex word per line
+    //  RELOC <offset> ADDR|WORD SECTION|SYMBOL <name> <addend>, belonging to the last section
+    //  SYMBOL <name> <section>|* <value> GLOBAL|LOCAL
+    pub fn write_to<W: Write>(&self, out:&mut W) -> io::Result<()> {
+        try!(writeln!(out, "{}", MAGIC));
+        for section in self.sections.iter() {
+            try!(writeln!(out, "SECTION {} {}", section.name, section.words.len()));
+            for word in section.words.iter() {
+                try!(writeln!(out, "{:016x}", word));
+            }
+            for reloc in section.relocations.iter() {
+                let kind = match reloc.kind { RelocKind::Addr => "ADDR", RelocKind::Word => "WORD" };
+                let (target_kind, target) = match reloc.target {
+                    RelocTarget::Section(ref name) => ("SECTION", name),
+                    RelocTarget::Symbol(ref name) => ("SYMBOL", name),
+                };
+                try!(writeln!(out, "RELOC {} {} {} {} {}", reloc.offset, kind, target_kind, target, reloc.addend));
+            }
+        }
+        for symbol in self.symbols.iter() {
+            try!(writeln!(out, "SYMBOL {} {} {} {}",
+                          symbol.name,
+                          symbol.section.as_ref().map(|s| &**s).unwrap_or("*"),
+                          symbol.value,
+                          if symbol.global { "GLOBAL" } else { "LOCAL" }));
+        }
+        Ok(())
+    }
+
+    pub fn read_from<R: BufRead>(input:R) -> Result<ObjectFile, LinkError> {
+        let mut obj = ObjectFile::new();
+        let mut lines = input.lines().enumerate();
+        let malformed = |line_no:usize, msg:&str| LinkError::MalformedObject(format!("line {}: {}", line_no + 1, msg));
+
+        match lines.next() {
+            Some((_, Ok(ref line))) if line.trim() == MAGIC => {},
+            _ => return Err(malformed(0, "not a compsim object file")),
+        }
+        while let Some((line_no, line)) = lines.next() {
+            let line = try!(line.map_err(|e| LinkError::MalformedObject(e.to_string())));
+            let fields:Vec<&str> = line.split_whitespace().collect();
+            match (fields.get(0).cloned(), fields.len()) {
+                (None, _) => {},
+                (Some("SECTION"), 3) => {
+                    let n = try!(fields[2].parse::<usize>().map_err(|_| malformed(line_no, "invalid section size")));
+                    let mut words = Vec::new();
+                    for _ in 0..n {
+                        let word = match lines.next() {
+                            Some((_, Ok(word))) => try!(u64::from_str_radix(word.trim(), 16).map_err(|_| malformed(line_no, "invalid word"))),
+                            _ => return Err(malformed(line_no, "section ends early")),
+                        };
+                        words.push(word);
+                    }
+                    obj.sections.push(Section{ name:fields[1].to_string(), words:words, relocations:Vec::new() });
+                },
+                (Some("RELOC"), 6) => {
+                    let kind = match fields[2] {
+                        "ADDR" => RelocKind::Addr,
+                        "WORD" => RelocKind::Word,
+                        _ => return Err(malformed(line_no, "invalid relocation kind")),
+                    };
+                    let target = match fields[3] {
+                        "SECTION" => RelocTarget::Section(fields[4].to_string()),
+                        "SYMBOL" => RelocTarget::Symbol(fields[4].to_string()),
+                        _ => return Err(malformed(line_no, "invalid relocation target")),
+                    };
+                    let reloc = Relocation{
+                        offset:try!(fields[1].parse().map_err(|_| malformed(line_no, "invalid offset"))),
+                        kind:kind,
+                        target:target,
+                        addend:try!(fields[5].parse().map_err(|_| malformed(line_no, "invalid addend"))),
+                    };
+                    match obj.sections.last_mut() {
+                        Some(section) => section.relocations.push(reloc),
+                        None => return Err(malformed(line_no, "relocation outside of a section")),
+                    }
+                },
+                (Some("SYMBOL"), 5) => {
+                    obj.symbols.push(Symbol{
+                        name:fields[1].to_string(),
+                        section:if fields[2] == "*" { None } else { Some(fields[2].to_string()) },
+                        value:try!(fields[3].parse().map_err(|_| malformed(line_no, "invalid symbol value"))),
+                        global:fields[4] == "GLOBAL",
+                    });
+                },
+                _ => return Err(malformed(line_no, "unknown record")),
+            }
+        }
+        Ok(obj)
+    }
+}
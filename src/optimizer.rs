@@ -0,0 +1,244 @@
+use std::collections::{HashMap, HashSet, BTreeMap};
+use std::fmt;
+use expr::MAX_ADDR;
+use parser::MemoryImage;
+use utils::{Instruction, Opcode, Reg};
+
+//what the optimizer needs to know about a word: instructions can be rewritten or removed, words
+//holding the address of a label move along with the label, anything else is left alone
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Word {
+    Instr(Instruction),
+    Addr(u64),
+    Data(u64),
+}
+
+impl Word {
+    pub fn value(&self) -> u64 {
+        match *self {
+            Word::Instr(instr)  => instr.0,
+            Word::Addr(value)   => value,
+            Word::Data(value)   => value,
+        }
+    }
+}
+
+//one entry of the report, addresses are those of the program before it was optimized
+#[derive(Debug, Clone, PartialEq)]
+pub enum Optimization {
+    PushPop(u64, Reg),
+    Nop(u64),
+    JumpToNext(u64, Instruction),
+    Shift(u64, Reg, usize), //only reported, the ISA has no shift that could replace the ADDs
+}
+
+impl fmt::Display for Optimization {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Optimization::PushPop(addr, reg)        => write!(f, "{:#014x}: removed PUSH {} POP {}", addr, reg, reg),
+            Optimization::Nop(addr)                 => write!(f, "{:#014x}: removed NOP", addr),
+            Optimization::JumpToNext(addr, instr)   => write!(f, "{:#014x}: removed {}, it jumps to the next instruction", addr, instr),
+            Optimization::Shift(addr, reg, n)       => write!(f, "{:#014x}: ADD {} {} x{} shifts {} left by {}", addr, reg, reg, n, reg, n),
+        }
+    }
+}
+
+//peephole optimizer: removes PUSH r POP r pairs, NOPs and jumps to the next instruction and moves
+//labels, jump targets and address words along. Nothing is removed if the flags it leaves behind
+//could still be read, if something jumps into the middle of a pair or if the program reads or
+//patches the word as data.
+pub struct Optimizer {
+    words:Vec<(u64, u64, Word)>, //current address, original address, word
+    labels:HashMap<String, u64>,
+    report:Vec<Optimization>,
+}
+
+impl Optimizer {
+
+    pub fn new(_words:BTreeMap<u64, Word>, _labels:HashMap<String, u64>) -> Optimizer {
+        Optimizer {
+            words:_words.into_iter().map(|(addr, word)| (addr, addr, word)).collect(),
+            labels:_labels,
+            report:Vec::new(),
+        }
+    }
+
+    //every word is taken for an instruction, so the list must not contain data
+    pub fn from_instructions(base:u64, code:Vec<Instruction>, _labels:HashMap<String, u64>) -> Optimizer {
+        let words = code.into_iter().enumerate().map(|(i, instr)| (base + i as u64, Word::Instr(instr))).collect();
+        Optimizer::new(words, _labels)
+    }
+
+    pub fn run(&mut self) -> &[Optimization] {
+        while self.pass() {}
+        self.find_shifts();
+        &self.report
+    }
+
+    pub fn report(&self) -> &[Optimization] {
+        &self.report
+    }
+
+    pub fn labels(&self) -> &HashMap<String, u64> {
+        &self.labels
+    }
+
+    pub fn image(&self) -> MemoryImage {
+        self.words.iter().map(|&(addr, _, word)| (addr, word.value())).collect()
+    }
+
+    pub fn instructions(&self) -> Vec<Instruction> {
+        self.words.iter().map(|&(_, _, word)| Instruction(word.value())).collect()
+    }
+
+    fn instr(&self, i:usize) -> Option<Instruction> {
+        match self.words[i].2 {
+            Word::Instr(instr) if instr.is_valid() => Some(instr),
+            _ => None,
+        }
+    }
+
+    //the word executed after word i if the program falls through, skipping removed words
+    fn next(&self, i:usize, removed:&HashSet<usize>) -> Option<usize> {
+        let mut j = i + 1;
+        while j < self.words.len() && self.words[j].0 == self.words[j - 1].0 + 1 {
+            if !removed.contains(&j) {
+                return Some(j);
+            }
+            j += 1;
+        }
+        None
+    }
+
+    //every instruction but the jumps, CALL and RET sets all flags
+    fn sets_flags(&self, i:Option<usize>, data:&HashSet<u64>) -> bool {
+        let i = match i {
+            Some(i) if !data.contains(&self.words[i].0) => i,
+            _ => return false,
+        };
+        match self.instr(i).map(|instr| instr.opcode()) {
+            Some(Opcode::Jz) | Some(Opcode::Jgz) | Some(Opcode::Jlz) | Some(Opcode::Call) | Some(Opcode::Ret) | None => false,
+            _ => true,
+        }
+    }
+
+    fn pass(&mut self) -> bool {
+        //addresses something can jump to and words the program uses as data
+        let mut targets:HashSet<u64> = self.labels.values().cloned().collect();
+        let mut data = HashSet::new();
+        for &(_, _, word) in self.words.iter() {
+            match word {
+                Word::Addr(addr) => { targets.insert(addr); },
+                Word::Instr(instr) if has_addr(instr) => match instr.opcode() {
+                    Opcode::Ld | Opcode::Sav => { data.insert(instr.addr()); },
+                    _ => { targets.insert(instr.addr()); },
+                },
+                _ => {},
+            }
+        }
+
+        //back to front, so that the word after the current one is already decided
+        let mut removed = HashSet::new();
+        let mut report = Vec::new();
+        for i in (0..self.words.len()).rev() {
+            let instr = match self.instr(i) {
+                Some(instr) if !removed.contains(&i) && !data.contains(&self.words[i].0) => instr,
+                _ => continue,
+            };
+            let next = self.next(i, &removed);
+            match instr.opcode() {
+                Opcode::Nop if self.sets_flags(next, &data) => {
+                    removed.insert(i);
+                    report.push(Optimization::Nop(self.words[i].1));
+                },
+                Opcode::Jz | Opcode::Jgz | Opcode::Jlz if self.sets_flags(next, &data) => {
+                    //anything between the jump and the next word is removed already
+                    let next_addr = self.words[next.unwrap()].0;
+                    if instr.addr() > self.words[i].0 && instr.addr() <= next_addr {
+                        removed.insert(i);
+                        report.push(Optimization::JumpToNext(self.words[i].1, instr));
+                    }
+                },
+                Opcode::Push if next == Some(i + 1) && instr.reg1() != Reg::ESP && instr.reg1() != Reg::ISP => {
+                    let pop = Instruction(self.words[i + 1].2.value());
+                    let pop_addr = self.words[i + 1].0;
+                    let pairs = self.instr(i + 1) == Some(pop) && pop.opcode() == Opcode::Pop && pop.reg1() == instr.reg1();
+                    if pairs && !targets.contains(&pop_addr) && !data.contains(&pop_addr) && self.sets_flags(self.next(i + 1, &removed), &data) {
+                        removed.insert(i);
+                        removed.insert(i + 1);
+                        report.push(Optimization::PushPop(self.words[i].1, instr.reg1()));
+                    }
+                },
+                _ => {},
+            }
+        }
+        if removed.is_empty() {
+            return false;
+        }
+        report.reverse();
+        self.report.extend(report);
+
+        //words after a removed one move down, but only up to the next gap in the image
+        let mut moved = HashMap::new();
+        let mut shift = 0;
+        for i in 0..self.words.len() {
+            let addr = self.words[i].0;
+            if i > 0 && addr != self.words[i - 1].0 + 1 {
+                moved.insert(self.words[i - 1].0 + 1, self.words[i - 1].0 + 1 - shift);
+                shift = 0;
+            }
+            moved.insert(addr, addr - shift);
+            if removed.contains(&i) {
+                shift += 1;
+            }
+        }
+        if let Some(&(last, _, _)) = self.words.last() {
+            moved.insert(last + 1, last + 1 - shift);
+        }
+
+        let relocate = |addr:u64| moved.get(&addr).cloned().unwrap_or(addr);
+        let mut words = Vec::new();
+        for (i, &(addr, origin, word)) in self.words.iter().enumerate() {
+            if removed.contains(&i) {
+                continue;
+            }
+            let word = match word {
+                Word::Addr(target) => Word::Addr(relocate(target)),
+                Word::Instr(instr) if has_addr(instr) => Word::Instr(Instruction((instr.0 & !MAX_ADDR) | relocate(instr.addr()))),
+                word => word,
+            };
+            words.push((relocate(addr), origin, word));
+        }
+        self.words = words;
+        for addr in self.labels.values_mut() {
+            *addr = relocate(*addr);
+        }
+        true
+    }
+
+    fn find_shifts(&mut self) {
+        let mut i = 0;
+        while i < self.words.len() {
+            let shift = match self.instr(i) {
+                Some(instr) if instr.opcode() == Opcode::Add && instr.reg1() == instr.reg2() => instr,
+                _ => { i += 1; continue },
+            };
+            let start = i;
+            while i < self.words.len() && self.instr(i) == Some(shift) && self.words[i].0 == self.words[start].0 + (i - start) as u64 {
+                i += 1;
+            }
+            self.report.push(Optimization::Shift(self.words[start].1, shift.reg1(), i - start));
+        }
+    }
+}
+
+//the instructions with an address operant, LD and SAV read and write data, the others jump
+fn has_addr(instr:Instruction) -> bool {
+    if !instr.is_valid() {
+        return false;
+    }
+    match instr.opcode() {
+        Opcode::Ld | Opcode::Sav | Opcode::Jz | Opcode::Jgz | Opcode::Jlz | Opcode::Call => true,
+        _ => false,
+    }
+}
@@ -3,70 +3,629 @@ extern crate rand;
 use std::io::{stdin, BufRead};
 use std::io::Read;
 use std::fs::File;
+use std::path::Path;
 use std::vec::IntoIter;
 use std::i64;
 use std::error::*;
 use std::num::*;
 use std::fmt;
-use utils::{Instruction, Reg};
+use std::collections::{HashMap, BTreeMap};
+use std::cell::RefCell;
+use utils::{Instruction, Reg, parse_instruction, strip_comment, split_operants};
+use expr;
+use preprocessor::{Preprocessor, SourceLine, split_directive};
+use diagnostic::Diagnostic;
+use listing::{Listing, ListingLine};
+use debuginfo::DebugInfo;
+use optimizer::Word;
+use object::{ObjectFile, Section, Symbol, Relocation, RelocKind, RelocTarget};
 
 
 
+pub type MemoryImage = BTreeMap<u64, u64>;
+
+const DEFAULT_SECTION:&'static str = ".text";
+const RELOC_PROBE:i64 = 1 << 32; //shift applied to a section or symbol to see how an expression depends on it
+const MAX_PADDING:u64 = 1 << 24; //.zero, .align and relocatable sections are stored word by word
+
 #[derive(Debug, Clone)]
 pub struct Parser {
     buffer:Vec<i64>,
-    program:Vec<Instruction>,
+    image:MemoryImage,
+    labels:HashMap<String, u64>,
+    sections:HashMap<String, u64>, //location counter of every section
+    section_order:Vec<String>,
+    cur_section:String,
+    preprocessor:Preprocessor,
+    //only used when assembling into an object file
+    relocatable:bool,
+    label_sections:HashMap<String, String>,
+    globals:Vec<String>,
+    section_images:HashMap<String, MemoryImage>,
+    relocations:Vec<(String, Relocation)>,
+    listing:Option<Vec<ListingLine>>,
+    debug_info:DebugInfo,
+    data_words:HashMap<u64, bool>, //words from directives, true if they hold the address of a label
+}
+
+//a source line after the first pass, waiting to be encoded at its address
+#[derive(Debug)]
+enum Statement<'a> {
+    Instr(&'a str),
+    Words(Vec<&'a str>),
+    Data(Vec<u64>),
+}
+
+impl<'a> Statement<'a> {
+    fn size(&self) -> u64 {
+        match *self {
+            Statement::Instr(_)             => 1,
+            Statement::Words(ref words)     => words.len() as u64,
+            Statement::Data(ref data)       => data.len() as u64,
+        }
+    }
 }
 
 impl Parser {
     
     pub fn new() -> Parser {
+        let mut sections = HashMap::new();
+        sections.insert(DEFAULT_SECTION.to_string(), 0);
         Parser{
             buffer:Vec::new(),
-            program:Vec::new(),
+            image:MemoryImage::new(),
+            labels:HashMap::new(),
+            sections:sections,
+            section_order:vec![DEFAULT_SECTION.to_string()],
+            cur_section:DEFAULT_SECTION.to_string(),
+            preprocessor:Preprocessor::new(),
+            relocatable:false,
+            label_sections:HashMap::new(),
+            globals:Vec::new(),
+            section_images:HashMap::new(),
+            relocations:Vec::new(),
+            listing:None,
+            debug_info:DebugInfo::new(),
+            data_words:HashMap::new(),
+        }
+    }
+
+    //remembers every line from now on together with the words it became
+    pub fn enable_listing(&mut self) {
+        if self.listing.is_none() {
+            self.listing = Some(Vec::new());
+        }
+    }
+
+    pub fn listing(&self) -> Option<Listing> {
+        self.listing.as_ref().map(|lines| Listing::new(lines.clone(), &self.labels, &self.label_sections))
+    }
+
+    //source line of every word and the code labels; only for flat images, in object files the
+    //addresses are not known yet
+    pub fn debug_info(&self) -> DebugInfo {
+        let mut info = self.debug_info.clone();
+        for (name, &addr) in self.labels.iter().filter(|&(name, _)| self.label_sections.contains_key(name)) {
+            info.add_symbol(addr, name);
         }
+        info
     }
 
-    pub fn read_from_input(&mut self) {
-        let mut input_buffer = String::new();
-        let stdin =  stdin();
-        let mut handle = stdin.lock();
+    fn list(&mut self, entry:usize, addr:u64, words:&[u64]) {
+        if let Some(ref mut listing) = self.listing {
+            listing[entry].addr = Some(addr);
+            listing[entry].words.extend_from_slice(words);
+        }
+    }
 
+    //labels are kept relative to their section and references to them or to undefined symbols
+    //become relocations, see into_object
+    pub fn relocatable() -> Parser {
+        let mut parser = Parser::new();
+        parser.relocatable = true;
+        parser
+    }
+
+    pub fn into_object(self) -> ObjectFile {
+        let mut obj = ObjectFile::new();
+        for name in self.section_order.iter() {
+            let image = self.section_images.get(name).cloned().unwrap_or(MemoryImage::new());
+            let len = image.keys().next_back().map(|&addr| addr + 1).unwrap_or(0).max(self.sections[name]);
+            let mut words = vec![0; len as usize];
+            for (addr, word) in image {
+                words[addr as usize] = word;
+            }
+            obj.sections.push(Section{
+                name:name.clone(),
+                words:words,
+                relocations:self.relocations.iter().filter(|r| &r.0 == name).map(|r| r.1.clone()).collect(),
+            });
+        }
+        for (name, &value) in self.labels.iter() {
+            obj.symbols.push(Symbol{
+                name:name.clone(),
+                section:self.label_sections.get(name).cloned(),
+                value:value,
+                global:self.globals.contains(name),
+            });
+        }
+        obj.symbols.sort_by(|a, b| a.name.cmp(&b.name));
+        obj
+    }
+
+    //reads stdin up to EOF, so that programs can be piped in
+    pub fn read_from_input(&mut self) -> Result<(), Vec<Diagnostic>> {
+        let stdin = stdin();
+        let handle = stdin.lock();
+        self.read_from_reader(handle, "<stdin>")
+    }
 
-        while let Ok(s) = handle.read_line(&mut input_buffer) {
-            self.program.push(input_buffer.parse().expect("Parsing line failed: "));
+    //every line is read exactly once; assembling has to wait for the end of the input because
+    //labels may be used before they are defined
+    pub fn read_from_reader<R: BufRead>(&mut self, input:R, name:&str) -> Result<(), Vec<Diagnostic>> {
+        let mut source = String::new();
+        for line in input.lines() {
+            match line {
+                Ok(line) => {
+                    source.push_str(&line);
+                    source.push('\n');
+                },
+                Err(err) => return Err(vec![Diagnostic::without_line(name, ParseError::Io(err.to_string()))]),
+            }
         }
+        let (lines, diagnostics) = self.preprocessor.process(&source, name);
+        self.assemble_lines(lines, diagnostics)
     }
 
+    //all words of the image in address order, gaps between them are dropped
     pub fn into_instructions(self) -> IntoIter<Instruction> {
-        self.program.into_iter()
+        self.image.values().map(|&word| Instruction(word)).collect::<Vec<_>>().into_iter()
     }
 
-    pub fn read_from_file(&mut self, _f:&mut File) {
+    pub fn into_image(self) -> MemoryImage {
+        self.image
+    }
+
+    pub fn image(&self) -> &MemoryImage {
+        &self.image
+    }
+
+    pub fn read_from_file(&mut self, _f:&mut File) -> Result<(), Vec<Diagnostic>> {
         let mut file_as_string = String::new();
-        _f.read_to_string(&mut file_as_string);
-        println!("{:?}", file_as_string);
-        for line in file_as_string.lines() {
-            self.program
-                .push(line.parse().expect("Parsing line failed: "));
+        if let Err(err) = _f.read_to_string(&mut file_as_string) {
+            return Err(vec![Diagnostic::without_line("<file>", ParseError::Io(err.to_string()))]);
+        }
+        self.assemble(&file_as_string)
+    }
+
+    //the image with instructions told apart from data, as the optimizer needs it
+    pub fn words(&self) -> BTreeMap<u64, Word> {
+        self.image.iter().map(|(&addr, &value)| {
+            let word = match self.data_words.get(&addr) {
+                Some(&true)     => Word::Addr(value),
+                Some(&false)    => Word::Data(value),
+                None            => Word::Instr(Instruction(value)),
+            };
+            (addr, word)
+        }).collect()
+    }
+
+    pub fn labels(&self) -> &HashMap<String, u64> {
+        &self.labels
+    }
+
+    fn location(&self) -> u64 {
+        self.sections[&self.cur_section]
+    }
+
+    fn advance(&mut self, n:u64) {
+        *self.sections.get_mut(&self.cur_section).unwrap() += n;
+    }
+
+    fn define_label(&mut self, name:&str) -> Result<(), ParseError> {
+        let addr = self.location();
+        try!(self.define(name, addr));
+        self.label_sections.insert(name.to_string(), self.cur_section.clone());
+        Ok(())
+    }
+
+    fn define(&mut self, name:&str, value:u64) -> Result<(), ParseError> {
+        if self.labels.contains_key(name) {
+            return Err(ParseError::DuplicateLabel(name.to_string()));
         }
+        self.labels.insert(name.to_string(), value);
+        Ok(())
+    }
+
+    //two passes: the first one assigns an address to every label and statement, the second one 
+    //encodes the statements with all label references resolved
+    pub fn assemble(&mut self, source:&str) -> Result<(), Vec<Diagnostic>> {
+        let (lines, diagnostics) = self.preprocessor.process(source, "<input>");
+        self.assemble_lines(lines, diagnostics)
+    }
+
+    pub fn assemble_file<P: AsRef<Path>>(&mut self, path:P) -> Result<(), Vec<Diagnostic>> {
+        let (lines, diagnostics) = self.preprocessor.process_file(path.as_ref());
+        self.assemble_lines(lines, diagnostics)
+    }
+
+    pub fn add_include_path<P: AsRef<Path>>(&mut self, dir:P) {
+        self.preprocessor.add_include_path(dir);
+    }
+
+    //constants from the command line (-D NAME=VALUE), visible to .if/.ifdef and to the program
+    pub fn define_constant(&mut self, name:&str, value:u64) -> Result<(), ParseError> {
+        try!(self.define(name, value));
+        self.preprocessor.define_constant(name, value);
+        Ok(())
+    }
+
+    //every line is assembled even if an earlier one failed, so that all errors are reported at once
+    fn assemble_lines(&mut self, lines:Vec<SourceLine>, mut diagnostics:Vec<Diagnostic>) -> Result<(), Vec<Diagnostic>> {
+        let mut statements:Vec<(String, u64, Statement, &SourceLine, usize)> = Vec::new();
+        let first_entry = self.listing.as_ref().map(|listing| listing.len()).unwrap_or(0);
+
+        for (i, line) in lines.iter().enumerate() {
+            if let Some(ref mut listing) = self.listing {
+                listing.push(ListingLine::new(line.clone()));
+            }
+            let mut rest = &*line.text;
+            if split_label(rest).is_some() {
+                let addr = self.location();
+                self.list(first_entry + i, addr, &[]);
+            }
+            while let Some((label, tail)) = split_label(rest) {
+                if let Err(err) = self.define_label(label) {
+                    diagnostics.push(Diagnostic::new(line, err));
+                }
+                rest = tail;
+            }
+            if rest.is_empty() {
+                continue;
+            }
+            let statement = if rest.starts_with('.') {
+                match self.directive(rest) {
+                    Ok(Some(statement)) => statement,
+                    Ok(None) => continue,
+                    Err(err) => { diagnostics.push(Diagnostic::new(line, err)); continue },
+                }
+            } else {
+                Statement::Instr(rest)
+            };
+            let addr = self.location();
+            if self.relocatable && statement.size() > MAX_PADDING - addr.min(MAX_PADDING) {
+                diagnostics.push(Diagnostic::new(line, ParseError::TooMuchData(statement.size())));
+                continue;
+            }
+            self.advance(statement.size());
+            statements.push((self.cur_section.clone(), addr, statement, line, first_entry + i));
+        }
+
+        for (section, addr, statement, line, entry) in statements {
+            let data_words = match statement {
+                Statement::Instr(_)             => Vec::new(),
+                Statement::Words(ref operants)  => operants.iter().map(|operant| self.refers_to_label(operant)).collect(),
+                Statement::Data(ref data)       => vec![false; data.len()],
+            };
+            let (words, relocations) = match self.encode(statement, addr) {
+                Ok(res) => res,
+                Err(err) => { diagnostics.push(Diagnostic::new(line, err)); continue },
+            };
+            self.list(entry, addr, &words);
+            self.relocations.extend(relocations.into_iter().map(|r| (section.clone(), r)));
+            let image = if self.relocatable {
+                self.section_images.entry(section).or_insert(MemoryImage::new())
+            } else {
+                &mut self.image
+            };
+            for (i, word) in words.into_iter().enumerate() {
+                if !self.relocatable {
+                    let (file, line_no) = line.origin();
+                    self.debug_info.add_line(addr + i as u64, file, line_no);
+                    if let Some(&refers) = data_words.get(i) {
+                        self.data_words.insert(addr + i as u64, refers);
+                    }
+                }
+                if image.insert(addr + i as u64, word).is_some() {
+                    diagnostics.push(Diagnostic::new(line, ParseError::OverlappingData(addr + i as u64)));
+                }
+            }
+        }
+
+        if diagnostics.is_empty() {
+            Ok(())
+        } else {
+            Err(diagnostics)
+        }
+    }
+
+    fn encode(&self, statement:Statement, addr:u64) -> Result<(Vec<u64>, Vec<Relocation>), ParseError> {
+        let relocations = RefCell::new(Vec::new());
+        let words = match statement {
+            Statement::Instr(line) => {
+                vec![try!(parse_instruction(line, &|operant: &str| self.resolve(operant, addr, RelocKind::Addr, &relocations))).0]
+            },
+            Statement::Words(operants) => {
+                let mut words = Vec::new();
+                for (i, operant) in operants.into_iter().enumerate() {
+                    words.push(try!(self.resolve(operant, addr + i as u64, RelocKind::Word, &relocations)));
+                }
+                words
+            },
+            Statement::Data(data) => data,
+        };
+        Ok((words, relocations.into_inner()))
+    }
+
+    fn resolve(&self, operant:&str, offset:u64, kind:RelocKind, relocations:&RefCell<Vec<Relocation>>) -> Result<u64, ParseError> {
+        let (value, target) = if self.relocatable {
+            try!(self.relocatable_value(operant))
+        } else {
+            (try!(expr::eval(operant, &self.labels)), None)
+        };
+        match (target, kind) {
+            (Some(target), _) => {
+                relocations.borrow_mut().push(Relocation{ offset:offset, kind:kind, target:target, addend:value });
+                Ok(0)
+            },
+            (None, RelocKind::Addr) => expr::check_addr(value),
+            (None, RelocKind::Word) => Ok(value as u64),
+        }
+    }
+
+    fn refers_to_label(&self, operant:&str) -> bool {
+        expr::symbols(operant).map(|names| names.iter().any(|name| self.label_sections.contains_key(name))).unwrap_or(false)
+    }
+
+    //splits an expression into "start of a section or undefined symbol + addend" by shifting every
+    //section and symbol it uses and watching how the value follows; differences of labels in the
+    //same section stay absolute
+    fn relocatable_value(&self, operant:&str) -> Result<(i64, Option<RelocTarget>), ParseError> {
+        let base_of = |name:&str| match self.label_sections.get(name) {
+            Some(section) => Some(RelocTarget::Section(section.clone())),
+            None if self.labels.contains_key(name) => None,
+            None => Some(RelocTarget::Symbol(name.to_string())),
+        };
+        let lookup = |name:&str, shifted:Option<&RelocTarget>| -> Option<i64> {
+            let value = self.labels.get(name).map(|&v| v as i64).unwrap_or(0);
+            match base_of(name) {
+                Some(ref base) if Some(base) == shifted => Some(value + RELOC_PROBE),
+                _ => Some(value),
+            }
+        };
+
+        let mut bases = Vec::new();
+        for name in try!(expr::symbols(operant)) {
+            if let Some(base) = base_of(&name) {
+                if !bases.contains(&base) {
+                    bases.push(base);
+                }
+            }
+        }
+        let value = try!(expr::eval_with(operant, &|name| lookup(name, None)));
+        let mut target = None;
+        for base in bases {
+            let shifted = try!(expr::eval_with(operant, &|name| lookup(name, Some(&base))));
+            match shifted.wrapping_sub(value) {
+                0 => {},
+                RELOC_PROBE if target.is_none() => target = Some(base),
+                _ => return Err(ParseError::InvalidExpression(format!("{} cannot be relocated", operant))),
+            }
+        }
+        Ok((value, target))
+    }
+
+    //handles the directives that only change the assembler state and turns the others into statements
+    fn directive<'a>(&mut self, line:&'a str) -> Result<Option<Statement<'a>>, ParseError> {
+        let (name, args) = split_directive(line);
+        let operants = split_operants(args);
+
+        match &*name.to_lowercase() {
+            ".org"      => {
+                let addr = try!(self.single_value(name, &operants));
+                //relocatable sections are stored word by word up to their end
+                if self.relocatable && addr > MAX_PADDING {
+                    return Err(ParseError::TooMuchData(addr));
+                }
+                *self.sections.get_mut(&self.cur_section).unwrap() = addr;
+                Ok(None)
+            },
+            ".word"     => {
+                if operants.is_empty() {
+                    return Err(ParseError::InvalidDirective(line.to_string()));
+                }
+                Ok(Some(Statement::Words(operants)))
+            },
+            ".string"   => {
+                let mut data:Vec<u64> = try!(parse_string_literal(args)).chars().map(|c| c as u64).collect();
+                data.push(0);
+                Ok(Some(Statement::Data(data)))
+            },
+            ".zero"     => {
+                let n = try!(self.single_value(name, &operants));
+                try!(self.check_padding(n));
+                Ok(Some(Statement::Data(vec![0; n as usize])))
+            },
+            ".align"    => {
+                let n = try!(self.single_value(name, &operants));
+                if n == 0 {
+                    return Err(ParseError::InvalidDirective(line.to_string()));
+                }
+                let padding = (n - self.location() % n) % n;
+                try!(self.check_padding(padding));
+                Ok(Some(Statement::Data(vec![0; padding as usize])))
+            },
+            ".equ"      => {
+                if operants.len() != 2 || !is_label(operants[0]) {
+                    return Err(ParseError::InvalidDirective(line.to_string()));
+                }
+                if !self.relocatable {
+                    let value = try!(resolve_operant(operants[1], &self.labels));
+                    try!(self.define(operants[0], value));
+                    return Ok(None);
+                }
+                //an alias of a label has to move along with the label's section
+                match try!(self.relocatable_value(operants[1])) {
+                    (value, None) => try!(self.define(operants[0], try!(expr::check_addr(value)))),
+                    (value, Some(RelocTarget::Section(section))) => {
+                        try!(self.define(operants[0], try!(expr::check_addr(value))));
+                        self.label_sections.insert(operants[0].to_string(), section);
+                    },
+                    (_, Some(RelocTarget::Symbol(name))) => return Err(ParseError::UndefinedLabel(name)),
+                }
+                Ok(None)
+            },
+            ".global"   => {
+                if operants.is_empty() {
+                    return Err(ParseError::InvalidDirective(line.to_string()));
+                }
+                self.globals.extend(operants.iter().map(|name| name.to_string()));
+                Ok(None)
+            },
+            ".section"  => {
+                if operants.len() != 1 {
+                    return Err(ParseError::InvalidDirective(line.to_string()));
+                }
+                self.cur_section = operants[0].to_string();
+                if !self.sections.contains_key(&self.cur_section) {
+                    //the linker places relocatable sections, the others follow the ones before them
+                    let start = if self.relocatable { 0 } else { self.sections.values().cloned().max().unwrap_or(0) };
+                    self.sections.insert(self.cur_section.clone(), start);
+                    self.section_order.push(self.cur_section.clone());
+                }
+                Ok(None)
+            },
+            _           => Err(ParseError::InvalidDirective(line.to_string())),
+        }
+    }
+
+    //padding has to end inside of the address space and must not exhaust the assembler's memory
+    fn check_padding(&self, n:u64) -> Result<(), ParseError> {
+        if n > MAX_PADDING || n > expr::MAX_ADDR + 1 - self.location().min(expr::MAX_ADDR + 1) {
+            return Err(ParseError::TooMuchData(n));
+        }
+        Ok(())
+    }
+
+    //directives like .org or .zero take exactly one value that has to be known in the first pass
+    fn single_value(&self, directive:&str, operants:&[&str]) -> Result<u64, ParseError> {
+        if operants.len() != 1 {
+            return Err(ParseError::InvalidDirective(directive.to_string()));
+        }
+        resolve_operant(operants[0], &self.labels)
     }
 }
 
+pub fn parse_string_literal(_s:&str) -> Result<String, ParseError> {
+    if _s.len() < 2 || !_s.starts_with('"') || !_s.ends_with('"') {
+        return Err(ParseError::InvalidDirective(_s.to_string()));
+    }
+    let mut res = String::new();
+    let mut chars = _s[1.._s.len()-1].chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            res.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n')   => res.push('\n'),
+            Some('t')   => res.push('\t'),
+            Some('0')   => res.push('\0'),
+            Some('\\')  => res.push('\\'),
+            Some('"')   => res.push('"'),
+            _           => return Err(ParseError::InvalidDirective(_s.to_string())),
+        }
+    }
+    Ok(res)
+}
+
+pub fn is_label(_s:&str) -> bool {
+    let mut chars = _s.chars();
+    match chars.next() {
+        Some(c) if c.is_alphabetic() || c == '_' || c == '.' => {},
+        _ => return false,
+    }
+    chars.all(is_label_char)
+}
+
+pub fn is_label_char(c:char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '.'
+}
+
+//splits "label: rest" into ("label", "rest")
+pub fn split_label(line:&str) -> Option<(&str, &str)> {
+    if let Some(pos) = line.find(':') {
+        let label = line[..pos].trim();
+        if is_label(label) {
+            return Some((label, line[pos+1..].trim()));
+        }
+    }
+    None
+}
+
+fn resolve_operant(operant:&str, labels:&HashMap<String, u64>) -> Result<u64, ParseError> {
+    expr::eval_addr(operant, labels)
+}
+
 
 #[derive(PartialEq, Debug, Clone)]
 pub enum ParseError {
     UnkownInstruction(String),
-    UnkownReg(ParseIntError),
+    UnkownReg(String),
     InvalidMemAddress(ParseIntError),
+    UndefinedLabel(String),
+    DuplicateLabel(String),
+    EmptyLine,
+    InvalidDirective(String),
+    OverlappingData(u64),
+    InvalidExpression(String),
+    ValueOutOfRange(i64),
+    InvalidMacroCall(String),
+    InMacro(String, usize, usize, Box<ParseError>), //macro name, line of the call, line in the definition, error
+    IncludeNotFound(String),
+    IncludeCycle(String),
+    InvalidOperants(String, usize), //mnemonic, expected number of operants
+    TooMuchData(u64),
+    Io(String),
 }
 
 impl fmt::Display for ParseError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
-            ParseError::UnkownInstruction(s)  => write!(f, "UnkownInstruction: {}", s),
-            ParseError::UnkownReg(ref err)          => write!(f, "UnkownReg: {}", err),
+            ParseError::UnkownInstruction(ref s)    => write!(f, "UnkownInstruction: {}", s),
+            ParseError::UnkownReg(ref s)            => write!(f, "UnkownReg: {}", s),
             ParseError::InvalidMemAddress(ref err)  => write!(f, "InvalidMemAddress: {}", err),
+            ParseError::UndefinedLabel(ref s)       => write!(f, "UndefinedLabel: {}", s),
+            ParseError::DuplicateLabel(ref s)       => write!(f, "DuplicateLabel: {}", s),
+            ParseError::EmptyLine                   => write!(f, "EmptyLine"),
+            ParseError::InvalidDirective(ref s)     => write!(f, "InvalidDirective: {}", s),
+            ParseError::OverlappingData(addr)       => write!(f, "OverlappingData: {:#x}", addr),
+            ParseError::InvalidExpression(ref s)    => write!(f, "InvalidExpression: {}", s),
+            ParseError::ValueOutOfRange(n)          => write!(f, "ValueOutOfRange: {} does not fit into the address field", n),
+            ParseError::InvalidMacroCall(ref s)     => write!(f, "InvalidMacroCall: {}", s),
+            ParseError::InMacro(ref name, call_line, def_line, ref err) => 
+                write!(f, "{} (in macro {} called at line {}, defined at line {})", err, name, call_line, def_line),
+            ParseError::IncludeNotFound(ref s)      => write!(f, "IncludeNotFound: {}", s),
+            ParseError::IncludeCycle(ref s)         => write!(f, "IncludeCycle: {} includes itself", s),
+            ParseError::InvalidOperants(ref s, n)   => write!(f, "InvalidOperants: {} takes {} operants", s, n),
+            ParseError::TooMuchData(n)              => write!(f, "TooMuchData: {} words of padding do not fit", n),
+            ParseError::Io(ref s)                   => write!(f, "Io: {}", s),
+        }
+    }
+}
+
+impl ParseError {
+    //the part of the source line the error is about, used to place the caret of a diagnostic
+    pub fn token(&self) -> Option<&str> {
+        match *self {
+            ParseError::UnkownInstruction(ref s)    => Some(s),
+            ParseError::UnkownReg(ref s)            => Some(s),
+            ParseError::UndefinedLabel(ref s)       => Some(s),
+            ParseError::DuplicateLabel(ref s)       => Some(s),
+            ParseError::IncludeNotFound(ref s)      => Some(s),
+            ParseError::InvalidOperants(ref s, _)   => Some(s),
+            ParseError::InMacro(_, _, _, ref err)   => err.token(),
+            _                                       => None,
         }
     }
 }
@@ -74,17 +633,45 @@ impl fmt::Display for ParseError {
 impl Error for ParseError {
     fn description(&self) -> &str {
         match *self {
-            ParseError::UnkownInstruction(ref err)  => err.description(),
-            ParseError::UnkownReg(ref err)          => err.description(),
+            ParseError::UnkownInstruction(_)        => "unknown instruction",
+            ParseError::UnkownReg(_)                => "unknown register",
             ParseError::InvalidMemAddress(ref err)  => err.description(),
+            ParseError::UndefinedLabel(_)           => "undefined label",
+            ParseError::DuplicateLabel(_)           => "duplicate label",
+            ParseError::EmptyLine                   => "empty line",
+            ParseError::InvalidDirective(_)         => "invalid directive",
+            ParseError::OverlappingData(_)          => "address written twice",
+            ParseError::InvalidExpression(_)        => "invalid expression",
+            ParseError::ValueOutOfRange(_)          => "value out of range",
+            ParseError::InvalidMacroCall(_)         => "invalid macro call",
+            ParseError::InMacro(_, _, _, ref err)   => err.description(),
+            ParseError::IncludeNotFound(_)          => "include file not found",
+            ParseError::IncludeCycle(_)             => "cyclic include",
+            ParseError::InvalidOperants(_, _)       => "wrong number of operants",
+            ParseError::TooMuchData(_)              => "too much data",
+            ParseError::Io(_)                       => "i/o error",
         }
     }
 
     fn cause(&self) -> Option<&Error> {
         match *self {
-            ParseError::UnkownInstruction(ref err)  => Some(err),
-            ParseError::UnkownReg(ref err)          => Some(err),
+            ParseError::UnkownInstruction(_)        => None,
+            ParseError::UnkownReg(_)                => None,
             ParseError::InvalidMemAddress(ref err)  => Some(err),
+            ParseError::UndefinedLabel(_)           => None,
+            ParseError::DuplicateLabel(_)           => None,
+            ParseError::EmptyLine                   => None,
+            ParseError::InvalidDirective(_)         => None,
+            ParseError::OverlappingData(_)          => None,
+            ParseError::InvalidExpression(_)        => None,
+            ParseError::ValueOutOfRange(_)          => None,
+            ParseError::InvalidMacroCall(_)         => None,
+            ParseError::InMacro(_, _, _, ref err)   => Some(&**err),
+            ParseError::IncludeNotFound(_)          => None,
+            ParseError::IncludeCycle(_)             => None,
+            ParseError::InvalidOperants(_, _)       => None,
+            ParseError::TooMuchData(_)              => None,
+            ParseError::Io(_)                       => None,
         }
     }
 }
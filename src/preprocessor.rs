@@ -0,0 +1,337 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Read, ErrorKind};
+use std::path::{Path, PathBuf};
+use parser::{ParseError, split_label, is_label, parse_string_literal};
+use utils::{strip_comment, split_operants};
+use diagnostic::Diagnostic;
+use expr;
+
+const MAX_MACRO_DEPTH:usize = 64;
+
+//one line of source after macro expansion
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceLine {
+    pub text:String, //without comment and surrounding whitespace
+    pub raw:String,
+    pub file:String,
+    pub line:usize,
+    pub expanded_from:Vec<MacroCall>, //outermost macro first
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MacroCall {
+    pub name:String,
+    pub file:String,
+    pub call_line:usize,
+    pub raw:String,
+}
+
+impl SourceLine {
+    //lines that came out of a macro belong to the line that called it
+    pub fn origin(&self) -> (&str, usize) {
+        match self.expanded_from.first() {
+            Some(call) => (&call.file, call.call_line),
+            None => (&self.file, self.line),
+        }
+    }
+
+    //attaches the chain of macro expansions this line came from to an error; lines of a macro body
+    //carry their line in the definition, and the call of an inner macro is a line of the outer one
+    pub fn wrap_error(&self, err:ParseError) -> ParseError {
+        let mut def_line = self.line;
+        let mut err = err;
+        for call in self.expanded_from.iter().rev() {
+            err = ParseError::InMacro(call.name.clone(), call.call_line, def_line, Box::new(err));
+            def_line = call.call_line;
+        }
+        err
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Macro {
+    params:Vec<String>,
+    file:String,
+    body:Vec<(usize, String)>,
+}
+
+//state of one .if/.elif/.else/.endif block
+#[derive(Debug, Clone, Copy)]
+struct Conditional {
+    outer_active:bool,
+    active:bool,
+    taken:bool,
+    seen_else:bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct Preprocessor {
+    macros:HashMap<String, Macro>,
+    expansions:usize,
+    constants:HashMap<String, u64>, //everything that .if and .ifdef can see
+    include_paths:Vec<PathBuf>,
+    include_stack:Vec<PathBuf>,
+    diagnostics:Vec<Diagnostic>,
+}
+
+impl Preprocessor {
+
+    pub fn new() -> Preprocessor {
+        Preprocessor {
+            macros:HashMap::new(),
+            expansions:0,
+            constants:HashMap::new(),
+            include_paths:Vec::new(),
+            include_stack:Vec::new(),
+            diagnostics:Vec::new(),
+        }
+    }
+
+    pub fn add_include_path<P: AsRef<Path>>(&mut self, dir:P) {
+        self.include_paths.push(dir.as_ref().to_path_buf());
+    }
+
+    pub fn define_constant(&mut self, name:&str, value:u64) {
+        self.constants.insert(name.to_string(), value);
+    }
+
+    //errors are collected instead of aborting, so that every problem is reported at once
+    pub fn process(&mut self, source:&str, file:&str) -> (Vec<SourceLine>, Vec<Diagnostic>) {
+        let lines:Vec<(usize, String)> = source.lines().enumerate().map(|(i, line)| (i + 1, line.to_string())).collect();
+        let mut res = Vec::new();
+        self.run(&lines, file, &Vec::new(), &mut res);
+        (res, self.diagnostics.drain(..).collect())
+    }
+
+    pub fn process_file(&mut self, path:&Path) -> (Vec<SourceLine>, Vec<Diagnostic>) {
+        let mut res = Vec::new();
+        if let Err(err) = self.include(path, &mut res) {
+            self.diagnostics.push(Diagnostic::without_line(&path.display().to_string(), err));
+        }
+        (res, self.diagnostics.drain(..).collect())
+    }
+
+    fn report(&mut self, location:&SourceLine, err:ParseError) {
+        self.diagnostics.push(Diagnostic::new(location, err));
+    }
+
+    fn run(&mut self, lines:&[(usize, String)], file:&str, expanded_from:&Vec<MacroCall>, res:&mut Vec<SourceLine>) {
+        let mut conditionals:Vec<Conditional> = Vec::new();
+        let mut i = 0;
+
+        while i < lines.len() {
+            let (line_no, ref raw) = lines[i];
+            i += 1;
+            let text = strip_comment(raw).trim();
+            let location = SourceLine{ text:text.to_string(), raw:raw.clone(), file:file.to_string(), line:line_no, expanded_from:expanded_from.clone() };
+            let active = conditionals.last().map(|c| c.active).unwrap_or(true);
+            let (directive, args) = split_directive(text);
+
+            match &*directive.to_lowercase() {
+                ".if" | ".ifdef" | ".ifndef" => {
+                    let cond = active && match self.condition(directive, args) {
+                        Ok(cond) => cond,
+                        Err(err) => { self.report(&location, err); false },
+                    };
+                    conditionals.push(Conditional{ outer_active:active, active:cond, taken:cond, seen_else:false });
+                },
+                ".elif" => {
+                    let cur = match conditionals.last().cloned() {
+                        Some(ref c) if !c.seen_else => *c,
+                        _ => { self.report(&location, ParseError::InvalidDirective(".elif without .if".to_string())); continue },
+                    };
+                    let cond = cur.outer_active && !cur.taken && match self.condition(".if", args) {
+                        Ok(cond) => cond,
+                        Err(err) => { self.report(&location, err); false },
+                    };
+                    *conditionals.last_mut().unwrap() = Conditional{ active:cond, taken:cur.taken || cond, .. cur };
+                },
+                ".else" => {
+                    let cur = match conditionals.last().cloned() {
+                        Some(ref c) if !c.seen_else => *c,
+                        _ => { self.report(&location, ParseError::InvalidDirective(".else without .if".to_string())); continue },
+                    };
+                    *conditionals.last_mut().unwrap() = Conditional{ active:cur.outer_active && !cur.taken, taken:true, seen_else:true, .. cur };
+                },
+                ".endif" => {
+                    if conditionals.pop().is_none() {
+                        self.report(&location, ParseError::InvalidDirective(".endif without .if".to_string()));
+                    }
+                },
+                _ if !active => {},
+                ".macro" => {
+                    let mut body = Vec::new();
+                    loop {
+                        match lines.get(i) {
+                            Some(&(_, ref body_raw)) if is_directive(body_raw, ".endm") => break,
+                            Some(&(_, ref body_raw)) if is_directive(body_raw, ".macro") => {
+                                self.report(&location, ParseError::InvalidDirective(format!("nested .macro in definition of {}", args)));
+                            },
+                            Some(&(body_line, ref body_raw)) => body.push((body_line, body_raw.clone())),
+                            None => {
+                                self.report(&location, ParseError::InvalidDirective(format!(".macro {} without .endm", args)));
+                                break;
+                            },
+                        }
+                        i += 1;
+                    }
+                    i += 1;
+                    if let Err(err) = self.define_macro(args, file, body) {
+                        self.report(&location, err);
+                    }
+                },
+                ".endm" => self.report(&location, ParseError::InvalidDirective(".endm without .macro".to_string())),
+                ".include" => {
+                    let path = parse_string_literal(args).and_then(|name| self.find_include(&name, file));
+                    if let Err(err) = path.and_then(|path| self.include(&path, res)) {
+                        self.report(&location, err);
+                    }
+                },
+                ".equ" => {
+                    //constants that do not depend on labels are visible to .if as well
+                    let operants = split_operants(args);
+                    if operants.len() == 2 && is_label(operants[0]) {
+                        if let Ok(value) = expr::eval(operants[1], &self.constants) {
+                            self.constants.insert(operants[0].to_string(), value as u64);
+                        }
+                    }
+                    res.push(location);
+                },
+                _ => self.expand_line(location, res),
+            }
+        }
+
+        if !conditionals.is_empty() {
+            let (line_no, raw) = lines.last().cloned().unwrap_or((0, String::new()));
+            let location = SourceLine{ text:String::new(), raw:raw, file:file.to_string(), line:line_no, expanded_from:expanded_from.clone() };
+            self.report(&location, ParseError::InvalidDirective(".if without .endif".to_string()));
+        }
+    }
+
+    fn condition(&self, directive:&str, args:&str) -> Result<bool, ParseError> {
+        match &*directive.to_lowercase() {
+            ".ifdef"    => Ok(self.constants.contains_key(args)),
+            ".ifndef"   => Ok(!self.constants.contains_key(args)),
+            _           => Ok(try!(expr::eval(args, &self.constants)) != 0),
+        }
+    }
+
+    fn find_include(&self, name:&str, including_file:&str) -> Result<PathBuf, ParseError> {
+        let mut candidates = Vec::new();
+        if Path::new(name).is_absolute() {
+            candidates.push(PathBuf::from(name));
+        } else {
+            if let Some(dir) = Path::new(including_file).parent() {
+                candidates.push(dir.join(name));
+            }
+            for dir in self.include_paths.iter() {
+                candidates.push(dir.join(name));
+            }
+        }
+        candidates.into_iter()
+            .find(|path| path.is_file())
+            .ok_or(ParseError::IncludeNotFound(name.to_string()))
+    }
+
+    fn include(&mut self, path:&Path, res:&mut Vec<SourceLine>) -> Result<(), ParseError> {
+        let canonical = try!(path.canonicalize().map_err(|err| match err.kind() {
+            ErrorKind::NotFound => ParseError::IncludeNotFound(path.display().to_string()),
+            _ => ParseError::Io(format!("{}: {}", path.display(), err)),
+        }));
+        if self.include_stack.contains(&canonical) {
+            return Err(ParseError::IncludeCycle(path.display().to_string()));
+        }
+        let mut source = String::new();
+        try!(File::open(&canonical)
+            .and_then(|mut f| f.read_to_string(&mut source))
+            .map_err(|err| ParseError::Io(format!("{}: {}", path.display(), err))));
+        let lines:Vec<(usize, String)> = source.lines().enumerate().map(|(i, line)| (i + 1, line.to_string())).collect();
+
+        self.include_stack.push(canonical);
+        self.run(&lines, &path.display().to_string(), &Vec::new(), res);
+        self.include_stack.pop();
+        Ok(())
+    }
+
+    fn define_macro(&mut self, args:&str, file:&str, body:Vec<(usize, String)>) -> Result<(), ParseError> {
+        let (name, params) = split_directive(args);
+        if name.is_empty() {
+            return Err(ParseError::InvalidDirective(".macro without a name".to_string()));
+        }
+        let name = name.to_lowercase();
+        if self.macros.contains_key(&name) {
+            return Err(ParseError::DuplicateLabel(name));
+        }
+        let params = split_operants(params).into_iter().map(|p| p.to_string()).collect();
+        self.macros.insert(name, Macro{ params:params, file:file.to_string(), body:body });
+        Ok(())
+    }
+
+    fn expand_line(&mut self, location:SourceLine, res:&mut Vec<SourceLine>) {
+        let mac;
+        let mut labels = String::new();
+        let call_args;
+        let name;
+        {
+            let mut rest = &*location.text;
+            while let Some((label, tail)) = split_label(rest) {
+                labels = labels + label + ": ";
+                rest = tail;
+            }
+            let (_name, _args) = split_directive(rest);
+            mac = match self.macros.get(&_name.to_lowercase()) {
+                Some(mac) => mac.clone(),
+                None => {
+                    if !location.text.is_empty() {
+                        res.push(location.clone());
+                    }
+                    return;
+                },
+            };
+            name = _name.to_string();
+            call_args = split_operants(_args).into_iter().map(|a| a.to_string()).collect::<Vec<String>>();
+        }
+
+        if !labels.is_empty() {
+            res.push(SourceLine{ text:labels, .. location.clone() });
+        }
+        if location.expanded_from.len() >= MAX_MACRO_DEPTH {
+            return self.report(&location, ParseError::InvalidMacroCall(format!("{} nested too deeply", name)));
+        }
+        if call_args.len() != mac.params.len() {
+            return self.report(&location, ParseError::InvalidMacroCall(
+                format!("{} expects {} arguments, got {}", name, mac.params.len(), call_args.len())));
+        }
+
+        //longest parameter names first so that \a does not clobber \ab
+        let mut substitutions:Vec<(String, String)> = mac.params.iter().map(|p| format!("\\{}", p)).zip(call_args.into_iter()).collect();
+        substitutions.sort_by(|a, b| b.0.len().cmp(&a.0.len()));
+        self.expansions += 1;
+        let unique = format!("_{}", self.expansions);
+
+        let body:Vec<(usize, String)> = mac.body.iter().map(|&(def_line, ref body_raw)| {
+            let mut expanded = body_raw.clone();
+            for &(ref param, ref arg) in substitutions.iter() {
+                expanded = expanded.replace(&**param, arg);
+            }
+            (def_line, expanded.replace("\\@", &unique))
+        }).collect();
+
+        let mut nested_from = location.expanded_from.clone();
+        nested_from.push(MacroCall{ name:name, file:location.file.clone(), call_line:location.line, raw:location.raw.clone() });
+        self.run(&body, &mac.file, &nested_from, res)
+    }
+}
+
+fn is_directive(raw:&str, directive:&str) -> bool {
+    split_directive(strip_comment(raw).trim()).0.to_lowercase() == directive
+}
+
+//splits "name rest of the line" into ("name", "rest of the line")
+pub fn split_directive(line:&str) -> (&str, &str) {
+    match line.find(char::is_whitespace) {
+        Some(pos) => (&line[..pos], line[pos..].trim()),
+        None => (line, ""),
+    }
+}
@@ -0,0 +1,233 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::io::{BufRead, Write};
+use std::sync::mpsc::{Sender, Receiver, channel};
+use std::thread;
+use cpu::Core;
+use expr;
+use parser::ParseError;
+use utils::{Instruction, Opcode, Reg, CPUBusOp, strip_comment};
+
+const PROMPT:&'static str = "> ";
+const STACK_TOP:u64 = 0x10000; //so that PUSH and CALL have room to grow down
+const MAX_DUMP:usize = 0x1000;  //:mem shows at most this many words
+
+//assembles every typed line and runs it right away on a core of its own; the memory of the core
+//lives in a thread that answers its bus requests
+pub struct Repl {
+    core:Core,
+}
+
+//what a line did, printed as "NAME: old -> new"
+#[derive(Debug, Clone, PartialEq)]
+pub struct Change {
+    pub name:&'static str,
+    pub old:String,
+    pub new:String,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ReplError {
+    Parse(ParseError),
+    Memory(String),
+}
+
+impl From<ParseError> for ReplError {
+    fn from(err:ParseError) -> ReplError {
+        ReplError::Parse(err)
+    }
+}
+
+impl fmt::Display for ReplError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ReplError::Parse(ref err)   => write!(f, "{}", err),
+            ReplError::Memory(ref s)    => write!(f, "Memory: {}", s),
+        }
+    }
+}
+
+impl Error for ReplError {
+    fn description(&self) -> &str {
+        match *self {
+            ReplError::Parse(ref err)   => err.description(),
+            ReplError::Memory(_)        => "memory not available",
+        }
+    }
+
+    fn cause(&self) -> Option<&Error> {
+        match *self {
+            ReplError::Parse(ref err)   => Some(err),
+            ReplError::Memory(_)        => None,
+        }
+    }
+}
+
+//answers the bus requests of a single core until it goes away; words that were never written read as 0.
+//Reads that start outside of the address space fail, those that run past its end are cut short.
+pub fn serve_memory(tx:Sender<CPUBusOp>, rx:Receiver<CPUBusOp>, mut memory:HashMap<u64, u64>) {
+    while let Ok(op) = rx.recv() {
+        match op {
+            CPUBusOp::RequestBlock(addr, n) => {
+                let reply = if addr > expr::MAX_ADDR {
+                    CPUBusOp::Error(format!("read from {:#x} outside of memory", addr))
+                } else {
+                    let end = addr.checked_add(n as u64).unwrap_or(!0).min(expr::MAX_ADDR + 1);
+                    CPUBusOp::GiveBlock((addr..end).map(|a| (a, memory.get(&a).cloned().unwrap_or(0))).collect())
+                };
+                if tx.send(reply).is_err() {
+                    break;
+                }
+            },
+            CPUBusOp::GiveBlock(values) => memory.extend(values),
+            CPUBusOp::Tick => {},
+            op => { let _ = tx.send(CPUBusOp::Error(format!("unsupported bus operation {:?}", op))); },
+        }
+    }
+}
+
+pub fn snapshot(core:&Core) -> Vec<(&'static str, String)> {
+    vec![
+        ("EAX", format!("{:#x}", core.EAX)),
+        ("EBX", format!("{:#x}", core.EBX)),
+        ("ECX", format!("{:#x}", core.ECX)),
+        ("EDX", format!("{:#x}", core.EDX)),
+        ("ESP", format!("{:#x}", core.ESP)),
+        ("EBP", format!("{:#x}", core.EBP)),
+        ("ISP", format!("{:#x}", core.ISP)),
+        ("OVERFLOW", core.OVERFLOW.to_string()),
+        ("ZERO", core.ZERO.to_string()),
+        ("SIGN", core.SIGN.to_string()),
+        ("CARRY", core.CARRY.to_string()),
+    ]
+}
+
+impl Repl {
+
+    pub fn new() -> Repl {
+        let (core_tx, mem_rx) = channel();
+        let (mem_tx, core_rx) = channel();
+        thread::spawn(move || serve_memory(mem_tx, mem_rx, HashMap::new()));
+        let mut core = Core::new(core_tx, core_rx);
+        core.ESP = STACK_TOP;
+        Repl{ core:core }
+    }
+
+    pub fn core(&self) -> &Core {
+        &self.core
+    }
+
+    //besides instructions there are a few commands:
+    //  :set REG VALUE      :mem ADDR [N]      :regs
+    pub fn eval_line(&mut self, line:&str) -> Result<Vec<Change>, ReplError> {
+        let before = snapshot(&self.core);
+        let fields:Vec<&str> = strip_comment(line).split_whitespace().collect();
+        match fields.get(0).cloned() {
+            None => return Ok(Vec::new()),
+            Some(":set") if fields.len() == 3 => {
+                let reg:Reg = try!(fields[1].parse());
+                let value = try!(expr::eval(fields[2], &HashMap::new()));
+                self.set_reg(reg, value as u64);
+            },
+            Some(cmd) if cmd.starts_with(':') => return Err(ReplError::Parse(ParseError::InvalidDirective(line.trim().to_string()))),
+            Some(_) => {
+                let instr:Instruction = try!(line.parse());
+                try!(self.check(instr));
+                //the instruction is stored where it would have been fetched from
+                let addr = self.core.ISP;
+                try!(self.core.tx.send(CPUBusOp::GiveBlock(vec![(addr, instr.0)]))
+                    .map_err(|_| ReplError::Memory("memory has shut down".to_string())));
+                self.core.ISP += 1;
+                self.core.execute(instr);
+            },
+        }
+        Ok(before.into_iter().zip(snapshot(&self.core).into_iter())
+            .filter(|&(ref old, ref new)| old.1 != new.1)
+            .map(|(old, new)| Change{ name:old.0, old:old.1, new:new.1 })
+            .collect())
+    }
+
+    //the core panics on addresses outside of memory, so they are caught before it runs
+    fn check(&self, instr:Instruction) -> Result<(), ReplError> {
+        let (isp, esp) = (self.core.ISP, self.core.ESP);
+        if isp > expr::MAX_ADDR {
+            return Err(ReplError::Memory(format!("ISP {:#x} is outside of memory", isp)));
+        }
+        match instr.opcode() {
+            Opcode::Push | Opcode::Call if esp == 0 || esp - 1 > expr::MAX_ADDR =>
+                Err(ReplError::Memory(format!("ESP {:#x} leaves no room to push", esp))),
+            Opcode::Pop | Opcode::Ret if esp > expr::MAX_ADDR =>
+                Err(ReplError::Memory(format!("ESP {:#x} is outside of memory", esp))),
+            _ => Ok(()),
+        }
+    }
+
+    fn set_reg(&mut self, reg:Reg, value:u64) {
+        match reg {
+            Reg::EAX => self.core.EAX = value,
+            Reg::EBX => self.core.EBX = value,
+            Reg::ECX => self.core.ECX = value,
+            Reg::EDX => self.core.EDX = value,
+            Reg::ESP => self.core.ESP = value,
+            Reg::EBP => self.core.EBP = value,
+            Reg::ISP => self.core.ISP = value,
+        }
+    }
+
+    //at most MAX_DUMP words are read
+    pub fn read_memory(&self, addr:u64, n:usize) -> Result<Vec<(u64, u64)>, ReplError> {
+        if addr > expr::MAX_ADDR {
+            return Err(ReplError::Memory(format!("{:#x} is outside of memory", addr)));
+        }
+        if self.core.tx.send(CPUBusOp::RequestBlock(addr, n.min(MAX_DUMP))).is_err() {
+            return Err(ReplError::Memory("memory has shut down".to_string()));
+        }
+        match self.core.rx.recv() {
+            Ok(CPUBusOp::GiveBlock(block)) => Ok(block),
+            Ok(CPUBusOp::Error(err)) => Err(ReplError::Memory(err)),
+            Ok(op) => Err(ReplError::Memory(format!("unexpected answer {:?}", op))),
+            Err(_) => Err(ReplError::Memory("memory has shut down".to_string())),
+        }
+    }
+
+    pub fn run<R: BufRead, W: Write>(&mut self, input:R, out:&mut W) -> io::Result<()> {
+        try!(write!(out, "{}", PROMPT));
+        try!(out.flush());
+        for line in input.lines() {
+            let line = try!(line);
+            let fields:Vec<&str> = line.split_whitespace().collect();
+            match fields.get(0).cloned() {
+                Some(":quit") => break,
+                Some(":regs") => for (name, value) in snapshot(&self.core) {
+                    try!(writeln!(out, "{:8} {}", name, value));
+                },
+                Some(":mem") if fields.len() == 2 || fields.len() == 3 => {
+                    let addr = expr::eval(fields[1], &HashMap::new());
+                    let n = fields.get(2).map(|n| expr::eval(n, &HashMap::new())).unwrap_or(Ok(1));
+                    let block = match (addr, n) {
+                        (Ok(addr), Ok(n)) if n < 0 => Err(ReplError::Memory(format!("cannot show {} words at {:#x}", n, addr))),
+                        (Ok(addr), Ok(n)) => self.read_memory(addr as u64, n as usize),
+                        (Err(err), _) | (_, Err(err)) => Err(ReplError::Parse(err)),
+                    };
+                    match block {
+                        Ok(block) => for (a, word) in block {
+                            try!(writeln!(out, "{:#014x}: {:#018x}  {}", a, word, Instruction(word)));
+                        },
+                        Err(err) => try!(writeln!(out, "error: {}", err)),
+                    }
+                },
+                _ => match self.eval_line(&line) {
+                    Ok(changes) => for change in changes {
+                        try!(writeln!(out, "{}: {} -> {}", change.name, change.old, change.new));
+                    },
+                    Err(err) => try!(writeln!(out, "error: {}", err)),
+                },
+            }
+            try!(write!(out, "{}", PROMPT));
+            try!(out.flush());
+        }
+        Ok(())
+    }
+}
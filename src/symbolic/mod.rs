@@ -0,0 +1,382 @@
+//symbolic execution of assembled programs: registers and memory hold bit-vector terms over the
+//inputs, every conditional jump whose outcome depends on them forks the path, and the built-in
+//solver picks concrete inputs that drive the program down each path.
+mod term;
+mod solver;
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::rc::Rc;
+use parser::MemoryImage;
+use utils::{Instruction, Opcode, Reg};
+use self::term::{Term, Test, Constraint, constant, var, add, mul};
+
+//why a path stopped
+#[derive(Debug, Clone, PartialEq)]
+pub enum Termination {
+    Halted(u64),                //JGZ to itself, the loop the compiler ends programs with
+    Reached(u64),               //one of the targets
+    InvalidInstruction(u64),
+    NoCode(u64),                //the word was never written, there is nothing to execute
+    OutOfRange(u64),            //an access outside of memory
+    StepLimit(u64),
+}
+
+impl Termination {
+    pub fn addr(&self) -> u64 {
+        match *self {
+            Termination::Halted(addr) | Termination::Reached(addr) | Termination::InvalidInstruction(addr) |
+            Termination::NoCode(addr) | Termination::OutOfRange(addr) | Termination::StepLimit(addr) => addr,
+        }
+    }
+
+    pub fn is_trap(&self) -> bool {
+        match *self {
+            Termination::InvalidInstruction(_) | Termination::NoCode(_) | Termination::OutOfRange(_) => true,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Termination {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Termination::Halted(_)              => write!(f, "halted"),
+            Termination::Reached(_)             => write!(f, "reached target"),
+            Termination::InvalidInstruction(_)  => write!(f, "trap: invalid instruction"),
+            Termination::NoCode(_)              => write!(f, "trap: executing uninitialized memory"),
+            Termination::OutOfRange(addr)       => write!(f, "trap: access to {:#x} outside of memory", addr),
+            Termination::StepLimit(_)           => write!(f, "step limit reached"),
+        }
+    }
+}
+
+//one explored path: the inputs that lead there and the jumps taken (true) or not on the way.
+//Inputs the path does not depend on are left out, any value will do.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Path {
+    pub end:Termination,
+    pub inputs:Vec<(String, u64)>,
+    pub branches:Vec<(u64, bool)>,
+    pub steps:u64,
+}
+
+impl fmt::Display for Path {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "{:#014x}: {}", self.end.addr(), self.end));
+        if self.inputs.is_empty() {
+            return write!(f, " for any input");
+        }
+        let inputs:Vec<String> = self.inputs.iter().map(|&(ref name, value)| format!("{}={}", name, value as i64)).collect();
+        write!(f, " with {}", inputs.join(" "))
+    }
+}
+
+#[derive(Debug, Clone)]
+struct State {
+    regs:Vec<Rc<Term>>,
+    memory:HashMap<u64, Rc<Term>>,
+    flags:Option<Rc<Term>>, //the last result, None after NOP cleared them; a taken jump leaves 0 behind
+    constraints:Vec<Constraint>,
+    model:Vec<u64>, //satisfies the constraints
+    branches:Vec<(u64, bool)>,
+    steps:u64,
+}
+
+pub struct Executor {
+    image:MemoryImage,
+    entry:u64,
+    registers:Vec<Option<u64>>, //the ones that are not inputs
+    symbols:BTreeMap<u64, String>,
+    targets:Vec<u64>,
+    memory_size:u64,
+    max_steps:u64,
+    max_paths:usize,
+    names:Vec<String>,
+    memory_inputs:HashMap<u64, usize>,
+}
+
+impl Executor {
+
+    //registers start out as inputs, and so does every word outside of the image
+    pub fn new(image:&MemoryImage, entry:u64) -> Executor {
+        Executor {
+            image:image.clone(),
+            entry:entry,
+            registers:vec![None; 6],
+            symbols:BTreeMap::new(),
+            targets:Vec::new(),
+            memory_size:1 << 52,
+            max_steps:10000,
+            max_paths:256,
+            names:Vec::new(),
+            memory_inputs:HashMap::new(),
+        }
+    }
+
+    pub fn set_register(&mut self, _reg:Reg, _value:u64) -> &mut Executor {
+        if _reg != Reg::ISP {
+            self.registers[_reg as usize] = Some(_value);
+        }
+        self
+    }
+
+    //turns a word of the image into an input, for example a .word the program reads its argument from
+    pub fn set_symbolic(&mut self, _addr:u64, _name:&str) -> &mut Executor {
+        self.symbols.insert(_addr, _name.to_string());
+        self
+    }
+
+    //paths stop as soon as they get to a target
+    pub fn add_target(&mut self, _addr:u64) -> &mut Executor {
+        self.targets.push(_addr);
+        self
+    }
+
+    pub fn set_memory_size(&mut self, _size:u64) -> &mut Executor {
+        self.memory_size = _size;
+        self
+    }
+
+    pub fn set_max_steps(&mut self, _steps:u64) -> &mut Executor {
+        self.max_steps = _steps;
+        self
+    }
+
+    pub fn set_max_paths(&mut self, _paths:usize) -> &mut Executor {
+        self.max_paths = _paths;
+        self
+    }
+
+    fn input(&mut self, name:String) -> Rc<Term> {
+        self.names.push(name);
+        var(self.names.len() - 1)
+    }
+
+    //depth first, the path that does not jump is explored first
+    pub fn run(&mut self) -> Vec<Path> {
+        self.names.clear();
+        self.memory_inputs.clear();
+        let mut memory = HashMap::new();
+        for (&addr, &word) in self.image.iter() {
+            memory.insert(addr, constant(word));
+        }
+        for (addr, name) in self.symbols.clone() {
+            let input = self.input(name);
+            memory.insert(addr, input);
+        }
+        let mut regs = Vec::new();
+        for reg in [Reg::EAX, Reg::EBX, Reg::ECX, Reg::EDX, Reg::ESP, Reg::EBP].iter() {
+            let value = match self.registers[*reg as usize] {
+                Some(value) => constant(value),
+                None => self.input(reg.to_string()),
+            };
+            regs.push(value);
+        }
+        regs.push(constant(self.entry));
+
+        let mut pending = vec![State {
+            regs:regs,
+            memory:memory,
+            flags:None,
+            constraints:Vec::new(),
+            model:Vec::new(),
+            branches:Vec::new(),
+            steps:0,
+        }];
+        let mut paths = Vec::new();
+        while let Some(mut state) = pending.pop() {
+            if paths.len() >= self.max_paths {
+                break;
+            }
+            let end = loop {
+                match self.step(&mut state, &mut pending) {
+                    Ok(()) => {},
+                    Err(end) => break end,
+                }
+            };
+            paths.push(self.path(&state, end));
+        }
+        paths
+    }
+
+    fn path(&self, state:&State, end:Termination) -> Path {
+        let mut vars = Vec::new();
+        for constraint in state.constraints.iter() {
+            constraint.term.vars(&mut vars);
+        }
+        vars.sort();
+        Path {
+            end:end,
+            inputs:vars.into_iter().map(|v| (self.names[v].clone(), state.model.get(v).cloned().unwrap_or(0))).collect(),
+            branches:state.branches.clone(),
+            steps:state.steps,
+        }
+    }
+
+    //pins a term to the value it has in the model, for addresses and code that depend on inputs.
+    //Only this one value is explored.
+    fn concretize(&self, state:&mut State, term:&Rc<Term>) -> u64 {
+        let value = term.eval(&state.model);
+        if term.as_const().is_none() {
+            state.constraints.push(Constraint::new(add(term, &constant(value.wrapping_neg())), Test::Zero, true));
+        }
+        value
+    }
+
+    fn check_addr(&self, addr:u64) -> Result<u64, Termination> {
+        if addr < self.memory_size { Ok(addr) } else { Err(Termination::OutOfRange(addr)) }
+    }
+
+    fn load(&mut self, state:&mut State, addr:u64) -> Result<Rc<Term>, Termination> {
+        try!(self.check_addr(addr));
+        if let Some(term) = state.memory.get(&addr) {
+            return Ok(term.clone());
+        }
+        let input = match self.memory_inputs.get(&addr) {
+            Some(&v) => var(v),
+            None => {
+                let input = self.input(format!("mem[{:#x}]", addr));
+                self.memory_inputs.insert(addr, self.names.len() - 1);
+                input
+            },
+        };
+        state.memory.insert(addr, input.clone());
+        Ok(input)
+    }
+
+    fn store(&self, state:&mut State, addr:u64, value:Rc<Term>) -> Result<(), Termination> {
+        try!(self.check_addr(addr));
+        state.memory.insert(addr, value);
+        Ok(())
+    }
+
+    fn stack_pointer(&self, state:&mut State) -> u64 {
+        let esp = state.regs[Reg::ESP as usize].clone();
+        self.concretize(state, &esp)
+    }
+
+    //runs one instruction like cpu::Core::execute, the other side of a jump that may go both ways
+    //is pushed onto pending
+    fn step(&mut self, state:&mut State, pending:&mut Vec<State>) -> Result<(), Termination> {
+        let isp = state.regs[Reg::ISP as usize].clone();
+        let addr = self.concretize(state, &isp);
+        if self.targets.contains(&addr) {
+            return Err(Termination::Reached(addr));
+        }
+        if state.steps >= self.max_steps {
+            return Err(Termination::StepLimit(addr));
+        }
+        try!(self.check_addr(addr));
+        let word = match state.memory.get(&addr).cloned() {
+            Some(word) => self.concretize(state, &word),
+            None => return Err(Termination::NoCode(addr)),
+        };
+        let instr = Instruction(word);
+        let opcode = match instr.try_opcode() {
+            Some(opcode) if instr.is_valid() => opcode,
+            _ => return Err(Termination::InvalidInstruction(addr)),
+        };
+        state.steps += 1;
+        state.regs[Reg::ISP as usize] = constant(addr.wrapping_add(1));
+
+        match opcode {
+            Opcode::Add | Opcode::Mul => {
+                let (r1, r2) = (instr.reg1() as usize, instr.reg2() as usize);
+                let res = if opcode == Opcode::Add {
+                    add(&state.regs[r1], &state.regs[r2])
+                } else {
+                    mul(&state.regs[r1], &state.regs[r2])
+                };
+                state.regs[r1] = res.clone();
+                state.flags = Some(res);
+            },
+            Opcode::Ld => {
+                let value = try!(self.load(state, instr.addr()));
+                state.regs[instr.reg1() as usize] = value.clone();
+                state.flags = Some(value);
+            },
+            Opcode::Sav => {
+                let value = state.regs[instr.reg1() as usize].clone();
+                try!(self.store(state, instr.addr(), value.clone()));
+                state.flags = Some(value);
+            },
+            Opcode::Push => {
+                let esp = self.stack_pointer(state).wrapping_sub(1);
+                state.regs[Reg::ESP as usize] = constant(esp);
+                let value = state.regs[instr.reg1() as usize].clone();
+                try!(self.store(state, esp, value.clone()));
+                state.flags = Some(value);
+            },
+            Opcode::Pop => {
+                let esp = self.stack_pointer(state);
+                let value = try!(self.load(state, esp));
+                state.regs[Reg::ESP as usize] = constant(esp.wrapping_add(1));
+                state.regs[instr.reg1() as usize] = value.clone();
+                state.flags = Some(value);
+            },
+            Opcode::Call => {
+                let esp = self.stack_pointer(state).wrapping_sub(1);
+                state.regs[Reg::ESP as usize] = constant(esp);
+                try!(self.store(state, esp, constant(addr.wrapping_add(1))));
+                state.regs[Reg::ISP as usize] = constant(instr.addr());
+            },
+            Opcode::Ret => {
+                let esp = self.stack_pointer(state);
+                let ret = try!(self.load(state, esp));
+                state.regs[Reg::ESP as usize] = constant(esp.wrapping_add(1));
+                state.regs[Reg::ISP as usize] = ret;
+            },
+            Opcode::Nop => state.flags = None,
+            Opcode::Jz | Opcode::Jgz | Opcode::Jlz => {
+                let taken = self.jump(state, instr, pending, addr);
+                state.branches.push((addr, taken));
+                if taken {
+                    //the jump sets ZERO, so JGZ to itself never gets out again
+                    if opcode == Opcode::Jgz && instr.addr() == addr {
+                        return Err(Termination::Halted(addr));
+                    }
+                    state.regs[Reg::ISP as usize] = constant(instr.addr());
+                    state.flags = Some(constant(0));
+                }
+            },
+        }
+        Ok(())
+    }
+
+    //decides whether the jump is taken on this path. A jump that depends on the inputs forks when
+    //the solver finds inputs for the other side.
+    fn jump(&self, state:&mut State, instr:Instruction, pending:&mut Vec<State>, addr:u64) -> bool {
+        let opcode = instr.opcode();
+        let flags = match state.flags {
+            Some(ref flags) => flags.clone(),
+            None => return opcode == Opcode::Jgz, //ZERO and SIGN are both clear
+        };
+        let condition = match opcode {
+            Opcode::Jz  => Constraint::new(flags, Test::Zero, true),
+            Opcode::Jgz => Constraint::new(flags, Test::Negative, false),
+            _           => Constraint::new(flags, Test::Negative, true),
+        };
+        if let Some(n) = condition.term.as_const() {
+            return condition.eval(&[n]);
+        }
+        let taken = condition.eval(&state.model);
+        let other = if taken { condition.negate() } else { condition.clone() };
+        let mut constraints = state.constraints.clone();
+        constraints.push(other.clone());
+        if let Some(model) = solver::solve(&constraints, self.names.len()) {
+            let mut fork = state.clone();
+            fork.constraints = constraints;
+            fork.model = model;
+            fork.branches.push((addr, !taken));
+            if !taken {
+                //a JGZ to itself is caught when the fork executes it again with ZERO set
+                fork.regs[Reg::ISP as usize] = constant(instr.addr());
+                fork.flags = Some(constant(0));
+            }
+            pending.push(fork);
+        }
+        state.constraints.push(if taken { condition } else { condition.negate() });
+        taken
+    }
+}
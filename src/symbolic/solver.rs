@@ -0,0 +1,438 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+use super::term::{Term, Test, Constraint, constant};
+
+//finds values for the variables 0..vars that satisfy all constraints. The terms are turned into
+//circuits of and/xor gates with one boolean per bit ("bit-blasting") and handed to a SAT solver.
+pub fn solve(constraints:&[Constraint], vars:usize) -> Option<Vec<u64>> {
+    let mut blaster = Blaster::new();
+    for constraint in constraints.iter() {
+        let lit = blaster.constraint(constraint);
+        blaster.sat.add_clause(vec![lit]);
+    }
+    if !blaster.sat.solve() {
+        return None;
+    }
+    Some((0..vars).map(|v| match blaster.vars.get(&v) {
+        Some(bits) => bits.iter().enumerate().fold(0, |n, (i, &bit)| if blaster.sat.value(bit) { n | 1 << i } else { n }),
+        None => 0,
+    }).collect())
+}
+
+//a literal is a variable shifted left by one, the lowest bit set if it is negated
+type Lit = usize;
+
+fn not(lit:Lit) -> Lit {
+    lit ^ 1
+}
+
+struct Blaster {
+    sat:Sat,
+    yes:Lit,
+    vars:HashMap<usize, Vec<Lit>>,
+    terms:HashMap<Rc<Term>, Vec<Lit>>, //equal subterms are only built once, loops compute the same ones over and over
+}
+
+impl Blaster {
+    fn new() -> Blaster {
+        let mut sat = Sat::new();
+        let yes = sat.new_var();
+        sat.add_clause(vec![yes]);
+        Blaster { sat:sat, yes:yes, vars:HashMap::new(), terms:HashMap::new() }
+    }
+
+    fn constraint(&mut self, constraint:&Constraint) -> Lit {
+        let bits = self.term(&constraint.term);
+        let lit = match constraint.test {
+            Test::Zero      => not(bits.iter().fold(not(self.yes), |any, &bit| self.or(any, bit))),
+            Test::Negative  => bits[63],
+        };
+        if constraint.holds { lit } else { not(lit) }
+    }
+
+    fn term(&mut self, term:&Rc<Term>) -> Vec<Lit> {
+        if let Some(bits) = self.terms.get(term) {
+            return bits.clone();
+        }
+        let bits = match **term {
+            Term::Const(n) => (0..64).map(|i| if n >> i & 1 == 1 { self.yes } else { not(self.yes) }).collect(),
+            Term::Var(v) => {
+                if !self.vars.contains_key(&v) {
+                    let bits = (0..64).map(|_| self.sat.new_var()).collect();
+                    self.vars.insert(v, bits);
+                }
+                self.vars[&v].clone()
+            },
+            Term::Add(ref a, ref b) => {
+                let (a, b) = (self.term(a), self.term(b));
+                self.adder(&a, &b)
+            },
+            //x * c is -(x * -c) when that takes fewer additions, x * -1 is common for subtraction
+            Term::Mul(ref a, ref b) => match b.as_const() {
+                Some(c) if c.wrapping_neg().count_ones() < c.count_ones() => {
+                    let a = self.term(a);
+                    let product = self.term(&constant(c.wrapping_neg()));
+                    let product = self.multiplier(&a, &product);
+                    self.negate(&product)
+                },
+                _ => {
+                    let (a, b) = (self.term(a), self.term(b));
+                    self.multiplier(&a, &b)
+                },
+            },
+        };
+        self.terms.insert(term.clone(), bits.clone());
+        bits
+    }
+
+    //gates with constant inputs are folded, so constant operants cost nothing
+    fn and(&mut self, a:Lit, b:Lit) -> Lit {
+        let no = not(self.yes);
+        if a == no || b == no || a == not(b) {
+            return no;
+        }
+        if a == self.yes || a == b {
+            return b;
+        }
+        if b == self.yes {
+            return a;
+        }
+        let g = self.sat.new_var();
+        self.sat.add_clause(vec![not(g), a]);
+        self.sat.add_clause(vec![not(g), b]);
+        self.sat.add_clause(vec![g, not(a), not(b)]);
+        g
+    }
+
+    fn or(&mut self, a:Lit, b:Lit) -> Lit {
+        not(self.and(not(a), not(b)))
+    }
+
+    fn xor(&mut self, a:Lit, b:Lit) -> Lit {
+        let no = not(self.yes);
+        if a == b {
+            return no;
+        }
+        if a == not(b) {
+            return self.yes;
+        }
+        if a == no || a == self.yes {
+            return if a == no { b } else { not(b) };
+        }
+        if b == no || b == self.yes {
+            return if b == no { a } else { not(a) };
+        }
+        let g = self.sat.new_var();
+        self.sat.add_clause(vec![not(g), a, b]);
+        self.sat.add_clause(vec![not(g), not(a), not(b)]);
+        self.sat.add_clause(vec![g, not(a), b]);
+        self.sat.add_clause(vec![g, a, not(b)]);
+        g
+    }
+
+    fn adder(&mut self, a:&[Lit], b:&[Lit]) -> Vec<Lit> {
+        let mut carry = not(self.yes);
+        let mut res = Vec::new();
+        for i in 0..a.len() {
+            let half = self.xor(a[i], b[i]);
+            res.push(self.xor(half, carry));
+            let generated = self.and(a[i], b[i]);
+            let propagated = self.and(half, carry);
+            carry = self.or(generated, propagated);
+        }
+        res
+    }
+
+    //two's complement, ~x + 1
+    fn negate(&mut self, a:&[Lit]) -> Vec<Lit> {
+        let inverted:Vec<Lit> = a.iter().map(|&bit| not(bit)).collect();
+        let one = self.term(&constant(1));
+        self.adder(&inverted, &one)
+    }
+
+    //shift and add, the bits above 64 are dropped like in the cpu
+    fn multiplier(&mut self, a:&[Lit], b:&[Lit]) -> Vec<Lit> {
+        let no = not(self.yes);
+        let mut res = vec![no; a.len()];
+        for i in 0..b.len() {
+            if b[i] == no {
+                continue;
+            }
+            let mut partial = vec![no; i];
+            for j in 0..a.len() - i {
+                let bit = self.and(a[j], b[i]);
+                partial.push(bit);
+            }
+            res = self.adder(&res, &partial);
+        }
+        res
+    }
+}
+
+//a CDCL solver: two watched literals, learning of first UIP clauses, activity based decisions
+//with saved phases and restarts
+struct Sat {
+    clauses:Vec<Vec<Lit>>,
+    watches:Vec<Vec<usize>>, //clauses that watch a literal, visited when it becomes false
+    units:Vec<Lit>,
+    assigns:Vec<Option<bool>>,
+    levels:Vec<usize>,
+    reasons:Vec<Option<usize>>,
+    phases:Vec<bool>,
+    activity:Vec<f64>,
+    bump:f64,
+    trail:Vec<Lit>,
+    trail_lim:Vec<usize>,
+    head:usize,
+    conflicting:bool,
+}
+
+impl Sat {
+    fn new() -> Sat {
+        Sat {
+            clauses:Vec::new(),
+            watches:Vec::new(),
+            units:Vec::new(),
+            assigns:Vec::new(),
+            levels:Vec::new(),
+            reasons:Vec::new(),
+            phases:Vec::new(),
+            activity:Vec::new(),
+            bump:1.0,
+            trail:Vec::new(),
+            trail_lim:Vec::new(),
+            head:0,
+            conflicting:false,
+        }
+    }
+
+    fn new_var(&mut self) -> Lit {
+        self.assigns.push(None);
+        self.levels.push(0);
+        self.reasons.push(None);
+        self.phases.push(false);
+        self.activity.push(0.0);
+        self.watches.push(Vec::new());
+        self.watches.push(Vec::new());
+        (self.assigns.len() - 1) << 1
+    }
+
+    fn lit_value(&self, lit:Lit) -> Option<bool> {
+        self.assigns[lit >> 1].map(|value| value != (lit & 1 == 1))
+    }
+
+    fn value(&self, lit:Lit) -> bool {
+        self.lit_value(lit) == Some(true)
+    }
+
+    fn add_clause(&mut self, mut lits:Vec<Lit>) {
+        lits.sort();
+        lits.dedup();
+        if lits.windows(2).any(|pair| pair[0] == not(pair[1])) {
+            return;
+        }
+        match lits.len() {
+            0 => self.conflicting = true,
+            1 => self.units.push(lits[0]),
+            _ => {
+                self.watches[lits[0]].push(self.clauses.len());
+                self.watches[lits[1]].push(self.clauses.len());
+                self.clauses.push(lits);
+            },
+        }
+    }
+
+    fn level(&self) -> usize {
+        self.trail_lim.len()
+    }
+
+    fn assign(&mut self, lit:Lit, reason:Option<usize>) {
+        let v = lit >> 1;
+        self.assigns[v] = Some(lit & 1 == 0);
+        self.levels[v] = self.level();
+        self.reasons[v] = reason;
+        self.trail.push(lit);
+    }
+
+    //returns the clause that became false, if any
+    fn propagate(&mut self) -> Option<usize> {
+        while self.head < self.trail.len() {
+            let false_lit = not(self.trail[self.head]);
+            self.head += 1;
+            let mut watching = ::std::mem::replace(&mut self.watches[false_lit], Vec::new());
+            let mut kept = 0;
+            let mut conflict = None;
+            let mut i = 0;
+            while i < watching.len() {
+                let c = watching[i];
+                i += 1;
+                if self.clauses[c][0] == false_lit {
+                    self.clauses[c].swap(0, 1);
+                }
+                let first = self.clauses[c][0];
+                if self.lit_value(first) == Some(true) {
+                    watching[kept] = c;
+                    kept += 1;
+                    continue;
+                }
+                let replacement = (2..self.clauses[c].len()).find(|&k| self.lit_value(self.clauses[c][k]) != Some(false));
+                if let Some(k) = replacement {
+                    self.clauses[c].swap(1, k);
+                    let lit = self.clauses[c][1];
+                    self.watches[lit].push(c);
+                    continue;
+                }
+                watching[kept] = c;
+                kept += 1;
+                if self.lit_value(first) == Some(false) {
+                    conflict = Some(c);
+                    while i < watching.len() {
+                        watching[kept] = watching[i];
+                        kept += 1;
+                        i += 1;
+                    }
+                } else {
+                    self.assign(first, Some(c));
+                }
+            }
+            watching.truncate(kept);
+            self.watches[false_lit] = watching;
+            if conflict.is_some() {
+                return conflict;
+            }
+        }
+        None
+    }
+
+    //the learnt clause asserts its first literal after jumping back to the returned level
+    fn analyze(&mut self, conflict:usize) -> (Vec<Lit>, usize) {
+        let mut seen = vec![false; self.assigns.len()];
+        let mut learnt = vec![0];
+        let mut pending = 0;
+        let mut clause = conflict;
+        let mut skip_first = false;
+        let mut index = self.trail.len();
+        loop {
+            for k in (if skip_first { 1 } else { 0 })..self.clauses[clause].len() {
+                let lit = self.clauses[clause][k];
+                let v = lit >> 1;
+                if seen[v] || self.levels[v] == 0 {
+                    continue;
+                }
+                seen[v] = true;
+                self.bump_activity(v);
+                if self.levels[v] == self.level() {
+                    pending += 1;
+                } else {
+                    learnt.push(lit);
+                }
+            }
+            loop {
+                index -= 1;
+                if seen[self.trail[index] >> 1] {
+                    break;
+                }
+            }
+            let lit = self.trail[index];
+            pending -= 1;
+            if pending == 0 {
+                learnt[0] = not(lit);
+                break;
+            }
+            clause = self.reasons[lit >> 1].unwrap();
+            skip_first = true;
+        }
+        let mut back = 0;
+        for k in 1..learnt.len() {
+            if self.levels[learnt[k] >> 1] > back {
+                back = self.levels[learnt[k] >> 1];
+                learnt.swap(1, k);
+            }
+        }
+        (learnt, back)
+    }
+
+    fn bump_activity(&mut self, v:usize) {
+        self.activity[v] += self.bump;
+        if self.activity[v] > 1e100 {
+            for a in self.activity.iter_mut() {
+                *a *= 1e-100;
+            }
+            self.bump *= 1e-100;
+        }
+    }
+
+    fn backtrack(&mut self, level:usize) {
+        if self.level() <= level {
+            return;
+        }
+        let start = self.trail_lim[level];
+        for &lit in self.trail[start..].iter() {
+            self.assigns[lit >> 1] = None;
+            self.phases[lit >> 1] = lit & 1 == 0;
+        }
+        self.trail.truncate(start);
+        self.trail_lim.truncate(level);
+        self.head = start;
+    }
+
+    fn decide(&mut self) -> Option<Lit> {
+        let mut best = None;
+        for v in 0..self.assigns.len() {
+            if self.assigns[v].is_none() && best.map(|b| self.activity[v] > self.activity[b]).unwrap_or(true) {
+                best = Some(v);
+            }
+        }
+        best.map(|v| if self.phases[v] { v << 1 } else { not(v << 1) })
+    }
+
+    fn solve(&mut self) -> bool {
+        if self.conflicting {
+            return false;
+        }
+        for lit in ::std::mem::replace(&mut self.units, Vec::new()) {
+            match self.lit_value(lit) {
+                Some(false) => return false,
+                Some(true)  => {},
+                None        => self.assign(lit, None),
+            }
+        }
+        let mut conflicts = 0;
+        let mut restart_at = 100;
+        loop {
+            if let Some(conflict) = self.propagate() {
+                if self.level() == 0 {
+                    return false;
+                }
+                conflicts += 1;
+                let (learnt, back) = self.analyze(conflict);
+                self.backtrack(back);
+                self.bump /= 0.95;
+                if learnt.len() == 1 {
+                    self.assign(learnt[0], None);
+                } else {
+                    let c = self.clauses.len();
+                    self.watches[learnt[0]].push(c);
+                    self.watches[learnt[1]].push(c);
+                    self.clauses.push(learnt);
+                    let lit = self.clauses[c][0];
+                    self.assign(lit, Some(c));
+                }
+                continue;
+            }
+            if conflicts >= restart_at {
+                conflicts = 0;
+                restart_at += restart_at / 2;
+                self.backtrack(0);
+                continue;
+            }
+            match self.decide() {
+                Some(lit) => {
+                    self.trail_lim.push(self.trail.len());
+                    self.assign(lit, None);
+                },
+                None => return true,
+            }
+        }
+    }
+}
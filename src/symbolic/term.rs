@@ -0,0 +1,127 @@
+use std::fmt;
+use std::rc::Rc;
+
+//a 64 bit value that may depend on the inputs; ADD and MUL are all the ISA can compute
+#[derive(Debug, PartialEq, Eq, Hash)]
+pub enum Term {
+    Const(u64),
+    Var(usize),
+    Add(Rc<Term>, Rc<Term>),
+    Mul(Rc<Term>, Rc<Term>),
+}
+
+impl Term {
+    pub fn as_const(&self) -> Option<u64> {
+        match *self {
+            Term::Const(n) => Some(n),
+            _ => None,
+        }
+    }
+
+    //variables that are missing from the model are 0
+    pub fn eval(&self, model:&[u64]) -> u64 {
+        match *self {
+            Term::Const(n)              => n,
+            Term::Var(v)                => model.get(v).cloned().unwrap_or(0),
+            Term::Add(ref a, ref b)     => a.eval(model).wrapping_add(b.eval(model)),
+            Term::Mul(ref a, ref b)     => a.eval(model).wrapping_mul(b.eval(model)),
+        }
+    }
+
+    pub fn vars(&self, res:&mut Vec<usize>) {
+        match *self {
+            Term::Const(_)              => {},
+            Term::Var(v)                => if !res.contains(&v) { res.push(v) },
+            Term::Add(ref a, ref b) |
+            Term::Mul(ref a, ref b)     => { a.vars(res); b.vars(res); },
+        }
+    }
+}
+
+pub fn constant(n:u64) -> Rc<Term> {
+    Rc::new(Term::Const(n))
+}
+
+pub fn var(v:usize) -> Rc<Term> {
+    Rc::new(Term::Var(v))
+}
+
+//constants are folded and kept on the right, so that ESP+1-1 does not grow into a tree
+pub fn add(a:&Rc<Term>, b:&Rc<Term>) -> Rc<Term> {
+    match (a.as_const(), b.as_const()) {
+        (Some(x), Some(y))  => constant(x.wrapping_add(y)),
+        (Some(_), None)     => add(b, a),
+        (None, Some(0))     => a.clone(),
+        (None, Some(y))     => match **a {
+            Term::Add(ref inner, ref c) if c.as_const().is_some() => add(inner, &constant(c.as_const().unwrap().wrapping_add(y))),
+            _ => Rc::new(Term::Add(a.clone(), b.clone())),
+        },
+        (None, None)        => Rc::new(Term::Add(a.clone(), b.clone())),
+    }
+}
+
+pub fn mul(a:&Rc<Term>, b:&Rc<Term>) -> Rc<Term> {
+    match (a.as_const(), b.as_const()) {
+        (Some(x), Some(y))  => constant(x.wrapping_mul(y)),
+        (Some(_), None)     => mul(b, a),
+        (None, Some(0))     => constant(0),
+        (None, Some(1))     => a.clone(),
+        _                   => Rc::new(Term::Mul(a.clone(), b.clone())),
+    }
+}
+
+impl fmt::Display for Term {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Term::Const(n)              => write!(f, "{}", n as i64),
+            Term::Var(v)                => write!(f, "v{}", v),
+            Term::Add(ref a, ref b)     => write!(f, "({} + {})", a, b),
+            Term::Mul(ref a, ref b)     => write!(f, "({} * {})", a, b),
+        }
+    }
+}
+
+//the flags a jump looks at: ZERO is term == 0, SIGN is term < 0
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Test {
+    Zero,
+    Negative,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Constraint {
+    pub term:Rc<Term>,
+    pub test:Test,
+    pub holds:bool,
+}
+
+impl Constraint {
+    pub fn new(term:Rc<Term>, test:Test, holds:bool) -> Constraint {
+        Constraint { term:term, test:test, holds:holds }
+    }
+
+    pub fn negate(&self) -> Constraint {
+        Constraint { holds:!self.holds, .. self.clone() }
+    }
+
+    pub fn eval(&self, model:&[u64]) -> bool {
+        let n = self.term.eval(model);
+        let res = match self.test {
+            Test::Zero      => n == 0,
+            Test::Negative  => (n as i64) < 0,
+        };
+        res == self.holds
+    }
+}
+
+impl fmt::Display for Constraint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let op = match (self.test, self.holds) {
+            (Test::Zero, true)      => "==",
+            (Test::Zero, false)     => "!=",
+            (Test::Negative, true)  => "<",
+            (Test::Negative, false) => ">=",
+        };
+        write!(f, "{} {} 0", self.term, op)
+    }
+}
@@ -0,0 +1,142 @@
+//memory that counts the words that go through it
+#[cfg(test)]
+pub struct Flat {
+    pub words:Vec<u64>,
+    pub fetched:usize,
+    pub stored:usize,
+}
+
+#[cfg(test)]
+impl ::cache::Backing for Flat {
+    fn fetch(&mut self, addr:u64, n:usize) -> Vec<u64> {
+        let start = (addr as usize).min(self.words.len());
+        let end = (start + n).min(self.words.len());
+        self.fetched += end - start;
+        self.words[start..end].to_vec()
+    }
+
+    fn store(&mut self, values:Vec<(u64, u64)>) {
+        self.stored += values.len();
+        for (addr, word) in values {
+            self.words[addr as usize] = word;
+        }
+    }
+}
+
+#[test]
+fn replacing_lines() {
+    use cache::{Cache, Replacement};
+
+    //a single set of two lines, touching line 0 again makes LRU and FIFO disagree
+    let mut misses = Vec::new();
+    for &replacement in [Replacement::Lru, Replacement::Fifo, Replacement::Plru, Replacement::Random].iter() {
+        let mut memory = Flat { words:(0..64).collect(), fetched:0, stored:0 };
+        let mut cache = Cache::new("L1D", 8, 4, 2).unwrap();
+        cache.set_replacement(replacement);
+        for &addr in [1, 5, 1, 9, 1].iter() {
+            assert_eq!(cache.read(addr, &mut memory), addr);
+        }
+        assert_eq!(memory.fetched as u64, cache.stats().misses * 4);
+        misses.push(cache.stats().misses);
+    }
+    assert_eq!(&misses[..3], &[3, 4, 3]);
+
+    //the last line cannot be filled, so it is not cached
+    let mut memory = Flat { words:(0..62).collect(), fetched:0, stored:0 };
+    let mut cache = Cache::new("L1D", 8, 4, 2).unwrap();
+    assert_eq!(cache.read(61, &mut memory), 61);
+    assert_eq!(cache.read(61, &mut memory), 61);
+    assert_eq!((cache.stats().hits, cache.stats().misses), (0, 2));
+    assert!(Cache::new("L2", 12, 4, 2).is_err());
+}
+
+#[test]
+fn writing_back_and_through() {
+    use cache::{Cache, WritePolicy};
+
+    let mut memory = Flat { words:vec![0; 64], fetched:0, stored:0 };
+    let mut cache = Cache::new("L1D", 16, 4, 1).unwrap();
+    for n in 0..5 {
+        cache.write(1, n, &mut memory);
+    }
+    assert_eq!((memory.words[1], memory.stored), (0, 0));
+    cache.write(17, 7, &mut memory);
+    assert_eq!((memory.words[1], memory.stored), (4, 4));
+    assert_eq!(cache.stats().evictions, 1);
+    cache.flush(&mut memory);
+    assert_eq!((memory.words[17], cache.stats().writebacks), (7, 2));
+
+    let mut memory = Flat { words:vec![0; 64], fetched:0, stored:0 };
+    let mut cache = Cache::new("L1D", 16, 4, 1).unwrap();
+    cache.set_write_policy(WritePolicy::WriteThrough);
+    for n in 0..5 {
+        cache.write(1, n, &mut memory);
+    }
+    assert_eq!((memory.words[1], memory.stored, memory.fetched), (4, 5, 0));
+    assert_eq!(cache.read(1, &mut memory), 4);
+    cache.write(1, 9, &mut memory);
+    assert_eq!((cache.read(1, &mut memory), memory.words[1]), (9, 9));
+    assert_eq!((cache.stats().hits, cache.stats().misses, cache.stats().writebacks), (2, 6, 0));
+}
+
+#[test]
+fn bypassing_uncached_ranges() {
+    use cache::{Cache, Hierarchy};
+
+    let mut memory = Flat { words:vec![0; 64], fetched:0, stored:0 };
+    let mut caches = Hierarchy::new(Cache::new("L1I", 16, 4, 1).unwrap(), Cache::new("L1D", 16, 4, 1).unwrap());
+    caches.add_uncached(8, 4).add_uncached(40, 1);
+    assert!(caches.is_cached(7) && !caches.is_cached(8) && !caches.is_cached(11) && caches.is_cached(12));
+    assert!(!caches.is_cached(40) && caches.is_cached(41));
+
+    caches.write(9, 5, &mut memory);
+    assert_eq!((memory.words[9], memory.stored), (5, 1));
+    memory.words[9] = 6;
+    assert_eq!(caches.read(9, &mut memory), 6);
+    assert_eq!(caches.fetch_instr(40, &mut memory), 0);
+    assert!(!caches.holds(9) && !caches.l1i.holds(40));
+    caches.write(12, 1, &mut memory);
+    assert!(caches.holds(12));
+    assert_eq!(caches.l1d.stats().misses, 1);
+}
+
+#[test]
+fn running_programs_through_caches() {
+    use cache::{Cache, Hierarchy};
+    use image::LoadImage;
+    use parser::Parser;
+    use Motherboard;
+
+    let mut parser = Parser::new();
+    parser.assemble("
+        start:
+            LD EAX, one
+            LD EBX, n
+            LD EDX, minus
+        loop:
+            ADD ECX, EAX
+            SAV 2000, ECX
+            ADD EBX, EDX
+            JZ done
+            JGZ loop
+            JLZ loop
+        done:
+            LD EDX, 2000
+        halt:
+            JGZ halt
+        one: .word 1
+        n: .word 3
+        minus: .word 0xffffffffffffffff").unwrap();
+    let labels = parser.labels().clone();
+    let mut board = Motherboard::new();
+    board.load(&LoadImage::from_memory_image(parser.image(), labels["start"])).unwrap();
+    let mut cpu = board.start();
+    let mut caches = Hierarchy::new(Cache::new("L1I", 16, 4, 2).unwrap(), Cache::new("L1D", 16, 4, 2).unwrap());
+    caches.set_l2(Cache::new("L2", 64, 8, 4).unwrap());
+    cpu.set_caches(&caches);
+    cpu.run(40).unwrap();
+    assert_eq!(cpu.core(0).unwrap().EDX, 3);
+    let report = cpu.cache_report();
+    assert_eq!(report.lines().count(), 3);
+    assert!(report.starts_with("core 0 L1I: "), "{}", report);
+}
@@ -0,0 +1,41 @@
+#[test]
+fn building_the_graph() {
+    use std::collections::BTreeSet;
+    use cfg::*;
+    use parser::Parser;
+
+    let source = "
+        start:
+            LD ECX, n
+        loop:
+            ADD EAX, ECX
+            ADD ECX, EBX
+            JZ done
+            JGZ loop
+            JLZ loop
+        done:
+            RET
+        n: .word 3";
+    let mut parser = Parser::new();
+    parser.assemble(source).unwrap();
+    let graph = Cfg::new(&parser.words(), parser.labels(), 0);
+
+    let starts:Vec<u64> = graph.blocks.iter().map(|block| block.start).collect();
+    assert_eq!(starts, vec![0, 1, 4, 5, 6]);
+    assert_eq!(graph.blocks[1].successors, vec![(4, Edge::Branch), (2, Edge::FallThrough)]);
+    assert_eq!(graph.blocks[3].successors, vec![(1, Edge::Branch)]);
+    assert_eq!(graph.blocks[4].successors, vec![]);
+    assert_eq!(graph.block_at(2), Some(1));
+
+    let idoms:Vec<Option<usize>> = (0..5).map(|block| graph.idom(block)).collect();
+    assert_eq!(idoms, vec![None, Some(0), Some(1), Some(2), Some(1)]);
+    assert!(graph.dominates(1, 4) && !graph.dominates(2, 4));
+    assert_eq!(graph.loops().to_vec(), vec![Loop{ header:1, latches:vec![2, 3], body:[1, 2, 3].iter().cloned().collect::<BTreeSet<_>>() }]);
+
+    let dot = graph.to_dot();
+    assert!(dot.starts_with("digraph cfg {\n"));
+    assert!(dot.contains("    b0 [label=\"start:\\l0x000000000000  LD ECX n\\l\", style=bold];\n"));
+    assert!(dot.contains("    b1 -> b4 [label=\"JZ\"];\n"));
+    assert!(dot.contains("    b3 -> b1 [label=\"JLZ\", color=red];\n"));
+    assert!(dot.contains("    b0 -> b1;\n"));
+}
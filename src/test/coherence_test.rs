@@ -0,0 +1,115 @@
+#[test]
+fn snooping_on_other_caches() {
+    use cache::{Cache, Hierarchy};
+    use coherence::{Bus, State};
+    use super::cache_test::Flat;
+
+    let caches = Hierarchy::new(Cache::new("L1I", 16, 4, 2).unwrap(), Cache::new("L1D", 16, 4, 2).unwrap());
+    let mut bus = Bus::new(vec![caches.clone(), caches]);
+    let mut memory = Flat { words:vec![0; 64], fetched:0, stored:0 };
+
+    bus.write(0, 1, 7, &mut memory);
+    assert_eq!((bus.state(0, 1), bus.state(1, 1)), (State::Modified, State::Invalid));
+    assert_eq!(memory.words[1], 0);
+
+    //the dirty copy is written back before the other core reads it
+    assert_eq!(bus.read(1, 1, &mut memory), 7);
+    assert_eq!((bus.state(0, 1), bus.state(1, 1)), (State::Shared, State::Shared));
+    assert_eq!(memory.words[1], 7);
+
+    bus.write(1, 2, 9, &mut memory);
+    assert_eq!((bus.state(0, 2), bus.state(1, 2)), (State::Invalid, State::Modified));
+    assert_eq!(bus.read(0, 2, &mut memory), 9);
+    assert_eq!(bus.read(0, 40, &mut memory), 0);
+    assert_eq!(bus.state(0, 40), State::Exclusive);
+
+    let line = &bus.lines()[&0];
+    assert_eq!((line.reads, line.read_exclusives, line.upgrades), (2, 1, 1));
+    assert_eq!((line.invalidations, line.flushes), (1, 2));
+    assert_eq!(bus.transitions()[&(State::Modified, State::Shared)], 2);
+    assert_eq!(bus.transitions()[&(State::Shared, State::Invalid)], 1);
+    assert!(bus.report().contains("    0x000000000000: 2 read(s), 1 read-exclusive(s), 1 upgrade(s), 1 invalidation(s), 2 flush(es)\n"));
+    assert_eq!(line.transitions[&(State::Shared, State::Invalid)], 1);
+    assert_eq!(bus.lines()[&40].transitions.keys().collect::<Vec<_>>(), vec![&(State::Invalid, State::Exclusive)]);
+}
+
+#[test]
+fn snooping_on_instruction_caches() {
+    use cache::{Cache, Hierarchy};
+    use coherence::{Bus, State};
+    use super::cache_test::Flat;
+
+    //code one core writes is fetched again by the other one
+    let caches = Hierarchy::new(Cache::new("L1I", 16, 4, 2).unwrap(), Cache::new("L1D", 16, 4, 2).unwrap());
+    let mut bus = Bus::new(vec![caches.clone(), caches]);
+    let mut memory = Flat { words:vec![0; 64], fetched:0, stored:0 };
+
+    bus.write(0, 1, 7, &mut memory);
+    assert_eq!(bus.fetch_instr(1, 1, &mut memory), 7);
+    assert_eq!(bus.state(0, 1), State::Shared);
+    bus.write(0, 1, 8, &mut memory);
+    assert!(!bus.caches(1).l1i.holds(1));
+    assert_eq!(bus.fetch_instr(1, 1, &mut memory), 8);
+    //a copy that is only in the instruction cache makes a read shared as well
+    assert_eq!(bus.fetch_instr(1, 20, &mut memory), 0);
+    assert_eq!(bus.read(0, 20, &mut memory), 0);
+    assert_eq!(bus.state(0, 20), State::Shared);
+
+    let line = &bus.lines()[&0];
+    assert_eq!((line.reads, line.read_exclusives, line.upgrades, line.invalidations, line.flushes), (2, 1, 1, 1, 2));
+    assert_eq!(line.transitions[&(State::Modified, State::Shared)], 2);
+    assert_eq!(line.transitions[&(State::Shared, State::Modified)], 1);
+    assert!(bus.report().contains("    0x000000000000: 2 read(s), 1 read-exclusive(s), 1 upgrade(s), 1 invalidation(s), 2 flush(es)\n        M -> S: 2\n"));
+    assert_eq!(bus.lines()[&20].transitions[&(State::Invalid, State::Shared)], 1);
+}
+
+#[test]
+fn showing_false_sharing() {
+    use cache::{Cache, Hierarchy};
+    use image::LoadImage;
+    use parser::Parser;
+    use Motherboard;
+
+    //each core counts its own word up to 10, either next to the other one or a line away
+    let invalidations = |distance:u64| {
+        let mut parser = Parser::new();
+        let counter = |name:&str, addr:u64| format!("
+            {0}:
+                LD EAX, one
+                LD EBX, count
+                LD EDX, minus
+            {0}_loop:
+                LD ECX, {1}
+                ADD ECX, EAX
+                SAV {1}, ECX
+                ADD EBX, EDX
+                JZ {0}_done
+                JGZ {0}_loop
+                JLZ {0}_loop
+            {0}_done:
+                JGZ {0}_done
+                JLZ {0}_done", name, addr);
+        parser.assemble(&format!("{}\n{}
+            one: .word 1
+            count: .word 10
+            minus: .word 0xffffffffffffffff", counter("first", 2000), counter("second", 2000 + distance))).unwrap();
+        let labels = parser.labels().clone();
+        let mut board = Motherboard::new();
+        board.load(&LoadImage::from_memory_image(parser.image(), 0)).unwrap();
+        let mut cpu = board.start();
+        cpu.add_core();
+        cpu.core_mut(0).unwrap().ISP = labels["first"];
+        cpu.core_mut(1).unwrap().ISP = labels["second"];
+        cpu.set_coherent_caches(&Hierarchy::new(Cache::new("L1I", 32, 4, 2).unwrap(), Cache::new("L1D", 32, 4, 2).unwrap()));
+        cpu.run(80).unwrap();
+        assert_eq!((cpu.core(0).unwrap().ECX, cpu.core(1).unwrap().ECX), (10, 10));
+        assert_eq!(cpu.cache_report().lines().count(), 4);
+        let report = cpu.coherence_report().unwrap();
+        assert!(report.starts_with("transitions:\n"), "{}", report);
+        //the lines that lost copies
+        report.lines().filter(|line| line.contains("invalidation(s)") && !line.contains(" 0 invalidation(s)"))
+            .map(|line| line.trim()[..14].to_string()).collect::<Vec<_>>()
+    };
+    assert!(invalidations(4).is_empty());
+    assert_eq!(invalidations(1), vec!["0x0000000007d0"]);
+}
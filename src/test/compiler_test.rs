@@ -0,0 +1,104 @@
+//runs a compiled program on a single core until it reaches __halt, EAX then holds the result of main
+#[cfg(test)]
+fn run(_source:&str) -> (::cpu::Core, ::std::collections::HashMap<String, u64>) {
+    let (image, labels) = ::compiler::compile_to_image(_source).unwrap();
+    (run_image(image, &labels), labels)
+}
+
+#[cfg(test)]
+pub fn run_image(image: ::parser::MemoryImage, labels:&::std::collections::HashMap<String, u64>) -> ::cpu::Core {
+    use std::sync::mpsc::channel;
+    use std::thread;
+    use cpu::Core;
+    use repl::serve_memory;
+
+    let (core_tx, mem_rx) = channel();
+    let (mem_tx, core_rx) = channel();
+    thread::spawn(move || serve_memory(mem_tx, mem_rx, image.into_iter().collect()));
+    let mut core = Core::new(core_tx, core_rx);
+    core.ISP = labels["__start"];
+    for _ in 0..100_000 {
+        if core.ISP == labels["__halt"] {
+            return core;
+        }
+        core.exec_instr();
+    }
+    panic!("program did not halt");
+}
+
+#[test]
+fn running_compiled_programs() {
+    use utils::CPUBusOp;
+
+    let (core, _) = run("
+        int fact(int n) {
+            if (n <= 1) return 1;
+            return n * fact(n - 1);
+        }
+        int main() { return fact(5); }");
+    assert_eq!(core.EAX, 120);
+
+    let (core, labels) = run("
+        int data[5] = {3, -1, 4, 1, 5};
+        int count, total;
+        int sum(int *p, int n) {
+            int s = 0;
+            while (n > 0) {
+                s = s + *p;
+                p = p + 1;
+                n = n - 1;
+            }
+            return s;
+        }
+        int main(void) {
+            int local[3];
+            int i = 0;
+            while (i < 3) { local[i] = i * i; i = i + 1; }
+            count = 5;
+            total = sum(data, count);
+            data[0] = local[2] + (total >= 12) + !(count != 5) + (2 > 3 || 1 && -1 < 0);
+            return data[0];
+        }");
+    assert_eq!(core.EAX, 7);
+    core.tx.send(CPUBusOp::RequestBlock(labels["total"], 1)).unwrap();
+    match core.rx.recv().unwrap() {
+        CPUBusOp::GiveBlock(block) => assert_eq!(block, vec![(labels["total"], 12)]),
+        op => panic!("{:?}", op),
+    }
+}
+
+#[test]
+fn compile_errors() {
+    use compiler::{compile, CompileError};
+
+    match compile("int main() { return x; }") {
+        Err(CompileError::UndefinedVariable(1, ref name)) if name == "x" => {},
+        other => panic!("{:?}", other),
+    }
+    match compile("int f(int a) { return a; }\nint main() {\n  return f(1, 2);\n}") {
+        Err(CompileError::WrongArgumentCount(3, ref name, 1, 2)) if name == "f" => {},
+        other => panic!("{:?}", other),
+    }
+    match compile("int main() {\n  int a[2];\n  a = 0;\n}") {
+        Err(CompileError::InvalidLvalue(3)) => {},
+        other => panic!("{:?}", other),
+    }
+    match compile("int main() { return 4 / 2; }") {
+        Err(CompileError::Unsupported(1, _)) => {},
+        other => panic!("{:?}", other),
+    }
+    match compile("int f() { return 0; }") {
+        Err(CompileError::NoMain) => {},
+        other => panic!("{:?}", other),
+    }
+    match compile("int main() {\n  return (1;\n}") {
+        Err(CompileError::UnexpectedToken(2, ref token)) if token == ";" => {},
+        other => panic!("{:?}", other),
+    }
+    //the names of the generated labels cannot be taken
+    match compile("int __load;\nint main() { return __load; }") {
+        Err(CompileError::ReservedName(1, ref name)) if name == "__load" => {},
+        other => panic!("{:?}", other),
+    }
+    assert!(compile("int _load;\nint main() { return _load; }").is_ok());
+}
@@ -30,6 +30,8 @@ fn basic_reg_instr() {
         ZERO:false,
         SIGN:false,
         CARRY:false,
+        caches:None,
+        coherence:None,
         tx:fake_tx,
         rx:fake_rx,
     };
@@ -38,3 +40,88 @@ fn basic_reg_instr() {
     }
     panic!();
 }
+
+//a core whose memory holds the assembled source, with room for the stack below 0x1000
+#[cfg(test)]
+pub fn core_running(source:&str) -> ::cpu::Core {
+    use std::sync::mpsc::channel;
+    use std::thread;
+    use cpu::Core;
+    use parser::Parser;
+    use repl::serve_memory;
+
+    let mut parser = Parser::new();
+    parser.assemble(source).unwrap();
+    let image = parser.into_image();
+    let (core_tx, mem_rx) = channel();
+    let (mem_tx, core_rx) = channel();
+    thread::spawn(move || serve_memory(mem_tx, mem_rx, image.into_iter().collect()));
+    let mut core = Core::new(core_tx, core_rx);
+    core.ESP = 0x1000;
+    core
+}
+
+#[test]
+fn flags_and_overflow() {
+    use utils::*;
+
+    let mut c = core_running("");
+    c.EAX = 2;
+    c.EBX = !0;
+    c.execute("ADD EAX EBX".parse::<Instruction>().unwrap());
+    assert_eq!((c.EAX, c.ZERO, c.SIGN, c.OVERFLOW, c.CARRY), (1, false, false, true, true));
+    c.EBX = !1;
+    c.execute("ADD EAX EBX".parse::<Instruction>().unwrap());
+    assert_eq!((c.EAX, c.ZERO, c.SIGN, c.OVERFLOW), (!0, false, true, false));
+    c.EBX = 1;
+    c.execute("ADD EAX EBX".parse::<Instruction>().unwrap());
+    assert_eq!((c.EAX, c.ZERO, c.SIGN), (0, true, false));
+
+    //an overflowing product wraps like the sum does
+    c.ECX = 1 << 62;
+    c.EDX = 6;
+    c.execute("MUL ECX EDX".parse::<Instruction>().unwrap());
+    assert_eq!((c.ECX, c.SIGN, c.OVERFLOW), (1 << 63, true, true));
+}
+
+#[test]
+fn push_and_pop() {
+    let mut c = core_running("PUSH EAX\nPUSH EBX\nPOP ECX\nPOP EDX");
+    c.EAX = 7;
+    c.EBX = 9;
+    for _ in 0..4 {
+        c.exec_instr();
+    }
+    assert_eq!((c.ECX, c.EDX, c.ESP), (9, 7, 0x1000));
+}
+
+#[test]
+fn fetching_through_the_pipe() {
+    use utils::*;
+
+    //the first fetch at 0 and the fetch right behind a full pipe have to go to memory
+    let patch = "ADD EBX EBX".parse::<Instruction>().unwrap().0;
+    let mut c = core_running(&format!("
+            ADD EAX EBX
+            LD ECX patch
+            SAV 3 ECX
+            NOP
+            NOP
+            NOP
+            NOP
+            NOP
+            ADD EAX EBX
+        patch: .word {:#x}", patch));
+    c.EBX = 3;
+    c.exec_instr();
+    assert_eq!(c.EAX, 3);
+    //the pipe sees the word that was written over it
+    for _ in 0..3 {
+        c.exec_instr();
+    }
+    assert_eq!(c.EBX, 6);
+    for _ in 0..5 {
+        c.exec_instr();
+    }
+    assert_eq!((c.EAX, c.ISP), (9, 9));
+}
@@ -0,0 +1,33 @@
+#[test]
+fn mapping_addresses_to_source() {
+    use std::io::Cursor;
+    use parser::*;
+    use debuginfo::*;
+
+    let mut p = Parser::new();
+    p.assemble(".macro clear reg\n\
+                SAV zero, \\reg\n\
+                .endm\n\
+                .equ LIMIT, 100\n\
+                sum_array:\n\
+                LD EAX, zero\n\
+                loop: ADD EAX, EBX\n\
+                clear EBX\n\
+                JZ loop\n\
+                zero: .word 0").unwrap();
+    let info = p.debug_info();
+
+    assert_eq!(info.location(0), Some(&SourceLocation{ file:"<input>".to_string(), line:6 }));
+    assert_eq!(info.location(2), Some(&SourceLocation{ file:"<input>".to_string(), line:8 }));
+    assert_eq!(info.location(5), None);
+    assert_eq!(info.symbol(3), Some(("loop", 2)));
+    assert_eq!(info.describe(0), "<input>:6 (in sum_array)");
+    assert_eq!(info.describe(3), "<input>:9 (in loop)");
+    assert_eq!(info.describe(100), "0x64 (in zero)");
+    assert!(!info.symbols().contains_key("LIMIT"));
+
+    let mut out = Vec::new();
+    info.write_to(&mut out).unwrap();
+    assert_eq!(DebugInfo::read_from(Cursor::new(out)), Ok(info));
+    assert!(DebugInfo::read_from(Cursor::new("COMPSIM-DBG 1\nLINE zz 1 a.asm")).is_err());
+}
@@ -0,0 +1,163 @@
+#[test]
+fn decoding_addresses() {
+    use device::{Decoder, MapError, Timer};
+    use expr::MAX_ADDR;
+
+    let mut decoder = Decoder::new();
+    decoder.map("timer", 0x100, 2, Box::new(Timer::new())).unwrap();
+    decoder.map("top", MAX_ADDR, 1, Box::new(Timer::new())).unwrap();
+    match decoder.map("uart", 0xf0, 0x11, Box::new(Timer::new())) {
+        Err(MapError::Overlap(ref a, ref b)) => assert_eq!((&a.name[..], &b.name[..]), ("uart", "timer")),
+        res => panic!("expected an overlap, got {:?}", res.err()),
+    }
+    assert!(decoder.map("empty", 0, 0, Box::new(Timer::new())).is_err());
+    assert!(decoder.map("beyond", MAX_ADDR, 2, Box::new(Timer::new())).is_err());
+    decoder.map("uart", 0xf0, 0x10, Box::new(Timer::new())).unwrap();
+    assert_eq!(decoder.mappings().iter().map(|m| m.start).collect::<Vec<_>>(), vec![0xf0, 0x100, MAX_ADDR]);
+
+    assert_eq!(decoder.read(0xff), Some(0));
+    assert_eq!(decoder.read(0x102), None);
+    assert!(decoder.write(0x101, 3));
+    assert!(!decoder.write(0x102, 3));
+    decoder.tick();
+    decoder.tick();
+    assert!(decoder.pending_irqs().is_empty());
+    decoder.tick();
+    assert_eq!(decoder.read(0x100), Some(3));
+    assert_eq!(decoder.pending_irqs(), vec!["timer"]);
+}
+
+#[test]
+fn mapping_devices_on_the_motherboard() {
+    use std::sync::{Arc, Mutex};
+    use device::Device;
+    use image::LoadImage;
+    use parser::Parser;
+    use Motherboard;
+
+    //remembers what was written, reads give back the offset
+    struct Port(Arc<Mutex<Vec<(u64, u64)>>>);
+    impl Device for Port {
+        fn read(&mut self, offset:u64) -> u64 { offset }
+        fn write(&mut self, offset:u64, word:u64) { self.0.lock().unwrap().push((offset, word)); }
+    }
+
+    let mut parser = Parser::new();
+    parser.assemble("
+            LD EAX, 2000001
+            SAV 2000000, EAX
+            SAV 5, EAX
+            LD EBX, 5").unwrap();
+    let written = Arc::new(Mutex::new(Vec::new()));
+    let mut board = Motherboard::new();
+    board.map_device("port", 2_000_000, 2, Box::new(Port(written.clone()))).unwrap();
+    board.map_device("shadow", 5, 1, Box::new(Port(written.clone()))).unwrap();
+    assert!(board.map_device("again", 2_000_001, 1, Box::new(Port(written.clone()))).is_err());
+    board.load(&LoadImage::from_memory_image(parser.image(), 0)).unwrap();
+    let mut cpu = board.start();
+    cpu.run(4).unwrap();
+    assert_eq!(cpu.core(0).unwrap().EAX, 1);
+    assert_eq!(cpu.core(0).unwrap().EBX, 0);
+    assert_eq!(*written.lock().unwrap(), vec![(0, 1), (0, 1)]);
+}
+
+#[test]
+fn clocking_devices_by_instructions() {
+    use device::Timer;
+    use image::LoadImage;
+    use parser::Parser;
+    use Motherboard;
+
+    //fetching and loading do not move the clock, only executed instructions do
+    let mut parser = Parser::new();
+    parser.assemble("
+            NOP
+            NOP
+            NOP
+            LD EAX, 0x1000
+            LD EBX, 0x1000").unwrap();
+    let mut board = Motherboard::new();
+    board.map_device("timer", 0x1000, 2, Box::new(Timer::new())).unwrap();
+    board.load(&LoadImage::from_memory_image(parser.image(), 0)).unwrap();
+    let mut cpu = board.start();
+    cpu.run(5).unwrap();
+    assert_eq!(cpu.core(0).unwrap().EAX, 3);
+    assert_eq!(cpu.core(0).unwrap().EBX, 4);
+}
+
+#[test]
+fn reaching_devices_through_caches() {
+    use cache::{Cache, Hierarchy};
+    use device::Timer;
+    use image::LoadImage;
+    use parser::Parser;
+    use Motherboard;
+
+    //a cached timer would answer the second read from the first one and keep the write to itself
+    let mut parser = Parser::new();
+    parser.assemble("
+            LD EAX, 0x1000
+            NOP
+            LD EBX, 0x1000
+            SAV 0x1000, EDX
+            LD ECX, 0x1000").unwrap();
+    let caches = Hierarchy::new(Cache::new("L1I", 16, 4, 2).unwrap(), Cache::new("L1D", 16, 4, 2).unwrap());
+    for &coherent in [false, true].iter() {
+        let mut board = Motherboard::new();
+        board.map_device("timer", 0x1000, 2, Box::new(Timer::new())).unwrap();
+        board.load(&LoadImage::from_memory_image(parser.image(), 0)).unwrap();
+        let mut cpu = board.start();
+        if coherent {
+            cpu.set_coherent_caches(&caches);
+        } else {
+            cpu.set_caches(&caches);
+            assert!(!cpu.core(0).unwrap().caches.as_ref().unwrap().is_cached(0x1001));
+        }
+        cpu.run(5).unwrap();
+        let core = cpu.core(0).unwrap();
+        assert_eq!((core.EAX, core.EBX, core.ECX), (0, 2, 1));
+    }
+}
+
+#[test]
+fn prefetching_up_to_devices() {
+    use std::sync::{Arc, Mutex};
+    use device::Device;
+    use image::LoadImage;
+    use parser::Parser;
+    use snowflake::ProcessUniqueId;
+    use utils::MemBusOp;
+    use Motherboard;
+
+    //remembers the offsets that were read
+    struct Probe(Arc<Mutex<Vec<u64>>>);
+    impl Device for Probe {
+        fn read(&mut self, offset:u64) -> u64 { self.0.lock().unwrap().push(offset); offset }
+        fn write(&mut self, _offset:u64, _word:u64) {}
+    }
+
+    //the code ends right below the device, filling the pipeline must not read it
+    let mut parser = Parser::new();
+    parser.assemble("
+            NOP
+            NOP
+            LD EAX, 4").unwrap();
+    let reads = Arc::new(Mutex::new(Vec::new()));
+    let mut board = Motherboard::new();
+    board.map_device("probe", 3, 2, Box::new(Probe(reads.clone()))).unwrap();
+    board.load(&LoadImage::from_memory_image(parser.image(), 0)).unwrap();
+    let id = ProcessUniqueId::new();
+    match board.memory.handle(MemBusOp::RequestBlock(id, 1, 8)) {
+        Some(MemBusOp::GiveBlock(_, ref block)) => assert_eq!(block.len(), 2),
+        res => panic!("expected a block, got {:?}", res),
+    }
+    match board.memory.handle(MemBusOp::RequestBlock(id, 3, 8)) {
+        Some(MemBusOp::GiveBlock(_, ref block)) => assert_eq!(*block, vec![(3, 0)]),
+        res => panic!("expected a block, got {:?}", res),
+    }
+    reads.lock().unwrap().clear();
+    let mut cpu = board.start();
+    cpu.run(3).unwrap();
+    assert_eq!(cpu.core(0).unwrap().EAX, 1);
+    assert_eq!(*reads.lock().unwrap(), vec![1]);
+}
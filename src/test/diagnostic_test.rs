@@ -0,0 +1,50 @@
+#[test]
+fn collecting_all_errors_with_locations() {
+    use parser::*;
+
+    let source = "start: NOP\n\
+                  \tLD EAX, tabel ; typo\n\
+                  PUSH EXX\n\
+                  FOO EAX\n\
+                  ADD EAX\n\
+                  start: NOP\n";
+    let mut p = Parser::new();
+    let diagnostics = p.assemble(source).unwrap_err();
+    let errors:Vec<(ParseError, usize, usize)> = diagnostics.iter().map(|d| (d.error.clone(), d.line, d.column)).collect();
+
+    assert_eq!(errors.len(), 5);
+    assert!(errors.contains(&(ParseError::DuplicateLabel("start".to_string()), 6, 1)));
+    assert!(errors.contains(&(ParseError::UndefinedLabel("tabel".to_string()), 2, 10)));
+    assert!(errors.contains(&(ParseError::UnkownReg("EXX".to_string()), 3, 6)));
+    assert!(errors.contains(&(ParseError::UnkownInstruction("FOO".to_string()), 4, 1)));
+    assert!(errors.contains(&(ParseError::InvalidOperants("ADD".to_string(), 2), 5, 1)));
+
+    //only whole words count, and operands come before the mnemonic
+    let mut p = Parser::new();
+    let diagnostics = p.assemble("ADD EAX, a\nx: PUSH push\nld: JZ ld.x").unwrap_err();
+    let errors:Vec<(ParseError, usize, usize, usize)> = diagnostics.iter().map(|d| (d.error.clone(), d.line, d.column, d.width)).collect();
+    assert_eq!(errors, vec![
+        (ParseError::UnkownReg("A".to_string()), 1, 10, 1),
+        (ParseError::UnkownReg("PUSH".to_string()), 2, 9, 4),
+        (ParseError::UndefinedLabel("ld.x".to_string()), 3, 8, 4),
+    ]);
+}
+
+#[test]
+fn rendering_diagnostics() {
+    use parser::*;
+
+    let source = ".macro load dst, src\n\
+                  LD \\dst, \\src\n\
+                  .endm\n\
+                  load EAX, missing\n";
+    let mut p = Parser::new();
+    let diagnostics = p.assemble(source).unwrap_err();
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(format!("{}", diagnostics[0]),
+               "<input>:2:9: error: UndefinedLabel: missing\n  \
+                  |\n\
+                2 | LD EAX, missing\n  \
+                  |         ^^^^^^^\n  \
+                  = note: in expansion of macro load called at <input>:4");
+}
@@ -0,0 +1,57 @@
+extern crate rand;
+
+#[test]
+fn rendering_instructions() {
+    use super::rand_instr;
+    use utils::*;
+
+    for _ in 0..1000 {
+        let (s, i) = rand_instr();
+        assert_eq!(i.to_string(), s);
+        assert_eq!(i.to_string().parse::<Instruction>(), Ok(i));
+    }
+    assert_eq!(Instruction(0x72_00_00_00_00_00_00_10).opcode(), Opcode::Jgz);
+    assert_eq!(Instruction(0x73_00_00_00_00_00_00_10).opcode(), Opcode::Jlz);
+    assert_eq!(Instruction(0x00_00_00_00_00_00_00_05).to_string(), ".word 0x5");
+    assert_eq!(Instruction(0x74_00_00_00_00_00_00_00).to_string(), ".word 0x7400000000000000");
+}
+
+#[test]
+fn round_trip_through_the_assembler() {
+    use self::rand::Rng;
+    use std::collections::HashMap;
+    use parser::*;
+    use disassembler::*;
+    use super::rand_instr;
+
+    let mut rng = rand::thread_rng();
+    let mut image = MemoryImage::new();
+    for addr in 0..200 {
+        let word = if rng.gen() { rand_instr().1 .0 } else { rng.gen() };
+        image.insert(addr, word);
+    }
+    for addr in 500..510 {
+        image.insert(addr, rng.gen());
+    }
+
+    let source = Disassembler::new().to_source(&image, 0..1000);
+    let mut p = Parser::new();
+    p.assemble(&source).unwrap();
+    assert_eq!(p.image(), &image);
+
+    let mut symbols = HashMap::new();
+    symbols.insert("start".to_string(), 0);
+    symbols.insert("far_away".to_string(), 12345);
+    let mut image = MemoryImage::new();
+    image.insert(0, "JZ 12345".parse::<::utils::Instruction>().unwrap().0);
+    image.insert(1, "CALL 0".parse::<::utils::Instruction>().unwrap().0);
+    let disassembler = Disassembler::with_symbols(&symbols);
+    let lines = disassembler.disassemble(&image, 0..2);
+    assert_eq!(lines[0].label, Some("start".to_string()));
+    assert_eq!(lines[0].text, "JZ far_away");
+    assert_eq!(lines[1].text, "CALL start");
+
+    let mut p = Parser::new();
+    p.assemble(&disassembler.to_source(&image, 0..2)).unwrap();
+    assert_eq!(p.image(), &image);
+}
@@ -0,0 +1,33 @@
+#[test]
+fn evaluating_expressions() {
+    use std::collections::HashMap;
+    use expr::*;
+
+    let mut symbols = HashMap::new();
+    symbols.insert("table".to_string(), 100u64);
+    symbols.insert("buf_end".to_string(), 0x20u64);
+
+    assert_eq!(eval("table+8*3", &symbols), Ok(124));
+    assert_eq!(eval("(buf_end-1) & ~0xf", &symbols), Ok(0x10));
+    assert_eq!(eval("1 << 4 | 0b11", &symbols), Ok(19));
+    assert_eq!(eval("0o17 % 4 - 'A'", &symbols), Ok(3 - 65));
+    assert_eq!(eval("-(2 + 3) * 2 >> 1", &symbols), Ok(-5));
+    assert_eq!(eval("'\\n' ^ 0xF", &symbols), Ok(5));
+    assert_eq!(eval("table == 100 & buf_end != 100", &symbols), Ok(1));
+    assert_eq!(eval("1 << 2 < 3", &symbols), Ok(0));
+}
+
+#[test]
+fn expression_errors() {
+    use std::collections::HashMap;
+    use expr::*;
+    use parser::ParseError;
+
+    let symbols = HashMap::new();
+    assert_eq!(eval("missing + 1", &symbols), Err(ParseError::UndefinedLabel("missing".to_string())));
+    assert!(eval("(1 + 2", &symbols).is_err());
+    assert!(eval("4 / 0", &symbols).is_err());
+    assert_eq!(eval_addr("1 << 52", &symbols), Err(ParseError::ValueOutOfRange(1 << 52)));
+    assert_eq!(eval_addr("0 - 1", &symbols), Err(ParseError::ValueOutOfRange(-1)));
+    assert_eq!(eval_addr("(1 << 52) - 1", &symbols), Ok(MAX_ADDR));
+}
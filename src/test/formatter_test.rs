@@ -0,0 +1,77 @@
+#[test]
+fn formatting_sources() {
+    use formatter::Formatter;
+    use parser::Parser;
+
+    let source = "
+
+; counts down
+.equ  N 3
+start: ld eax,n   ; load it
+  loop:add Eax ,ebx
+\tjz   done # finished
+   ; keep going
+x: y: jgz loop
+
+
+   .MACRO twice r
+   push \\r
+   .endm
+   twice eax
+done: Ret
+n: .word N   N
+";
+    let formatted = Formatter::new().format_source(source).unwrap();
+    assert_eq!(formatted, "\
+; counts down
+    .equ N, 3
+start:
+    LD   EAX, n                         ; load it
+loop:
+    ADD  EAX, EBX
+    JZ   done                           # finished
+    ; keep going
+x: y:
+    JGZ  loop
+
+    .macro twice r
+    PUSH \\r
+    .endm
+    twice eax
+done:
+    RET
+n:  .word N, N
+");
+    assert_eq!(Formatter::new().format(&formatted), formatted);
+
+    let mut before = Parser::new();
+    before.assemble(source).unwrap();
+    let mut after = Parser::new();
+    after.assemble(&formatted).unwrap();
+    assert_eq!(before.image(), after.image());
+    assert_eq!(before.labels(), after.labels());
+}
+
+#[test]
+fn refusing_broken_sources() {
+    use formatter::Formatter;
+
+    let errors = Formatter::new().format_source("NOP\nFOO EAX\n").unwrap_err();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].line, 2);
+    //symbols of other files are fine
+    assert!(Formatter::new().format_source(".global main\nmain: CALL puts\n").is_ok());
+
+    //a file that cannot be read is not mistaken for a missing include
+    use std::env;
+    use std::fs::{File, remove_file};
+    use std::io::Write;
+    use parser::ParseError;
+    let path = env::temp_dir().join("compsim_refusing_broken_sources.asm");
+    File::create(&path).unwrap().write_all(b"NOP ; \xff\n").unwrap();
+    match Formatter::new().format_file(&path).unwrap_err()[0].error {
+        ParseError::Io(_) => {},
+        ref res => panic!("expected Io, got {:?}", res),
+    }
+    remove_file(path).unwrap();
+}
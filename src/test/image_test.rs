@@ -0,0 +1,113 @@
+#[test]
+fn saving_and_loading_images() {
+    use std::env;
+    use parser::*;
+    use image::*;
+
+    let mut p = Parser::new();
+    p.assemble("start: LD EAX, 5\n\
+                JZ start\n\
+                .org 0x2000\n\
+                .word 0x0123456789abcdef, 7").unwrap();
+    let program = LoadImage::from_memory_image(p.image(), 1);
+    assert_eq!(program.segments.len(), 2);
+    assert_eq!(program.segments[1], Segment{ addr:0x2000, words:vec![0x0123456789abcdef, 7] });
+
+    for &format in [ImageFormat::IntelHex, ImageFormat::Native].iter() {
+        let mut out = Vec::new();
+        program.write_to(&mut out, format).unwrap();
+        assert_eq!(ImageFormat::detect(&out), format);
+        assert_eq!(LoadImage::read_from(&mut &out[..], format).unwrap(), program);
+    }
+
+    //raw images have neither addresses nor an entry
+    let mut out = Vec::new();
+    program.write_to(&mut out, ImageFormat::Raw).unwrap();
+    assert_eq!(out.len(), 8 * 0x2002);
+    let raw = LoadImage::read_from(&mut &out[..], ImageFormat::Raw).unwrap();
+    assert_eq!(raw.entry, 0);
+    assert_eq!(raw.to_memory_image()[&0x2000], 0x0123456789abcdef);
+    assert_eq!(raw.to_memory_image()[&0x1000], 0);
+
+    let mut hex = Vec::new();
+    program.write_to(&mut hex, ImageFormat::IntelHex).unwrap();
+    let hex = String::from_utf8(hex).unwrap();
+    assert!(hex.starts_with(":10000000050000000000003000000000000000714A\n"));
+    assert!(hex.ends_with(":0400000500000008EF\n:00000001FF\n"));
+    let corrupted = hex.replacen(":1000000005", ":1000000006", 1);
+    match LoadImage::read_from(&mut corrupted.as_bytes(), ImageFormat::IntelHex) {
+        Err(ImageError::Checksum(1)) => {},
+        res => panic!("expected a checksum error, got {:?}", res),
+    }
+
+    //only a well formed first record makes an image Intel HEX
+    let colon = LoadImage{ entry:0, segments:vec![Segment{ addr:0, words:vec![0x3a, 1] }] };
+    let mut out = Vec::new();
+    colon.write_to(&mut out, ImageFormat::Raw).unwrap();
+    assert_eq!(ImageFormat::detect(&out), ImageFormat::Raw);
+    assert_eq!(ImageFormat::detect(b":0000000X\n"), ImageFormat::Raw);
+    assert_eq!(ImageFormat::detect(b":00000001FF\r\n"), ImageFormat::IntelHex);
+    match LoadImage::read_from(&mut &b":02000004FFFFFC\n:02FFFF000102FD\n"[..], ImageFormat::IntelHex) {
+        Err(ImageError::Malformed(ref msg)) if msg.starts_with("line 2:") => {},
+        res => panic!("expected a malformed image, got {:?}", res),
+    }
+    let empty = LoadImage{ entry:0, segments:vec![Segment{ addr:0, words:vec![] }] };
+    let mut out = Vec::new();
+    empty.write_to(&mut out, ImageFormat::IntelHex).unwrap();
+    assert_eq!(LoadImage::read_from(&mut &out[..], ImageFormat::IntelHex).unwrap().segments, vec![]);
+
+    let path = env::temp_dir().join("compsim_saving_and_loading_images.img");
+    program.save(&path).unwrap();
+    assert_eq!(LoadImage::load(&path).unwrap(), program);
+    assert!(program.save(env::temp_dir().join("compsim_image.txt")).is_err());
+}
+
+#[test]
+fn booting_the_motherboard() {
+    use std::env;
+    use image::*;
+    use Motherboard;
+
+    let path = env::temp_dir().join("compsim_booting_the_motherboard.hex");
+    let program = LoadImage{ entry:3, segments:vec![Segment{ addr:2, words:vec![0x30_00_00_00_00_00_00_05, 42] }] };
+    program.save(&path).unwrap();
+
+    let mut board = Motherboard::new();
+    board.boot(&path).unwrap();
+    assert_eq!(board.memory.memory[2], 0x30_00_00_00_00_00_00_05);
+    assert_eq!(board.memory.memory[3], 42);
+
+    //the ram spans the whole address space unless it is limited
+    let far = LoadImage{ entry:0, segments:vec![Segment{ addr:2_000_000, words:vec![1] }] };
+    board.load(&far).unwrap();
+    assert_eq!(board.memory()[2_000_000], 1);
+
+    let mut board = Motherboard::new();
+    board.memory_mut().set_size(1_000_000);
+    let wrapping = LoadImage{ entry:0, segments:vec![Segment{ addr:!0 - 1, words:vec![1, 2, 3] }] };
+    match board.load(&wrapping) {
+        Err(ImageError::AddressOutOfRange(addr)) => assert_eq!(addr, !0 - 1),
+        res => panic!("expected an address error, got {:?}", res),
+    }
+    match board.load(&far) {
+        Err(ImageError::AddressOutOfRange(2_000_000)) => {},
+        res => panic!("expected an address error, got {:?}", res),
+    }
+}
+
+#[test]
+fn running_booted_images() {
+    use std::env;
+    use image::LoadImage;
+    use parser::Parser;
+    use boot;
+
+    let mut p = Parser::new();
+    p.assemble("start: LD EAX, value\nhalt: JGZ halt\nvalue: .word 7").unwrap();
+    let path = env::temp_dir().join("compsim_running_booted_images.img");
+    LoadImage::from_memory_image(p.image(), 0).save(&path).unwrap();
+    let path = path.display().to_string();
+    assert!(boot(&["-n".to_string(), "5".to_string(), path.clone()]).is_ok());
+    assert!(boot(&["-n".to_string(), "five".to_string(), path.clone()]).is_err());
+    assert!(boot(&[path.clone(), path.clone(), path]).is_err());
+}
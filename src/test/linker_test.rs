@@ -0,0 +1,94 @@
+#[test]
+fn object_files_round_trip() {
+    use std::io::Cursor;
+    use parser::*;
+    use object::*;
+
+    let mut p = Parser::relocatable();
+    p.assemble(".global start\n\
+                start: LD EAX, value\n\
+                JZ ext+2\n\
+                .section .data\n\
+                value: .word 7, start\n\
+                .equ SIZE, 4").unwrap();
+    let obj = p.into_object();
+
+    let text = obj.section(".text").unwrap();
+    assert_eq!(text.words.len(), 2);
+    assert_eq!(text.relocations, vec![
+        Relocation{ offset:0, kind:RelocKind::Addr, target:RelocTarget::Section(".data".to_string()), addend:0 },
+        Relocation{ offset:1, kind:RelocKind::Addr, target:RelocTarget::Symbol("ext".to_string()), addend:2 },
+    ]);
+    assert_eq!(obj.section(".data").unwrap().words, vec![7, 0]);
+    assert_eq!(obj.symbols, vec![
+        Symbol{ name:"SIZE".to_string(), section:None, value:4, global:false },
+        Symbol{ name:"start".to_string(), section:Some(".text".to_string()), value:0, global:true },
+        Symbol{ name:"value".to_string(), section:Some(".data".to_string()), value:0, global:false },
+    ]);
+
+    let mut out = Vec::new();
+    obj.write_to(&mut out).unwrap();
+    assert_eq!(ObjectFile::read_from(Cursor::new(out)), Ok(obj));
+}
+
+#[test]
+fn linking_modules() {
+    use parser::*;
+    use linker::*;
+    use utils::*;
+
+    let assemble = |source:&str| {
+        let mut p = Parser::relocatable();
+        p.assemble(source).unwrap();
+        p.into_object()
+    };
+    let main = assemble(".global start\n\
+                         start: CALL print\n\
+                         JZ start\n\
+                         .section .data\n\
+                         msg: .word msg_end - msg, print\n\
+                         msg_end:");
+    let lib = assemble(".global print\n\
+                        print: LD EAX, count\n\
+                        RET\n\
+                        .section .data\n\
+                        count: .word 0");
+
+    let script = LinkerScript::parse("ENTRY start\n.text 0x10\n.data 0x100").unwrap();
+    let mut linker = Linker::new(script);
+    linker.add(main.clone());
+    linker.add(lib.clone());
+    let linked = linker.link().unwrap();
+
+    assert_eq!(linked.entry, Some(0x10));
+    assert_eq!(linked.symbols["print"], 0x12);
+    assert_eq!(linked.image[&0x10], "CALL 18".parse::<Instruction>().unwrap().0);
+    assert_eq!(linked.image[&0x11], "JZ 16".parse::<Instruction>().unwrap().0);
+    assert_eq!(linked.image[&0x12], "LD EAX 258".parse::<Instruction>().unwrap().0);
+    assert_eq!(linked.image[&0x100], 2);
+    assert_eq!(linked.image[&0x101], 0x12);
+    assert_eq!(linked.image[&0x102], 0);
+
+    let mut linker = Linker::new(LinkerScript::new());
+    linker.add(main.clone());
+    assert_eq!(linker.link(), Err(LinkError::UndefinedSymbol("print".to_string())));
+    let mut linker = Linker::new(LinkerScript::new());
+    linker.add(main.clone());
+    linker.add(lib.clone());
+    linker.add(lib);
+    assert_eq!(linker.link(), Err(LinkError::DuplicateSymbol("print".to_string())));
+    assert!(LinkerScript::parse(".text 0x10 0x20").is_err());
+
+    let mut p = Parser::relocatable();
+    assert_eq!(p.assemble("a: JZ a*2").unwrap_err()[0].error,
+               ParseError::InvalidExpression("a*2 cannot be relocated".to_string()));
+
+    //sections are stored densely, so they must stay small and end inside of the address space
+    let mut p = Parser::relocatable();
+    assert_eq!(p.assemble(".org 0xfffffffffffff\nNOP").unwrap_err()[0].error, ParseError::TooMuchData(0xfffffffffffff));
+    let mut p = Parser::relocatable();
+    assert_eq!(p.assemble(".org 0xffffff\nNOP\nNOP").unwrap_err()[0].error, ParseError::TooMuchData(1));
+    let mut linker = Linker::new(LinkerScript::parse(".text 0xfffffffffffff").unwrap());
+    linker.add(main);
+    assert_eq!(linker.link(), Err(LinkError::AddressOutOfRange(0x10000000000001)));
+}
@@ -0,0 +1,54 @@
+#[test]
+fn linting_programs() {
+    use lint::*;
+    use parser::Parser;
+    use utils::Reg;
+
+    let source = "
+        start:
+            LD EAX, one
+            ADD EAX, EBX
+            PUSH EAX
+            JZ skip
+            PUSH EAX
+        skip:
+            CALL func
+            SAV 5000, EAX
+            CALL tail
+            JGZ start
+            JLZ start
+            NOP
+            NOP
+        func:
+            PUSH EAX
+            RET
+        tail:
+            MUL EAX, EAX
+        one: .word 1";
+    let mut parser = Parser::new();
+    parser.assemble(source).unwrap();
+    let mut linter = Linter::new(parser.words(), parser.labels());
+    let lints:Vec<(u64, LintKind)> = linter.set_memory_size(1000).run().into_iter().map(|lint| (lint.addr, lint.kind)).collect();
+    assert_eq!(lints, vec![
+        (0, LintKind::StackImbalance(0, 1)),
+        (1, LintKind::UninitializedRead(Reg::EBX)),
+        (5, LintKind::StackImbalance(1, 2)),
+        (6, LintKind::TargetOutOfRange(5000)),
+        (10, LintKind::Unreachable(2)),
+        (13, LintKind::UnbalancedReturn(1)),
+        (14, LintKind::FallsOffEnd(15)),
+    ]);
+    assert_eq!(Lint{ addr:14, kind:LintKind::FallsOffEnd(15) }.to_string(),
+               "0x00000000000e: warning: FallsOffEnd: execution runs on to 0xf, which holds no instruction");
+}
+
+#[test]
+fn writes_to_isp() {
+    use lint::*;
+    use parser::Parser;
+
+    //after the jump through ISP nothing is known, so nothing counts as unreachable
+    let mut parser = Parser::new();
+    parser.assemble("LD ECX, one\nADD ISP, ECX\nNOP\none: .word 1").unwrap();
+    assert_eq!(Linter::new(parser.words(), parser.labels()).run(), vec![Lint{ addr:1, kind:LintKind::IspWrite }]);
+}
@@ -0,0 +1,35 @@
+#[test]
+fn listing_lines_and_symbols() {
+    use parser::*;
+
+    let mut p = Parser::new();
+    p.enable_listing();
+    p.assemble(".macro twice reg\n\
+                ADD \\reg, \\reg\n\
+                .endm\n\
+                .equ N, 5\n\
+                start: LD EAX, N\n\
+                twice EAX\n\
+                JZ start\n\
+                data: .word 1, 2").unwrap();
+    let listing = p.listing().unwrap();
+
+    let words:Vec<(Option<u64>, Vec<u64>)> = listing.lines.iter().map(|l| (l.addr, l.words.clone())).collect();
+    assert_eq!(words, vec![
+        (None, vec![]),
+        (Some(0), vec![p.image()[&0]]),
+        (Some(1), vec![p.image()[&1]]),
+        (Some(2), vec![p.image()[&2]]),
+        (Some(3), vec![1, 2]),
+    ]);
+    assert_eq!(listing.symbols["N"].defined_at, Some(("<input>".to_string(), 4)));
+    assert_eq!(listing.symbols["N"].references, vec![("<input>".to_string(), 5)]);
+    assert_eq!(listing.symbols["start"].references, vec![("<input>".to_string(), 7)]);
+    assert_eq!(listing.symbols["data"].section, Some(".text".to_string()));
+
+    let text = listing.to_string();
+    assert!(text.contains("000000000000  3000000000000005      5  start: LD EAX, N"));
+    assert!(text.contains("                                    6  twice EAX"));
+    assert!(text.contains("000000000003  0000000000000001      8  data: .word 1, 2\n000000000004  0000000000000002\n"));
+    assert!(text.contains("start                000000000000  .text      <input>:5"));
+}
@@ -0,0 +1,121 @@
+#[cfg(test)]
+fn request(id:u64, method:&str, uri:&str, line:u64, character:u64) -> ::json::Json {
+    use json::Json;
+    Json::obj(vec![
+        ("jsonrpc", Json::str("2.0")),
+        ("id", Json::Num(id as f64)),
+        ("method", Json::str(method)),
+        ("params", Json::obj(vec![
+            ("textDocument", Json::obj(vec![("uri", Json::str(uri))])),
+            ("position", Json::obj(vec![("line", Json::Num(line as f64)), ("character", Json::Num(character as f64))])),
+        ])),
+    ])
+}
+
+#[test]
+fn answering_requests() {
+    use json::Json;
+    use lsp::Server;
+
+    let uri = "file:///tmp/prog.asm";
+    let text = "start:\n    LD EAX, value\n    JZ start ; start again\n    FOO EAX\nvalue: .word 1\n";
+    let mut server = Server::new();
+    let open = Json::obj(vec![
+        ("jsonrpc", Json::str("2.0")),
+        ("method", Json::str("textDocument/didOpen")),
+        ("params", Json::obj(vec![("textDocument", Json::obj(vec![("uri", Json::str(uri)), ("text", Json::str(text))]))])),
+    ]);
+    let replies = server.handle(&open).unwrap();
+    assert_eq!(replies.len(), 1);
+    assert_eq!(replies[0].get(&["method"]), Some(&Json::str("textDocument/publishDiagnostics")));
+    let diagnostics = match replies[0].get(&["params", "diagnostics"]) {
+        Some(&Json::Arr(ref diagnostics)) => diagnostics.clone(),
+        other => panic!("{:?}", other),
+    };
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].get(&["range", "start", "line"]), Some(&Json::Num(3.0)));
+    assert_eq!(diagnostics[0].get(&["range", "start", "character"]), Some(&Json::Num(4.0)));
+
+    let hover = server.handle(&request(1, "textDocument/hover", uri, 1, 5)).unwrap();
+    assert_eq!(hover[0].get(&["result", "contents", "value"]), Some(&Json::str("```\nLD REG, ADDR\n```\nloads the word at ADDR into REG")));
+    let hover = server.handle(&request(2, "textDocument/hover", uri, 1, 14)).unwrap();
+    assert_eq!(hover[0].get(&["result", "contents", "value"]), Some(&Json::str("**value** = 0x3")));
+
+    let definition = server.handle(&request(3, "textDocument/definition", uri, 2, 8)).unwrap();
+    assert_eq!(definition[0].get(&["result"]).unwrap().to_string(),
+               format!("{{\"range\":{{\"end\":{{\"character\":5,\"line\":0}},\"start\":{{\"character\":0,\"line\":0}}}},\"uri\":\"{}\"}}", uri));
+    //the comment does not count
+    match server.handle(&request(4, "textDocument/references", uri, 0, 0)).unwrap()[0].get(&["result"]) {
+        Some(&Json::Arr(ref refs)) => assert_eq!(refs.len(), 2),
+        other => panic!("{:?}", other),
+    }
+    match server.handle(&request(5, "textDocument/completion", uri, 0, 0)).unwrap()[0].get(&["result"]) {
+        Some(&Json::Arr(ref items)) => assert_eq!(items.len(), 12 + 7 + 2),
+        other => panic!("{:?}", other),
+    }
+    let unknown = server.handle(&request(6, "workspace/symbol", uri, 0, 0)).unwrap();
+    assert_eq!(unknown[0].get(&["error", "code"]), Some(&Json::Num(-32601.0)));
+}
+
+#[test]
+fn speaking_the_protocol() {
+    use json::Json;
+    use lsp::run;
+
+    let frame = |body:&str| format!("Content-Length: {}\r\n\r\n{}", body.len(), body);
+    let input = frame("{\"jsonrpc\":\"2.0\",\"id\":1,\"method\":\"initialize\",\"params\":{}}")
+        + &frame("{\"jsonrpc\":\"2.0\",\"method\":\"initialized\",\"params\":{}}")
+        + &frame("{\"jsonrpc\":\"2.0\",\"id\":2,\"method\":\"shutdown\"}")
+        + &frame("{\"jsonrpc\":\"2.0\",\"method\":\"exit\"}")
+        + &frame("{\"jsonrpc\":\"2.0\",\"id\":3,\"method\":\"shutdown\"}");
+    let mut out = Vec::new();
+    run(input.as_bytes(), &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    let bodies:Vec<&str> = out.split("Content-Length: ").skip(1).map(|msg| msg.splitn(2, "\r\n\r\n").nth(1).unwrap()).collect();
+    assert_eq!(bodies.len(), 2);
+    let init = Json::parse(bodies[0]).unwrap();
+    assert_eq!(init.get(&["result", "capabilities", "hoverProvider"]), Some(&Json::Bool(true)));
+    assert_eq!(bodies[1], "{\"id\":2,\"jsonrpc\":\"2.0\",\"result\":null}");
+
+    //broken and oversized messages are answered with an error instead of being dropped
+    let input = frame("{\"jsonrpc\":\"2.0\",\"id\":1,") + &frame("{\"jsonrpc\":\"2.0\",\"id\":2,\"method\":\"shutdown\"}")
+        + "Content-Length: 100000000\r\n\r\n{}";
+    let mut out = Vec::new();
+    run(input.as_bytes(), &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    let replies:Vec<Json> = out.split("Content-Length: ").skip(1).map(|msg| Json::parse(msg.splitn(2, "\r\n\r\n").nth(1).unwrap()).unwrap()).collect();
+    assert_eq!(replies.len(), 3);
+    assert_eq!((replies[0].get(&["id"]), replies[0].get(&["error", "code"])), (Some(&Json::Null), Some(&Json::Num(-32700.0))));
+    assert_eq!(replies[1].get(&["id"]), Some(&Json::Num(2.0)));
+    assert_eq!(replies[2].get(&["error", "code"]), Some(&Json::Num(-32600.0)));
+
+    assert_eq!(Json::parse("{\"a\": [1, -2.5e1, \"\\u00e9\\ud83d\\ude00\\n\", true, null]}").unwrap().to_string(),
+               "{\"a\":[1,-25,\"\u{e9}\u{1f600}\\n\",true,null]}");
+}
+
+#[test]
+fn counting_utf16_columns() {
+    use json::Json;
+    use lsp::Server;
+
+    let uri = "file:///tmp/wide.asm";
+    let text = "ab\u{20ac}x\nstart: NOP\ndata: .word '\u{1f600}', start\n";
+    let mut server = Server::new();
+    server.handle(&Json::obj(vec![
+        ("jsonrpc", Json::str("2.0")),
+        ("method", Json::str("textDocument/didOpen")),
+        ("params", Json::obj(vec![("textDocument", Json::obj(vec![("uri", Json::str(uri)), ("text", Json::str(text))]))])),
+    ])).unwrap();
+
+    //the emoji takes two code units, so start begins at 18 instead of 17
+    match server.handle(&request(1, "textDocument/references", uri, 1, 0)).unwrap()[0].get(&["result"]) {
+        Some(&Json::Arr(ref refs)) => {
+            assert_eq!(refs.len(), 2);
+            assert_eq!(refs[1].get(&["range", "start", "character"]), Some(&Json::Num(18.0)));
+            assert_eq!(refs[1].get(&["range", "end", "character"]), Some(&Json::Num(23.0)));
+        },
+        other => panic!("{:?}", other),
+    }
+    let definition = server.handle(&request(2, "textDocument/definition", uri, 2, 19)).unwrap();
+    assert_eq!(definition[0].get(&["result", "range", "start", "line"]), Some(&Json::Num(1.0)));
+}
@@ -0,0 +1,50 @@
+#[test]
+fn paging_on_first_touch() {
+    use memory::{SparseMemory, MemoryError, PAGE_WORDS};
+    use expr::MAX_ADDR;
+
+    let mut memory = SparseMemory::new();
+    assert_eq!(memory.size(), MAX_ADDR + 1);
+    assert_eq!(memory.read(MAX_ADDR), Ok(0));
+    assert_eq!(memory.stats().resident_pages, 0);
+
+    memory.write(MAX_ADDR, 7).unwrap();
+    memory.write(3, 1).unwrap();
+    memory.write(4, 0).unwrap();
+    memory.write(PAGE_WORDS * 5, 0).unwrap();
+    assert_eq!(memory.read(MAX_ADDR), Ok(7));
+    assert_eq!(memory[3], 1);
+    assert_eq!(memory.resident(), vec![0, PAGE_WORDS * 5, (MAX_ADDR / PAGE_WORDS) * PAGE_WORDS]);
+    assert_eq!(memory.stats().resident_bytes, 3 * PAGE_WORDS * 8);
+    assert_eq!(memory.write(MAX_ADDR + 1, 1), Err(MemoryError::OutOfRange(MAX_ADDR + 1)));
+
+    memory.write(3, 0).unwrap();
+    memory.trim();
+    assert_eq!(memory.stats().resident_pages, 1);
+    assert_eq!(memory.stats().peak_pages, 3);
+}
+
+#[test]
+fn limiting_and_filling() {
+    use memory::{SparseMemory, MemoryError, PAGE_WORDS};
+
+    let mut memory = SparseMemory::new();
+    memory.write(PAGE_WORDS * 2, 0).unwrap();
+    memory.set_fill(!0);
+    memory.write(PAGE_WORDS + 10, 5).unwrap();
+    memory.write(PAGE_WORDS * 3, 6).unwrap();
+    assert_eq!(memory.read(PAGE_WORDS + 11), Ok(!0));
+    //pages that were written to before keep the old fill
+    assert_eq!(memory.read(PAGE_WORDS * 2), Ok(0));
+    assert_eq!(memory.read(PAGE_WORDS * 2 + 1), Ok(0));
+    assert_eq!(memory.read(PAGE_WORDS * 4), Ok(!0));
+
+    memory.set_size(PAGE_WORDS + 10);
+    assert_eq!(memory.read(PAGE_WORDS + 10), Err(MemoryError::OutOfRange(PAGE_WORDS + 10)));
+    assert_eq!(memory.resident(), vec![PAGE_WORDS]);
+
+    //growing again must not bring the old words back
+    memory.set_size(PAGE_WORDS * 4);
+    assert_eq!(memory.read(PAGE_WORDS + 10), Ok(!0));
+    assert_eq!(memory.read(PAGE_WORDS * 3), Ok(!0));
+}
@@ -9,6 +9,26 @@ use enum_primitive::FromPrimitive;
 mod parser_test;
 mod cpu_test;
 mod utils_test;
+mod expr_test;
+mod diagnostic_test;
+mod disassembler_test;
+mod linker_test;
+mod listing_test;
+mod image_test;
+mod repl_test;
+mod debuginfo_test;
+mod compiler_test;
+mod optimizer_test;
+mod lint_test;
+mod cfg_test;
+mod lsp_test;
+mod formatter_test;
+mod symbolic_test;
+mod ram_test;
+mod memory_test;
+mod device_test;
+mod cache_test;
+mod coherence_test;
 
 
 pub fn rand_reg() -> (&'static str, Reg, u64) {
@@ -0,0 +1,77 @@
+#[test]
+fn peephole_optimizations() {
+    use optimizer::*;
+    use parser::Parser;
+    use utils::{Instruction, Reg};
+
+    let source = "
+        start:
+            PUSH EAX
+            POP EAX
+            LD EBX, value
+            NOP
+            NOP
+            ADD EAX, EBX
+            JZ next
+        next:
+            ADD ECX, ECX
+            ADD ECX, ECX
+            PUSH EBX
+        back:
+            POP EBX
+            JLZ back
+            CALL start
+        value: .word 5
+        ptr: .word next";
+    let mut parser = Parser::new();
+    parser.assemble(source).unwrap();
+    let mut optimizer = Optimizer::new(parser.words(), parser.labels().clone());
+    assert_eq!(optimizer.run().to_vec(), vec![
+        Optimization::PushPop(0, Reg::EAX),
+        Optimization::Nop(3),
+        Optimization::Nop(4),
+        Optimization::JumpToNext(6, "JZ 7".parse().unwrap()),
+        Optimization::Shift(7, Reg::ECX, 2),
+    ]);
+    let expected:Vec<Instruction> = ["LD EBX 8", "ADD EAX EBX", "ADD ECX ECX", "ADD ECX ECX", "PUSH EBX", "POP EBX", "JLZ 5", "CALL 0"]
+        .iter().map(|s| s.parse().unwrap()).collect();
+    assert_eq!(optimizer.instructions()[..8].to_vec(), expected);
+    assert_eq!(optimizer.image()[&8], 5);
+    assert_eq!(optimizer.image()[&9], 2);
+    assert_eq!((optimizer.labels()["next"], optimizer.labels()["back"], optimizer.labels()["value"]), (2, 5, 8));
+    assert_eq!(optimizer.report()[3].to_string(), "0x000000000006: removed JZ 7, it jumps to the next instruction");
+
+    //NOP clears the flags, so it stays if they are read afterwards or the program may end behind it
+    let code:Vec<Instruction> = ["NOP", "JZ 0", "NOP"].iter().map(|s| s.parse().unwrap()).collect();
+    let mut optimizer = Optimizer::from_instructions(0, code.clone(), ::std::collections::HashMap::new());
+    assert!(optimizer.run().is_empty());
+    assert_eq!(optimizer.instructions(), code);
+}
+
+#[test]
+fn optimized_programs_behave_the_same() {
+    use compiler::compile;
+    use optimizer::Optimizer;
+    use parser::Parser;
+    use super::compiler_test::run_image;
+
+    let asm = compile("
+        int values[4] = {2, 3, 5, 7};
+        int sum(int *p, int n) {
+            int s = 0;
+            while (n > 0) {
+                if (*p > 2) s = s + *p;
+                p = p + 1;
+                n = n - 1;
+            }
+            return s;
+        }
+        int main() { return sum(values, 4); }").unwrap();
+    let mut parser = Parser::new();
+    parser.assemble(&asm).unwrap();
+    let mut optimizer = Optimizer::new(parser.words(), parser.labels().clone());
+    assert!(!optimizer.run().is_empty());
+    assert!(optimizer.image().len() < parser.image().len());
+    assert_eq!(run_image(parser.image().clone(), parser.labels()).EAX, 15);
+    assert_eq!(run_image(optimizer.image(), optimizer.labels()).EAX, 15);
+}
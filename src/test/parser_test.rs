@@ -56,7 +56,7 @@ fn converting_file_to_programm() {
 
     let mut f_handle = File::open("test.asm").unwrap();
     let mut p = Parser::new();
-    p.read_from_file(&mut f_handle);
+    p.read_from_file(&mut f_handle).unwrap();
     println!("{:#?}",p);
     let mut iter = p.into_instructions().zip(inst_vec.into_iter());
    
@@ -66,3 +66,306 @@ fn converting_file_to_programm() {
     }
     remove_file("test.asm");
 }
+
+#[test]
+fn resolving_labels() {
+    use parser::*;
+    use utils::*;
+
+    let source = "start: LD EAX data\n\
+                  loop:\n\
+                  ADD EAX EBX\n\
+                  JZ end\n\
+                  CALL loop\n\
+                  JGZ start\n\
+                  end: NOP\n\
+                  data: NOP\n";
+    let mut p = Parser::new();
+    p.assemble(source).unwrap();
+    assert_eq!(p.labels().get("start"), Some(&0));
+    assert_eq!(p.labels().get("loop"), Some(&1));
+    assert_eq!(p.labels().get("end"), Some(&5));
+
+    let program:Vec<Instruction> = p.into_instructions().collect();
+    assert_eq!(program[0], InstructionBuilder::new().set_opcode(Opcode::Ld).set_reg1(Reg::EAX).set_addr(6).finalize());
+    assert_eq!(program[2], InstructionBuilder::new().set_opcode(Opcode::Jz).set_addr(5).finalize());
+    assert_eq!(program[3], InstructionBuilder::new().set_opcode(Opcode::Call).set_addr(1).finalize());
+    assert_eq!(program[4], InstructionBuilder::new().set_opcode(Opcode::Jgz).set_addr(0).finalize());
+}
+
+#[test]
+fn label_errors() {
+    use parser::*;
+
+    let mut p = Parser::new();
+    assert_eq!(p.assemble("JZ nowhere").unwrap_err()[0].error, ParseError::UndefinedLabel("nowhere".to_string()));
+
+    let mut p = Parser::new();
+    assert_eq!(p.assemble("here: NOP\nhere: NOP").unwrap_err()[0].error, ParseError::DuplicateLabel("here".to_string()));
+}
+
+#[test]
+fn comments_and_relaxed_syntax() {
+    use parser::*;
+    use utils::*;
+
+    let source = "; a program with comments\n\
+                  \n\
+                  # another comment style\n\
+                  start:  ld eax, 5    ; load\n\
+                  \tAdd Eax,Ebx\n\
+                  \n\
+                  jz start # loop\n";
+    let mut p = Parser::new();
+    p.assemble(source).unwrap();
+
+    let program:Vec<Instruction> = p.into_instructions().collect();
+    assert_eq!(program.len(), 3);
+    assert_eq!(program[0], "LD EAX 5".parse().unwrap());
+    assert_eq!(program[1], "ADD EAX EBX".parse().unwrap());
+    assert_eq!(program[2], "JZ 0".parse().unwrap());
+    assert_eq!("   ; nothing here".parse::<Instruction>(), Err(ParseError::EmptyLine));
+}
+
+#[test]
+fn directives_build_memory_image() {
+    use parser::*;
+
+    let source = ".equ SIZE, 3\n\
+                  .org 10\n\
+                  start: LD EAX table\n\
+                  JZ start\n\
+                  .section .data\n\
+                  .org 100\n\
+                  table: .word 7, SIZE, start\n\
+                  .align 4\n\
+                  buf: .zero SIZE\n\
+                  msg: .string \"hi; \\\"x\\\"\"\n";
+    let mut p = Parser::new();
+    p.assemble(source).unwrap();
+    assert_eq!(p.labels().get("table"), Some(&100));
+    assert_eq!(p.labels().get("buf"), Some(&104));
+    assert_eq!(p.labels().get("msg"), Some(&107));
+
+    let image = p.into_image();
+    assert_eq!(image[&10], "LD EAX 100".parse::<::utils::Instruction>().unwrap().0);
+    assert_eq!(image[&11], "JZ 10".parse::<::utils::Instruction>().unwrap().0);
+    assert_eq!((image[&100], image[&101], image[&102]), (7, 3, 10));
+    assert_eq!(image[&103], 0);
+    assert_eq!(image[&106], 0);
+    let msg:String = (107..114).map(|addr| image[&addr] as u8 as char).collect();
+    assert_eq!(msg, "hi; \"x\"");
+    assert_eq!(image[&114], 0);
+    assert_eq!(image.len(), 2 + 15);
+}
+
+#[test]
+fn sections_follow_each_other() {
+    use parser::*;
+
+    let mut p = Parser::new();
+    p.assemble("start: JZ start\nNOP\n.section .data\nvalue: .word 7\n.section .bss\nbuf: .zero 2").unwrap();
+    assert_eq!((p.labels()["value"], p.labels()["buf"]), (2, 3));
+    let image = p.into_image();
+    assert_eq!(image[&2], 7);
+    assert_eq!(image.len(), 5);
+
+    //going back to a section continues it, running into the next one is an error
+    let mut p = Parser::new();
+    let err = p.assemble("NOP\n.section .data\n.word 1\n.section .text\nNOP").unwrap_err();
+    assert_eq!(err[0].error, ParseError::OverlappingData(1));
+}
+
+#[test]
+fn directive_errors() {
+    use parser::*;
+
+    let mut p = Parser::new();
+    assert_eq!(p.assemble(".bogus 1").unwrap_err()[0].error, ParseError::InvalidDirective(".bogus 1".to_string()));
+
+    let mut p = Parser::new();
+    assert_eq!(p.assemble("NOP\n.org 0\nNOP").unwrap_err()[0].error, ParseError::OverlappingData(0));
+
+    let mut p = Parser::new();
+    assert_eq!(p.assemble(".zero 0xfffffffffffff").unwrap_err()[0].error, ParseError::TooMuchData(0xfffffffffffff));
+    let mut p = Parser::new();
+    assert_eq!(p.assemble(".org 0xffffffffffffe\n.zero 3").unwrap_err()[0].error, ParseError::TooMuchData(3));
+    let mut p = Parser::new();
+    assert_eq!(p.assemble("NOP\n.align 0x8000000000000").unwrap_err()[0].error, ParseError::TooMuchData(0x7ffffffffffff));
+}
+
+#[test]
+fn expressions_in_operants() {
+    use parser::*;
+    use utils::*;
+
+    let source = ".equ BUF_SIZE, 0x10\n\
+                  LD EAX, table + 8*3\n\
+                  SAV (buf_end-1) EBX\n\
+                  .org 0x40\n\
+                  table: .zero BUF_SIZE\n\
+                  buf_end: .word -1, 'a'\n";
+    let mut p = Parser::new();
+    p.assemble(source).unwrap();
+    let image = p.into_image();
+    assert_eq!(image[&0], "LD EAX 88".parse::<Instruction>().unwrap().0);
+    assert_eq!(image[&1], "SAV 79 EBX".parse::<Instruction>().unwrap().0);
+    assert_eq!(image[&0x50], 0xff_ff_ff_ff_ff_ff_ff_ff);
+    assert_eq!(image[&0x51], 'a' as u64);
+
+    let mut p = Parser::new();
+    assert_eq!(p.assemble("JZ 0x10000000000000").unwrap_err()[0].error, ParseError::ValueOutOfRange(1 << 52));
+}
+
+#[test]
+fn expanding_macros() {
+    use parser::*;
+
+    let source = ".macro push_all\n\
+                  PUSH EAX\n\
+                  PUSH EBX\n\
+                  .endm\n\
+                  .macro wait_zero reg, target\n\
+                  spin\\@: ADD \\reg \\reg\n\
+                  JZ \\target\n\
+                  JGZ spin\\@\n\
+                  .endm\n\
+                  .macro prologue\n\
+                  push_all\n\
+                  wait_zero ECX, done\n\
+                  .endm\n\
+                  start: prologue\n\
+                  wait_zero EDX, start\n\
+                  done: NOP\n";
+    let mut p = Parser::new();
+    p.assemble(source).unwrap();
+
+    let mut expected = Parser::new();
+    expected.assemble("PUSH EAX\nPUSH EBX\nADD ECX ECX\nJZ 8\nJGZ 2\nADD EDX EDX\nJZ 0\nJGZ 5\nNOP").unwrap();
+    assert_eq!(p.image(), expected.image());
+    assert_eq!(p.labels().get("start"), Some(&0));
+    assert_eq!(p.labels().get("done"), Some(&8));
+}
+
+#[test]
+fn macro_errors_point_at_call_and_definition() {
+    use parser::*;
+
+    let source = ".macro jump_to target\n\
+                  NOP\n\
+                  JZ \\target\n\
+                  .endm\n\
+                  NOP\n\
+                  jump_to nowhere\n";
+    let mut p = Parser::new();
+    assert_eq!(p.assemble(source).unwrap_err()[0].error,
+               ParseError::InMacro("jump_to".to_string(), 6, 3, Box::new(ParseError::UndefinedLabel("nowhere".to_string()))));
+
+    let mut p = Parser::new();
+    match p.assemble(".macro m a\nNOP\n.endm\nm").unwrap_err()[0].error {
+        ParseError::InvalidMacroCall(_) => {},
+        ref res => panic!("expected InvalidMacroCall, got {:?}", res),
+    }
+
+    let mut p = Parser::new();
+    assert!(p.assemble(".macro forever\nforever\n.endm\nforever").is_err());
+}
+
+#[test]
+fn conditional_assembly() {
+    use parser::*;
+
+    let source = ".equ MODEL, 2\n\
+                  .if MODEL == 1\n\
+                  PUSH EAX\n\
+                  .elif MODEL >= 2\n\
+                  PUSH EBX\n\
+                  .ifdef FAST\n\
+                  PUSH ECX\n\
+                  .else\n\
+                  PUSH EDX\n\
+                  .endif\n\
+                  .else\n\
+                  PUSH ESP\n\
+                  .endif\n\
+                  .ifndef MODEL\n\
+                  NOP\n\
+                  .endif\n";
+    let mut p = Parser::new();
+    p.assemble(source).unwrap();
+    let mut expected = Parser::new();
+    expected.assemble("PUSH EBX\nPUSH EDX").unwrap();
+    assert_eq!(p.image(), expected.image());
+
+    let mut p = Parser::new();
+    p.define_constant("FAST", 1).unwrap();
+    p.assemble(source).unwrap();
+    let mut expected = Parser::new();
+    expected.assemble("PUSH EBX\nPUSH ECX").unwrap();
+    assert_eq!(p.image(), expected.image());
+
+    let mut p = Parser::new();
+    assert!(p.assemble(".if 1\nNOP").is_err());
+    let mut p = Parser::new();
+    assert!(p.assemble(".else\nNOP\n.endif").is_err());
+}
+
+#[test]
+fn including_files() {
+    use std::env;
+    use std::fs::{File, create_dir_all, remove_dir_all};
+    use std::io::Write;
+    use parser::*;
+
+    let dir = env::temp_dir().join("compsim_including_files");
+    create_dir_all(dir.join("lib")).unwrap();
+    File::create(dir.join("main.asm")).unwrap().write_all(b".include \"defs.asm\"\nLD EAX, VALUE\n.include \"util.asm\"\n").unwrap();
+    File::create(dir.join("lib").join("defs.asm")).unwrap().write_all(b".equ VALUE, 42\n").unwrap();
+    File::create(dir.join("lib").join("util.asm")).unwrap().write_all(b"util: RET\n").unwrap();
+    File::create(dir.join("a.asm")).unwrap().write_all(b".include \"b.asm\"\n").unwrap();
+    File::create(dir.join("b.asm")).unwrap().write_all(b"NOP\n.include \"a.asm\"\n").unwrap();
+
+    let mut p = Parser::new();
+    assert_eq!(p.assemble_file(dir.join("main.asm")).unwrap_err()[0].error, ParseError::IncludeNotFound("defs.asm".to_string()));
+
+    let mut p = Parser::new();
+    p.add_include_path(dir.join("lib"));
+    p.assemble_file(dir.join("main.asm")).unwrap();
+    assert_eq!(p.labels().get("util"), Some(&1));
+    assert_eq!(p.image()[&0], "LD EAX 42".parse::<::utils::Instruction>().unwrap().0);
+
+    let mut p = Parser::new();
+    match p.assemble_file(dir.join("a.asm")).unwrap_err()[0].error {
+        ParseError::IncludeCycle(_) => {},
+        ref res => panic!("expected IncludeCycle, got {:?}", res),
+    }
+
+    File::create(dir.join("binary.asm")).unwrap().write_all(b"NOP\n\xff\n").unwrap();
+    let mut p = Parser::new();
+    match p.assemble_file(dir.join("binary.asm")).unwrap_err()[0].error {
+        ParseError::Io(ref s) => assert!(s.starts_with(&*dir.join("binary.asm").display().to_string()), "{}", s),
+        ref res => panic!("expected Io, got {:?}", res),
+    }
+    remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn reading_piped_input() {
+    use parser::*;
+
+    let mut p = Parser::new();
+    p.read_from_reader("start: JZ end\nNOP\nend: JZ start\n".as_bytes(), "<stdin>").unwrap();
+    assert_eq!(p.image().len(), 3);
+    assert_eq!(p.labels()["end"], 2);
+
+    let mut p = Parser::new();
+    let diagnostics = p.read_from_reader("NOP\nFOO\n".as_bytes(), "<stdin>").unwrap_err();
+    assert_eq!((&*diagnostics[0].file, diagnostics[0].line), ("<stdin>", 2));
+
+    //the io error is kept instead of being mistaken for a missing file
+    let mut p = Parser::new();
+    match p.read_from_reader(&b"NOP\n\xff\xfe\n"[..], "<stdin>").unwrap_err()[0].error {
+        ParseError::Io(ref s) => assert!(s.contains("UTF-8"), "{}", s),
+        ref res => panic!("expected Io, got {:?}", res),
+    }
+}
@@ -0,0 +1,69 @@
+#[test]
+fn serving_the_memory_bus() {
+    use std::sync::mpsc::channel;
+    use snowflake::ProcessUniqueId;
+    use utils::MemBusOp;
+    use {Ram, RAM_SIZE};
+
+    let (tx, _) = channel();
+    let (_, rx) = channel();
+    let mut ram = Ram::new(tx, rx);
+    let id = ProcessUniqueId::new();
+    assert!(ram.handle(MemBusOp::GiveBlock(id, vec![(5, 42), (RAM_SIZE - 1, 7)])).is_none());
+    match ram.handle(MemBusOp::RequestBlock(id, 4, 2)) {
+        Some(MemBusOp::GiveBlock(got, ref block)) if got == id => assert_eq!(*block, vec![(4, 0), (5, 42)]),
+        res => panic!("expected a block, got {:?}", res),
+    }
+    //reads are cut short at the end of memory
+    match ram.handle(MemBusOp::RequestBlock(id, RAM_SIZE - 1, 8)) {
+        Some(MemBusOp::GiveBlock(_, ref block)) => assert_eq!(*block, vec![(RAM_SIZE - 1, 7)]),
+        res => panic!("expected a block, got {:?}", res),
+    }
+    match ram.handle(MemBusOp::RequestBlock(id, RAM_SIZE, 1)) {
+        Some(MemBusOp::Error(got, _)) if got == id => {},
+        res => panic!("expected an error, got {:?}", res),
+    }
+    //a write that does not fit is not applied at all
+    match ram.handle(MemBusOp::GiveBlock(id, vec![(5, 1), (RAM_SIZE, 1)])) {
+        Some(MemBusOp::Error(got, _)) if got == id => {},
+        res => panic!("expected an error, got {:?}", res),
+    }
+    assert_eq!(ram.memory[5], 42);
+}
+
+#[test]
+fn running_programs_on_the_motherboard() {
+    use image::LoadImage;
+    use parser::Parser;
+    use Motherboard;
+
+    let mut parser = Parser::new();
+    parser.assemble("
+        start:
+            LD EAX, a
+            LD EBX, b
+            ADD EAX, EBX
+            SAV c, EAX
+            LD ECX, c
+        halt:
+            JGZ halt
+        a: .word 40
+        b: .word 2
+        c: .word 0
+        LD EAX, 1000000").unwrap();
+    let labels = parser.labels().clone();
+    let mut board = Motherboard::new();
+    board.load(&LoadImage::from_memory_image(parser.image(), labels["start"])).unwrap();
+    let mut cpu = board.start();
+    cpu.run(8).unwrap();
+    assert_eq!(cpu.core(0).unwrap().ECX, 42);
+    assert_eq!(cpu.core(0).unwrap().ISP, labels["halt"]);
+
+    let mut board = Motherboard::new();
+    board.memory_mut().set_size(1_000_000);
+    board.load(&LoadImage::from_memory_image(parser.image(), labels["c"] + 1)).unwrap();
+    let mut cpu = board.start();
+    let err = cpu.run(1).unwrap_err();
+    assert!(err.contains("read from 0xf4240 outside of memory"), "{}", err);
+    assert!(cpu.core(0).is_none());
+}
@@ -0,0 +1,69 @@
+#[test]
+fn evaluating_lines() {
+    use repl::*;
+    use parser::ParseError;
+
+    let mut repl = Repl::new();
+    repl.eval_line(":set EAX 5").unwrap();
+    repl.eval_line(":set EBX 0x7").unwrap();
+    assert_eq!(repl.eval_line("ADD EAX, EBX").unwrap(), vec![
+        Change{ name:"EAX", old:"0x5".to_string(), new:"0xc".to_string() },
+        Change{ name:"ISP", old:"0x0".to_string(), new:"0x1".to_string() },
+    ]);
+    repl.eval_line("SAV 100 EAX").unwrap();
+    assert_eq!(repl.eval_line("LD ECX, 100").unwrap()[0], Change{ name:"ECX", old:"0x0".to_string(), new:"0xc".to_string() });
+    assert_eq!(repl.read_memory(0, 3).unwrap().iter().map(|w| w.1).collect::<Vec<_>>(),
+               vec!["ADD EAX EBX", "SAV 100 EAX", "LD ECX 100"].iter().map(|s| s.parse::<::utils::Instruction>().unwrap().0).collect::<Vec<_>>());
+    assert_eq!(repl.eval_line("  ; nothing").unwrap(), vec![]);
+    assert_eq!(repl.eval_line("FOO EAX"), Err(ReplError::Parse(ParseError::UnkownInstruction("FOO".to_string()))));
+
+    //dumps stay inside of the address space and are kept short
+    assert_eq!(repl.read_memory(::expr::MAX_ADDR - 1, 8).unwrap().len(), 2);
+    assert_eq!(repl.read_memory(0, !0).unwrap().len(), 0x1000);
+    match repl.read_memory(!0, 1) {
+        Err(ReplError::Memory(_)) => {},
+        res => panic!("expected a memory error, got {:?}", res),
+    }
+}
+
+#[test]
+fn running_a_session() {
+    use repl::*;
+
+    let input = ":set EDX 2\nMUL EDX, EDX\nbogus\n:mem 0\n:quit\nNOP\n";
+    let mut out = Vec::new();
+    Repl::new().run(input.as_bytes(), &mut out).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(),
+               "> EDX: 0x0 -> 0x2\n\
+                > EDX: 0x2 -> 0x4\n\
+                ISP: 0x0 -> 0x1\n\
+                > error: UnkownInstruction: BOGUS\n\
+                > 0x000000000000: 0x2330000000000000  MUL EDX EDX\n\
+                > ");
+}
+
+#[test]
+fn refusing_addresses_outside_of_memory() {
+    use repl::*;
+
+    let memory_error = |repl:&mut Repl, line:&str| match repl.eval_line(line) {
+        Err(ReplError::Memory(_)) => {},
+        res => panic!("expected a memory error for {}, got {:?}", line, res),
+    };
+    let mut repl = Repl::new();
+    repl.eval_line(":set ESP 0").unwrap();
+    memory_error(&mut repl, "PUSH EAX");
+    memory_error(&mut repl, "CALL 5");
+    repl.eval_line(":set ESP 0xffffffffffffffff").unwrap();
+    memory_error(&mut repl, "POP EAX");
+    memory_error(&mut repl, "RET");
+    repl.eval_line(":set ESP 0x100").unwrap();
+    repl.eval_line(":set ISP 0xffffffffffffffff").unwrap();
+    memory_error(&mut repl, "NOP");
+
+    //the core is left alone and keeps working
+    repl.eval_line(":set ISP 0").unwrap();
+    repl.eval_line(":set EBX 9").unwrap();
+    repl.eval_line("PUSH EBX").unwrap();
+    assert_eq!(repl.eval_line("POP EAX").unwrap()[0], Change{ name:"EAX", old:"0x0".to_string(), new:"0x9".to_string() });
+}
@@ -0,0 +1,133 @@
+#[test]
+fn finding_inputs_for_paths() {
+    use parser::Parser;
+    use symbolic::{Executor, Termination};
+    use utils::Reg;
+
+    //x * 3 == 21 has the single solution 7, even with wrapping multiplication
+    let source = "
+        start:
+            LD EBX, three
+            MUL EAX, EBX
+            LD EBX, minus21
+            ADD EAX, EBX
+            JZ found
+            LD ECX, input
+            JLZ broken
+        done:
+            JGZ done
+            JLZ done
+        found:
+            NOP
+            JGZ done
+        broken:
+            .word 0x7f00000000000000
+        three: .word 3
+        minus21: .word -21
+        input: .word 0";
+    let mut parser = Parser::new();
+    parser.assemble(source).unwrap();
+    let labels = parser.labels().clone();
+    let mut executor = Executor::new(parser.image(), labels["start"]);
+    executor.set_symbolic(labels["input"], "input");
+    let paths = executor.run();
+    let ends:Vec<Termination> = paths.iter().map(|path| path.end.clone()).collect();
+    assert_eq!(ends, vec![
+        Termination::Halted(labels["done"]),
+        Termination::InvalidInstruction(labels["broken"]),
+        Termination::Halted(labels["done"]),
+    ]);
+    assert_eq!(paths[0].inputs, vec![("input".to_string(), 0), ("EAX".to_string(), 0)]);
+    assert!(paths[1].end.is_trap());
+    assert_eq!(paths[1].inputs[0].0, "input");
+    assert!((paths[1].inputs[0].1 as i64) < 0 && paths[1].inputs[1].1 != 7);
+    assert_eq!(paths[2].inputs, vec![("EAX".to_string(), 7)]);
+    assert_eq!(paths[2].branches, vec![(labels["start"] + 4, true), (labels["found"] + 1, true), (labels["done"], true)]);
+
+    //a target ends the path, registers can be fixed
+    let mut executor = Executor::new(parser.image(), labels["start"]);
+    executor.set_register(Reg::EAX, 7).add_target(labels["found"]);
+    let paths = executor.run();
+    assert_eq!(paths.len(), 1);
+    assert_eq!(paths[0].end, Termination::Reached(labels["found"]));
+    assert_eq!(paths[0].inputs, vec![]);
+}
+
+#[test]
+fn exploring_compiled_programs() {
+    use compiler;
+    use symbolic::{Executor, Termination};
+
+    //g is overwritten by main, so only the word read through p is an input. Modulo 2^64, x * x == 49
+    //holds for 7 and -7 but also for 2^63 - 7, which is positive, and the comparison takes two paths
+    //when it fails
+    let source = "
+        int g;
+        int *p = 5000;
+        int check(int x) {
+            if (x * x == 49) {
+                if (x < 0) {
+                    return 2;
+                }
+                return 1;
+            }
+            return 0;
+        }
+        int main() {
+            g = *p;
+            return check(g);
+        }";
+    let (image, labels) = compiler::compile_to_image(source).unwrap();
+    let mut executor = Executor::new(&image, labels["__start"]);
+    let paths = executor.run();
+    let mut squares:Vec<i64> = paths.iter().map(|path| {
+        assert_eq!(path.end, Termination::Halted(labels["__halt"]));
+        let x = path.inputs.iter().find(|input| input.0 == "mem[0x1388]").map(|input| input.1).unwrap_or(0);
+        if x.wrapping_mul(x) == 49 { x as i64 } else { 0 }
+    }).collect();
+    squares.sort();
+    assert_eq!(squares.len(), 4);
+    assert!(squares[0] == -7 && squares[1] == 0 && squares[2] == 0 && squares[3] > 0);
+}
+
+#[test]
+fn jumping_like_the_core() {
+    use parser::Parser;
+    use symbolic::{Executor, Termination};
+    use utils::{Instruction, Opcode};
+    use super::cpu_test::core_running;
+
+    //a taken jump leaves ZERO set, so the JZ behind the JGZ is taken as well
+    let source = "
+        start:
+            NOP
+            JGZ next
+            JLZ start
+        next:
+            JZ zero
+        other:
+            JGZ other
+        zero:
+            JGZ zero";
+    let mut parser = Parser::new();
+    parser.assemble(source).unwrap();
+    let labels = parser.labels().clone();
+    let paths = Executor::new(parser.image(), labels["start"]).run();
+    assert_eq!(paths.len(), 1);
+    assert_eq!(paths[0].end, Termination::Halted(labels["zero"]));
+
+    let mut core = core_running(source);
+    core.ISP = labels["start"];
+    let mut branches = Vec::new();
+    while branches.len() < paths[0].branches.len() {
+        let addr = core.ISP;
+        let instr = Instruction(parser.image()[&addr]);
+        core.exec_instr();
+        match instr.opcode() {
+            Opcode::Jz | Opcode::Jgz | Opcode::Jlz => branches.push((addr, core.ISP != addr + 1)),
+            _ => {},
+        }
+    }
+    assert_eq!(branches, paths[0].branches);
+    assert_eq!(core.ISP, labels["zero"]);
+}
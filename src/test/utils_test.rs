@@ -10,3 +10,18 @@ fn nth_byte() {
     }
     
 }
+
+#[test]
+fn splitting_operants_with_escaped_quotes() {
+    use utils::{split_operants, strip_comment};
+    use parser::Parser;
+
+    assert_eq!(split_operants(r"'\'', 5"), vec![r"'\''", "5"]);
+    assert_eq!(split_operants(r"'\'' EAX"), vec![r"'\''", "EAX"]);
+    assert_eq!(split_operants(r#""a\", b", 1"#), vec![r#""a\", b""#, "1"]);
+    assert_eq!(strip_comment(r".word '\'', 5 ; quote"), r".word '\'', 5 ");
+
+    let mut p = Parser::new();
+    p.assemble(".word '\\'', 5").unwrap();
+    assert_eq!(p.into_image().values().cloned().collect::<Vec<_>>(), vec!['\'' as u64, 5]);
+}
@@ -3,8 +3,10 @@ use std::str::FromStr;
 use snowflake::ProcessUniqueId;
 use num::FromPrimitive;
 use std::fmt;
+use std::collections::HashMap;
     
 use parser::ParseError;
+use expr;
 
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub struct Instruction (pub u64);
@@ -15,7 +17,23 @@ impl Instruction {
     }
 
     pub fn opcode(&self) -> Opcode {
-        Opcode::from_u64(self.0 >> 60).expect("Unknown Instruction")
+        self.try_opcode().expect("Unknown Instruction")
+    }
+
+    //the conditional jumps share the opcode nibble 7 and are told apart by the next nibble
+    pub fn try_opcode(&self) -> Option<Opcode> {
+        match self.0 >> 60 {
+            0x7 => match get_nth_byte(self.0, 0) & 0x0f {
+                0x1 => Some(Opcode::Jz),
+                0x2 => Some(Opcode::Jgz),
+                0x3 => Some(Opcode::Jlz),
+                _   => None,
+            },
+            n => Opcode::from_u64(n).and_then(|op| match op {
+                Opcode::Jgz | Opcode::Jlz => None,
+                op => Some(op),
+            }),
+        }
     }
 
     pub fn reg1(&self) -> Reg {
@@ -29,11 +47,62 @@ impl Instruction {
     pub fn addr(&self) -> u64 {
         self.0 & 0x00_0f_ff_ff_ff_ff_ff_ffu64
     } 
+
+    //true if the word is exactly what the assembler emits for some instruction, so that
+    //disassembling and reassembling it gives back the same word
+    pub fn is_valid(&self) -> bool {
+        let opcode = match self.try_opcode() {
+            Some(op) => op,
+            None => return false,
+        };
+        let reg1 = Reg::from_u8(get_nth_byte(self.0, 0) & 0x0f);
+        let reg2 = Reg::from_u8(get_nth_byte(self.0, 1) >> 4);
+        let mut builder = InstructionBuilder::new();
+        builder.set_opcode(opcode);
+        match opcode {
+            Opcode::Add | Opcode::Mul => match (reg1, reg2) {
+                (Some(r1), Some(r2)) => { builder.set_reg1(r1).set_reg2(r2); },
+                _ => return false,
+            },
+            Opcode::Ld | Opcode::Sav => match reg1 {
+                Some(r1) => { builder.set_reg1(r1).set_addr(self.addr()); },
+                None => return false,
+            },
+            Opcode::Push | Opcode::Pop => match reg1 {
+                Some(r1) => { builder.set_reg1(r1); },
+                None => return false,
+            },
+            Opcode::Jz | Opcode::Jgz | Opcode::Jlz | Opcode::Call => { builder.set_addr(self.addr()); },
+            Opcode::Nop | Opcode::Ret => {},
+        }
+        builder.finalize() == *self
+    }
+
+    //renders the instruction in the syntax FromStr accepts, address operants go through render_addr
+    pub fn render(&self, render_addr:&Fn(u64) -> String) -> String {
+        if !self.is_valid() {
+            return format!(".word {:#x}", self.0);
+        }
+        match self.opcode() {
+            Opcode::Nop => "NOP".to_string(),
+            Opcode::Ret => "RET".to_string(),
+            Opcode::Add => format!("ADD {} {}", self.reg1(), self.reg2()),
+            Opcode::Mul => format!("MUL {} {}", self.reg1(), self.reg2()),
+            Opcode::Ld  => format!("LD {} {}", self.reg1(), render_addr(self.addr())),
+            Opcode::Sav => format!("SAV {} {}", render_addr(self.addr()), self.reg1()),
+            Opcode::Push=> format!("PUSH {}", self.reg1()),
+            Opcode::Pop => format!("POP {}", self.reg1()),
+            Opcode::Jz  => format!("JZ {}", render_addr(self.addr())),
+            Opcode::Jgz => format!("JGZ {}", render_addr(self.addr())),
+            Opcode::Jlz => format!("JLZ {}", render_addr(self.addr())),
+            Opcode::Call=> format!("CALL {}", render_addr(self.addr())),
+        }
+    }
 }
 
 impl fmt::Display for Instruction {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:?}",  self.opcode())
+        write!(f, "{}", self.render(&|addr| addr.to_string()))
     }
 }
 
@@ -41,91 +110,123 @@ impl FromStr for Instruction {
     type Err = ParseError;
 
     fn from_str(_s: &str) -> Result<Self, Self::Err> {
-        let mut iter = _s.split_whitespace();
-
-        let operation = iter.next().expect("Read empty line");
-        if let Some(operant1) = iter.next() {
-            if let Some(operant2) = iter.next() {
-                match operation {
-                    "ADD"   => {
-                        Ok(InstructionBuilder::new()
-                            .set_opcode(Opcode::Add)
-                            .set_reg1(operant1.parse().unwrap())
-                            .set_reg2(operant2.parse().unwrap())
-                            .finalize())
-                    },
-
-                    "MUL"   => {
-                        Ok(InstructionBuilder::new()
-                            .set_opcode(Opcode::Mul)
-                            .set_reg1(operant1.parse().unwrap())
-                            .set_reg2(operant2.parse().unwrap())
-                            .finalize())
- 
-                    },
-
-                    "LD"    => {
-                        Ok(InstructionBuilder::new()
-                            .set_opcode(Opcode::Ld)
-                            .set_reg1(operant1.parse().unwrap())
-                            .set_addr(operant2.parse().unwrap())
-                            .finalize())
-
-                    },
-
-                    "SAV"   => {
-                        Ok(InstructionBuilder::new()
-                            .set_opcode(Opcode::Sav)
-                            .set_addr(operant1.parse().unwrap())
-                            .set_reg1(operant2.parse().unwrap())
-                            .finalize())
-
-                    },
-
-                    s      => Err(ParseError::UnkownInstruction(s.to_string())),
-                }
-            } else {
-                match operation {
-                    "PUSH"  => {
-                        Ok(InstructionBuilder::new()
-                            .set_opcode(Opcode::Push)
-                            .set_reg1(operant1.parse().unwrap())
-                            .finalize())
-
-                    },
-                    "POP"   => {
-                        Ok(InstructionBuilder::new()
-                            .set_opcode(Opcode::Pop)
-                            .set_reg1(operant1.parse().unwrap())
-                            .finalize())
-                    },
-                    "JZ"    => {
-                        Ok(InstructionBuilder::new()
-                            .set_opcode(Opcode::Jz)
-                            .set_addr(operant1.parse().unwrap())
-                            .finalize())
-                    },
-                    "JGZ"   => { 
-                        Ok(InstructionBuilder::new()
-                            .set_opcode(Opcode::Jgz)
-                            .set_addr(operant1.parse().unwrap())
-                            .finalize())
-                    },
-                    "JLZ"   => { 
-                        Ok(InstructionBuilder::new()
-                            .set_opcode(Opcode::Jlz)
-                            .set_addr(operant1.parse().unwrap())
-                            .finalize())
-                    },
-                    s       => Err(ParseError::UnkownInstruction(s.to_string())),
-                }
+        parse_instruction(_s, &|operant: &str| expr::eval_addr(operant, &HashMap::new()))
+    }
+}
+
+//parses a single line of assembly; address operants are handed to resolve_addr so that 
+//the assembler can substitute labels
+pub fn parse_instruction(_s: &str, resolve_addr: &Fn(&str) -> Result<u64, ParseError>) -> Result<Instruction, ParseError> {
+    let line = strip_comment(_s).trim();
+    let (operation, operants) = match line.find(char::is_whitespace) {
+        Some(pos) => (line[..pos].to_uppercase(), split_operants(&line[pos..])),
+        None if !line.is_empty() => (line.to_uppercase(), Vec::new()),
+        None => return Err(ParseError::EmptyLine),
+    };
+    if let Some(n) = operant_count(&operation) {
+        if n != operants.len() {
+            return Err(ParseError::InvalidOperants(operation, n));
+        }
+    }
+    let mut iter = operants.into_iter();
+
+    if let Some(operant1) = iter.next() {
+        if let Some(operant2) = iter.next() {
+            match &*operation {
+                "ADD"   => {
+                    Ok(InstructionBuilder::new()
+                        .set_opcode(Opcode::Add)
+                        .set_reg1(try!(operant1.parse()))
+                        .set_reg2(try!(operant2.parse()))
+                        .finalize())
+                },
+
+                "MUL"   => {
+                    Ok(InstructionBuilder::new()
+                        .set_opcode(Opcode::Mul)
+                        .set_reg1(try!(operant1.parse()))
+                        .set_reg2(try!(operant2.parse()))
+                        .finalize())
+
+                },
+
+                "LD"    => {
+                    Ok(InstructionBuilder::new()
+                        .set_opcode(Opcode::Ld)
+                        .set_reg1(try!(operant1.parse()))
+                        .set_addr(try!(resolve_addr(operant2)))
+                        .finalize())
+
+                },
+
+                "SAV"   => {
+                    Ok(InstructionBuilder::new()
+                        .set_opcode(Opcode::Sav)
+                        .set_addr(try!(resolve_addr(operant1)))
+                        .set_reg1(try!(operant2.parse()))
+                        .finalize())
+
+                },
+
+                s      => Err(ParseError::UnkownInstruction(s.to_string())),
             }
         } else {
-            match operation {
-                "NOP" => Ok(Instruction(0)),
+            match &*operation {
+                "PUSH"  => {
+                    Ok(InstructionBuilder::new()
+                        .set_opcode(Opcode::Push)
+                        .set_reg1(try!(operant1.parse()))
+                        .finalize())
+
+                },
+                "POP"   => {
+                    Ok(InstructionBuilder::new()
+                        .set_opcode(Opcode::Pop)
+                        .set_reg1(try!(operant1.parse()))
+                        .finalize())
+                },
+                "JZ"    => {
+                    Ok(InstructionBuilder::new()
+                        .set_opcode(Opcode::Jz)
+                        .set_addr(try!(resolve_addr(operant1)))
+                        .finalize())
+                },
+                "JGZ"   => { 
+                    Ok(InstructionBuilder::new()
+                        .set_opcode(Opcode::Jgz)
+                        .set_addr(try!(resolve_addr(operant1)))
+                        .finalize())
+                },
+                "JLZ"   => { 
+                    Ok(InstructionBuilder::new()
+                        .set_opcode(Opcode::Jlz)
+                        .set_addr(try!(resolve_addr(operant1)))
+                        .finalize())
+                },
+                "CALL"  => {
+                    Ok(InstructionBuilder::new()
+                        .set_opcode(Opcode::Call)
+                        .set_addr(try!(resolve_addr(operant1)))
+                        .finalize())
+                },
                 s       => Err(ParseError::UnkownInstruction(s.to_string())),
             }
         }
+    } else {
+        match &*operation {
+            "NOP" => Ok(Instruction(0)),
+            "RET" => Ok(InstructionBuilder::new().set_opcode(Opcode::Ret).finalize()),
+            s       => Err(ParseError::UnkownInstruction(s.to_string())),
+        }
+    }
+}
+
+pub fn operant_count(mnemonic:&str) -> Option<usize> {
+    match mnemonic {
+        "NOP" | "RET"                                   => Some(0),
+        "PUSH" | "POP" | "JZ" | "JGZ" | "JLZ" | "CALL"  => Some(1),
+        "ADD" | "MUL" | "LD" | "SAV"                    => Some(2),
+        _                                               => None,
     }
 }
 
@@ -149,6 +250,8 @@ impl InstructionBuilder {
             Opcode::Jz  => 0x71_00_00_00_00_00_00_00u64,
             Opcode::Jgz => 0x72_00_00_00_00_00_00_00u64,
             Opcode::Jlz => 0x73_00_00_00_00_00_00_00u64,
+            Opcode::Call=> 0xa0_00_00_00_00_00_00_00u64,
+            Opcode::Ret => 0xb0_00_00_00_00_00_00_00u64,
         };
         self
     }
@@ -201,6 +304,8 @@ pub enum Opcode {
     Jz  = 0x7,
     Jgz = 0x8,
     Jlz = 0x9,
+    Call= 0xa,
+    Ret = 0xb,
 }
 }
 
@@ -232,7 +337,7 @@ impl FromStr for Reg {
     type Err = ParseError;
 
     fn from_str(_s: &str) -> Result<Self, Self::Err> {
-        match _s {
+        match &*_s.to_uppercase() {
             "EAX" => Ok(Reg::EAX),
             "EBX" => Ok(Reg::EBX),
             "ECX" => Ok(Reg::ECX),
@@ -240,7 +345,7 @@ impl FromStr for Reg {
             "ESP" => Ok(Reg::ESP),
             "EBP" => Ok(Reg::EBP),
             "ISP" => Ok(Reg::ISP),
-            s       => panic!("Unrecognized register token: {}", s),
+            s       => Err(ParseError::UnkownReg(s.to_string())),
         }
     }
 
@@ -254,13 +359,71 @@ pub enum CPUBusOp {
     WakeUp,
     ExecAt(u64),
     Error(String),
+    Tick, //a core executed an instruction, nothing is answered
 }
 
 #[derive(Debug)]
 pub enum MemBusOp {
     RequestBlock(ProcessUniqueId, u64, usize),
     GiveBlock(ProcessUniqueId, Vec<(u64, u64)>),
-    Error(String)
+    Error(ProcessUniqueId, String), //for the core whose request failed
+    Tick, //the clock of the devices, one for every instruction a core executed
+}
+
+//cuts off everything after a ';' or '#' that is not part of a string or character literal
+pub fn strip_comment(line:&str) -> &str {
+    let mut quote = None;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match (quote, c) {
+            (Some(_), _) if escaped         => escaped = false,
+            (Some(_), '\\')                 => escaped = true,
+            (Some(q), c) if c == q          => quote = None,
+            (Some(_), _)                    => {},
+            (None, '"') | (None, '\'')      => quote = Some(c),
+            (None, ';') | (None, '#')       => return &line[..i],
+            _                               => {},
+        }
+    }
+    line
+}
+
+//splits the operants at commas, or at whitespace if there are none, so that expressions 
+//may contain spaces as long as the operants are separated by commas
+pub fn split_operants(_s:&str) -> Vec<&str> {
+    let by_comma = split_top_level(_s, &|c| c == ',');
+    if by_comma.len() > 1 {
+        by_comma
+    } else {
+        split_top_level(_s, &|c:char| c.is_whitespace())
+    }
+}
+
+//splits at every separator that is neither quoted nor inside parentheses
+fn split_top_level<'a>(_s:&'a str, is_sep:&Fn(char) -> bool) -> Vec<&'a str> {
+    let mut res = Vec::new();
+    let mut depth = 0;
+    let mut quote = None;
+    let mut escaped = false;
+    let mut start = 0;
+    for (i, c) in _s.char_indices() {
+        match (quote, c) {
+            (Some(_), _) if escaped     => escaped = false,
+            (Some(_), '\\')             => escaped = true,
+            (Some(q), c) if c == q      => quote = None,
+            (Some(_), _)                => {},
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (None, '(')                 => depth += 1,
+            (None, ')')                 => depth -= 1,
+            (None, c) if depth == 0 && is_sep(c) => {
+                res.push(_s[start..i].trim());
+                start = i + c.len_utf8();
+            },
+            _                           => {},
+        }
+    }
+    res.push(_s[start..].trim());
+    res.into_iter().filter(|op| !op.is_empty()).collect()
 }
 
 pub fn get_nth_byte(num:u64, nth:usize) -> u8 {